@@ -0,0 +1,15 @@
+use miner::{cli, node_client::NetworkNodeClient, send_tx::send_transaction};
+
+fn main() {
+    let args = cli::parse_send_tx_args();
+    let node_url = args.node_url.clone();
+    let node_client = NetworkNodeClient::new(node_url);
+
+    match send_transaction(&args, &node_client) {
+        Ok(transaction) => println!("Transaction sent: {}", transaction.id()),
+        Err(error) => {
+            println!("Transaction rejected: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
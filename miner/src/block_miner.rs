@@ -1,19 +1,184 @@
-use spec::{types::Block, validators::validate_pow};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use spec::{
+    types::{Block, PowAlgorithm},
+    validators::validate_pow,
+};
 
 use crate::cli::MinerArgs;
 
-pub fn mine_block(args: &MinerArgs, block_template: &Block) -> Option<Block> {
+// Number of nonces tried between two checks of the deadline and the cancellation
+const INTERRUPT_CHECK_INTERVAL: u64 = 1024;
+
+// Lets other threads stop a mining search, e.g. on Ctrl-C
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Mine the block until a valid nonce is found, the extranonce rolls are exhausted,
+// the deadline is reached or the search is cancelled
+pub fn mine_block(
+    args: &MinerArgs,
+    pow_algorithm: PowAlgorithm,
+    block_template: &Block,
+    deadline: Option<Instant>,
+    cancellation: &CancellationToken,
+) -> Option<Block> {
     let mut block_canditate = block_template.clone();
+    let mut extranonce: u64 = 0;
+    let is_interrupted = || {
+        cancellation.is_cancelled() || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    };
+
+    loop {
+        // mining is just trying different nonces until the block hash has enough starting zeroes
+        for nonce in 0..args.max_nonce {
+            // the nonce range may take long to scan, so the search is interrupted in the middle of it
+            if nonce % INTERRUPT_CHECK_INTERVAL == 0 && is_interrupted() {
+                return None;
+            }
+
+            block_canditate.nonce = nonce;
+            block_canditate.hash = block_canditate.calculate_hash();
 
-    // mining is just trying different nonces until the block hash has enough starting zeroes
-    for nonce in 0..args.max_nonce {
-        block_canditate.nonce = nonce;
-        block_canditate.hash = block_canditate.calculate_hash();
+            if validate_pow(pow_algorithm, args.difficulty, &block_canditate).is_ok() {
+                return Some(block_canditate);
+            }
+        }
 
-        if validate_pow(args.difficulty, &block_canditate).is_ok() {
-            return Some(block_canditate);
+        // at high difficulties the nonce range may not be enough,
+        // so the coinbase is changed to try the same nonces over a different block
+        extranonce += 1;
+        let is_out_of_rolls =
+            args.max_extranonce_rolls != 0 && extranonce > args.max_extranonce_rolls;
+        if is_out_of_rolls || is_interrupted() {
+            return None;
         }
+
+        roll_extranonce(&mut block_canditate, extranonce)?;
     }
+}
+
+// The extranonce is stored in the data of the coinbase transaction, which is free for the miner to use
+fn roll_extranonce(block: &mut Block, extranonce: u64) -> Option<()> {
+    let coinbase = block.transactions.first_mut()?;
+    coinbase.data = extranonce.to_le_bytes().to_vec();
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use spec::types::{hash::ConsensusHash, Address, Transaction};
 
-    None
+    use super::*;
+
+    #[test]
+    fn should_roll_the_extranonce_when_nonces_are_exhausted() {
+        // a single nonce is not enough to meet the difficulty
+        let args = create_mock_args(4, 1, 0);
+        let mut template = create_mock_template();
+        while validate_pow(PowAlgorithm::Sha3, args.difficulty, &template).is_ok() {
+            template.timestamp += 1;
+            template.hash = template.calculate_hash();
+        }
+
+        let block = mine_block(
+            &args,
+            PowAlgorithm::Sha3,
+            &template,
+            None,
+            &CancellationToken::default(),
+        )
+        .unwrap();
+
+        validate_pow(PowAlgorithm::Sha3, args.difficulty, &block).unwrap();
+        assert_ne!(block.transactions[0].data, template.transactions[0].data);
+    }
+
+    #[test]
+    fn should_give_up_after_the_max_extranonce_rolls() {
+        let args = create_mock_args(256, 1, 3);
+        let template = create_mock_template();
+
+        assert_eq!(
+            mine_block(
+                &args,
+                PowAlgorithm::Sha3,
+                &template,
+                None,
+                &CancellationToken::default()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn should_give_up_after_the_deadline() {
+        let args = create_mock_args(256, 1, 0);
+        let template = create_mock_template();
+
+        let block = mine_block(
+            &args,
+            PowAlgorithm::Sha3,
+            &template,
+            Some(Instant::now()),
+            &CancellationToken::default(),
+        );
+
+        assert_eq!(block, None);
+    }
+
+    #[test]
+    fn should_give_up_promptly_when_cancelled() {
+        let args = create_mock_args(256, u64::MAX, 0);
+        let template = create_mock_template();
+        let cancellation = CancellationToken::default();
+
+        let handle = thread::spawn({
+            let cancellation = cancellation.clone();
+            move || {
+                thread::sleep(Duration::from_millis(50));
+                cancellation.cancel();
+            }
+        });
+
+        // the search stops in the middle of the nonce range, which would take forever to scan
+        let start = Instant::now();
+        let block = mine_block(&args, PowAlgorithm::Sha3, &template, None, &cancellation);
+        assert_eq!(block, None);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        handle.join().unwrap();
+    }
+
+    fn create_mock_template() -> Block {
+        let coinbase = Transaction::new_coinbase(Address::default());
+        Block::new(0, 0, ConsensusHash::default(), vec![coinbase])
+    }
+
+    fn create_mock_args(difficulty: u32, max_nonce: u64, max_extranonce_rolls: u64) -> MinerArgs {
+        MinerArgs {
+            difficulty,
+            max_nonce,
+            max_extranonce_rolls,
+            ..MinerArgs::with_defaults(&Address::default())
+        }
+    }
 }
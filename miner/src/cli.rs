@@ -1,13 +1,17 @@
-use clap::Parser;
-use spec::types::Address;
+use clap::{ArgEnum, Parser};
+use spec::types::{Address, Coin};
 
 #[derive(Parser, Debug, Clone)]
 #[clap(author, version, about, long_about = None)]
 pub struct MinerArgs {
     /// Account address that will receive the mining rewards
-    #[clap(short = 'a', long, value_parser)]
+    #[clap(short = 'a', long, value_parser = parse_address)]
     pub miner_address: Address,
 
+    /// Other addresses that take turns with the miner address to receive the rewards, one mined block each
+    #[clap(long, value_parser = parse_address, multiple = true)]
+    pub payout_addresses: Vec<Address>,
+
     /// Network address of the node
     #[clap(
         short = 'n',
@@ -17,19 +21,149 @@ pub struct MinerArgs {
     )]
     pub node_url: String,
 
+    /// Number of times the node is contacted at startup before giving up, in case it is not up yet
+    #[clap(long, value_parser, default_value = "10")]
+    pub node_connect_attempts: u32,
+
+    /// Milliseconds to wait after the first failed attempt to contact the node, doubled after each one
+    #[clap(long, value_parser, default_value = "500")]
+    pub node_connect_backoff_ms: u64,
+
     /// Minimum number of starting zeroes needed in a block hash for a proof-of-work valid block
+    /// The miner refuses to start when it does not match the node difficulty, unless forced
     #[clap(short = 'd', long, value_parser, default_value = "10")]
     pub difficulty: u32,
 
+    /// Mine with the configured difficulty even if it does not match the node one (e.g. for testing)
+    #[clap(long, value_parser)]
+    pub force_difficulty: bool,
+
     /// Maximum number of blocks to mine (0 for unlimited)
     #[clap(long, value_parser, default_value = "0")]
     pub max_blocks: u64,
 
+    /// Maximum number of seconds to keep mining (0 for unlimited)
+    #[clap(long, value_parser, default_value = "0")]
+    pub max_runtime_secs: u64,
+
+    /// Maximum number of milliseconds to spend mining a single template (0 for unlimited)
+    #[clap(long, value_parser, default_value = "0")]
+    pub mining_attempt_timeout_ms: u64,
+
     /// Maximum nonce that will be used when mining a block
     #[clap(long, value_parser, default_value = "1000000")]
     pub max_nonce: u64,
+
+    /// Maximum number of times the coinbase extranonce is changed to try all the nonces again (0 for unlimited)
+    #[clap(long, value_parser, default_value = "0")]
+    pub max_extranonce_rolls: u64,
+
+    /// Number of consecutive blocks that can fail to be mined before applying the failure policy
+    #[clap(long, value_parser, default_value = "3")]
+    pub max_mining_failures: u32,
+
+    /// What to do after too many consecutive blocks failed to be mined
+    #[clap(long, arg_enum, value_parser, default_value = "backoff")]
+    pub on_mining_failures: MiningFailurePolicy,
+
+    /// Seconds to wait before mining again, with the backoff failure policy
+    #[clap(long, value_parser, default_value = "10")]
+    pub mining_failure_backoff_secs: u64,
+
+    /// Only check that the node would accept the mined blocks, without mining
+    #[clap(long, value_parser)]
+    pub dry_run: bool,
+
+    /// Mine and submit exactly one block, even without pending transactions, then exit
+    #[clap(long, value_parser)]
+    pub once: bool,
+}
+
+impl MinerArgs {
+    /// Arguments with the default value of every option, mining for the indicated address
+    pub fn with_defaults(miner_address: &Address) -> Self {
+        MinerArgs::try_parse_from(["miner", "-a", &miner_address.to_string()])
+            .expect("The default arguments are valid")
+    }
+}
+
+/// Blocks fail to be mined when no nonce meets the difficulty after all the extranonce rolls
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MiningFailurePolicy {
+    /// Keep mining right away (each attempt uses the current time as timestamp, so other hashes are tried)
+    Retry,
+    /// Wait before mining again
+    Backoff,
+    /// Stop the miner
+    Exit,
+}
+
+/// Addresses are checked when the arguments are parsed, as rewards sent to a mistyped address are lost
+fn parse_address(value: &str) -> Result<Address, String> {
+    value
+        .parse()
+        .map_err(|error| format!("{error}, an address must be 64 hexadecimal characters"))
 }
 
 pub fn parse_args() -> MinerArgs {
     MinerArgs::parse()
 }
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about = "Broadcast a transaction to a node", long_about = None)]
+pub struct SendTxArgs {
+    /// Account address that sends the funds
+    #[clap(short = 'f', long, value_parser = parse_address)]
+    pub from: Address,
+
+    /// Account address that receives the funds
+    #[clap(short = 't', long, value_parser = parse_address)]
+    pub to: Address,
+
+    /// Amount of coins to transfer
+    #[clap(long, value_parser)]
+    pub amount: Coin,
+
+    /// Network address of the node
+    #[clap(
+        short = 'n',
+        long,
+        value_parser,
+        default_value = "http://localhost:8000"
+    )]
+    pub node_url: String,
+}
+
+pub fn parse_send_tx_args() -> SendTxArgs {
+    SendTxArgs::parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "f780b958227ff0bf5795ede8f9f7eaac67e7e06666b043a400026cbd421ce28e";
+
+    #[test]
+    fn should_parse_a_valid_miner_address() {
+        let args = MinerArgs::try_parse_from(["miner", "-a", ADDRESS]).unwrap();
+        assert_eq!(args.miner_address, ADDRESS.parse().unwrap());
+    }
+
+    #[test]
+    fn should_reject_a_malformed_miner_address() {
+        let malformed_addresses = [
+            // not hexadecimal
+            ADDRESS.replace('f', "g"),
+            // too short
+            ADDRESS[2..].to_string(),
+        ];
+        for address in malformed_addresses {
+            let error = MinerArgs::try_parse_from(["miner", "-a", &address]).unwrap_err();
+            let message = error.to_string();
+            assert!(message.contains("--miner-address"), "{message}");
+            assert!(message.contains(&address), "{message}");
+            assert!(message.contains("64 hexadecimal characters"), "{message}");
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use spec::{
+    types::Block,
+    validators::{validate_block_hash, validate_coinbase, BLOCK_SUBSIDY},
+};
+use thiserror::Error;
+
+use crate::{
+    cli::MinerArgs,
+    mining_loop::{check_difficulty, MiningError},
+    node_client::{BlockTemplateError, NodeClient},
+};
+
+#[derive(Error, PartialEq, Eq, Debug)]
+pub enum DryRunError {
+    #[error("Mismatched difficulty: the miner uses {miner} but the node requires {node}, use --force-difficulty to mine anyway")]
+    MismatchedDifficulty { miner: u32, node: u32 },
+
+    #[error("Invalid block: {0}")]
+    InvalidBlock(String),
+
+    #[error("Could not reach the node: {0}")]
+    NodeUnreachable(String),
+
+    #[error("The node is in read-only mode, so it would reject the mined blocks")]
+    ReadOnlyNode,
+}
+
+// Check that the node would accept the blocks mined with the current configuration, without actually mining
+pub fn run_dry_run(args: &MinerArgs, node_client: &impl NodeClient) -> Result<Block, DryRunError> {
+    node_client
+        .get_network()
+        .map_err(DryRunError::NodeUnreachable)?;
+
+    // the difficulty is checked exactly as the mining loop does, against the one required for the next block
+    check_difficulty(args, node_client).map_err(|error| match error {
+        MiningError::MismatchedDifficulty { miner, node } => {
+            DryRunError::MismatchedDifficulty { miner, node }
+        }
+        error => DryRunError::InvalidBlock(error.to_string()),
+    })?;
+
+    // get the template exactly as the mining loop does
+    let block = node_client
+        .get_block_template(&args.miner_address)
+        .map_err(|error| match error {
+            BlockTemplateError::ReadOnlyNode => DryRunError::ReadOnlyNode,
+            BlockTemplateError::Unavailable(message) => DryRunError::NodeUnreachable(message),
+        })?;
+
+    // the coinbase must reward this miner, otherwise the mined blocks would be useless
+    let rewards_miner = block
+        .transactions
+        .first()
+        .is_some_and(|coinbase| coinbase.recipient == args.miner_address);
+    if !rewards_miner {
+        return Err(DryRunError::InvalidBlock(
+            "Coinbase does not reward the miner address".to_string(),
+        ));
+    }
+
+    // the subsidy only drops below the constant one near the maximum supply, which the node checks
+    validate_coinbase(block.transactions.first(), BLOCK_SUBSIDY)
+        .and_then(|_| validate_block_hash(&block))
+        .map_err(|error| DryRunError::InvalidBlock(error.to_string()))?;
+
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use spec::types::Address;
+
+    use crate::node_client::mock::MockNodeClient;
+
+    use super::*;
+
+    #[test]
+    fn should_accept_matching_configuration() {
+        let node_client = MockNodeClient::new(10);
+        let args = create_mock_args(10);
+
+        let block = run_dry_run(&args, &node_client).unwrap();
+
+        // the block includes the coinbase for the miner
+        assert_eq!(block.transactions.len(), 1);
+        assert_eq!(block.transactions[0].recipient, args.miner_address);
+    }
+
+    #[test]
+    fn should_report_mismatched_difficulty() {
+        let node_client = MockNodeClient::new(20);
+        let mut args = create_mock_args(10);
+
+        let err = run_dry_run(&args, &node_client).unwrap_err();
+
+        assert_eq!(
+            err,
+            DryRunError::MismatchedDifficulty {
+                miner: 10,
+                node: 20
+            }
+        );
+
+        // unless the difficulty is forced
+        args.force_difficulty = true;
+        run_dry_run(&args, &node_client).unwrap();
+    }
+
+    #[test]
+    fn should_check_the_difficulty_of_a_retargeted_chain() {
+        // the chain retargeted away from the network difficulty
+        let node_client = MockNodeClient::new(10);
+        node_client.required_difficulty.set(20);
+
+        // so the network difficulty is no longer the one required
+        let err = run_dry_run(&create_mock_args(10), &node_client).unwrap_err();
+        assert_eq!(
+            err,
+            DryRunError::MismatchedDifficulty {
+                miner: 10,
+                node: 20
+            }
+        );
+
+        // but the current one is
+        run_dry_run(&create_mock_args(20), &node_client).unwrap();
+    }
+
+    #[test]
+    fn should_report_a_read_only_node() {
+        let node_client = MockNodeClient::new(10);
+        node_client.read_only.set(true);
+
+        let err = run_dry_run(&create_mock_args(10), &node_client).unwrap_err();
+
+        assert_eq!(err, DryRunError::ReadOnlyNode);
+    }
+
+    fn create_mock_args(difficulty: u32) -> MinerArgs {
+        MinerArgs {
+            difficulty,
+            dry_run: true,
+            ..MinerArgs::with_defaults(&Address::default())
+        }
+    }
+}
@@ -1,4 +1,6 @@
 pub mod block_miner;
 pub mod cli;
+pub mod dry_run;
 pub mod mining_loop;
 pub mod node_client;
+pub mod send_tx;
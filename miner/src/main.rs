@@ -1,9 +1,55 @@
-use miner::{cli, mining_loop::run_mining_loop, node_client::NetworkNodeClient};
+use miner::{
+    block_miner::CancellationToken,
+    cli,
+    dry_run::run_dry_run,
+    mining_loop::{mine_one_block, run_mining_loop},
+    node_client::NetworkNodeClient,
+};
 
 fn main() {
     let args = cli::parse_args();
     let node_url = args.node_url.clone();
     let node_client = NetworkNodeClient::new(node_url);
+    let cancellation = CancellationToken::default();
+    set_ctrlc_handler(cancellation.clone());
 
-    run_mining_loop(args, node_client);
+    if args.dry_run {
+        match run_dry_run(&args, &node_client) {
+            Ok(block) => println!("Dry run succeeded, block {} would be accepted", block.index),
+            Err(error) => {
+                println!("Dry run failed: {}", error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.once {
+        match mine_one_block(&args, &node_client, &cancellation) {
+            Ok(block) => println!("Block {} mined and accepted", block.index),
+            Err(error) => {
+                println!("Mining failed: {}", error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Err(error) = run_mining_loop(args, &node_client, &cancellation) {
+        println!("Mining stopped: {}", error);
+        std::process::exit(1);
+    }
+}
+
+// The first Ctrl-C (or termination) stops mining after the current nonce range, so a found block
+// is not lost in the middle of its submission, while a second one exits right away
+fn set_ctrlc_handler(cancellation: CancellationToken) {
+    ctrlc::set_handler(move || {
+        if cancellation.is_cancelled() {
+            std::process::exit(130);
+        }
+        println!("Stopping the miner, press Ctrl-C again to exit right away");
+        cancellation.cancel();
+    })
+    .expect("Error setting Ctrl-C handler");
 }
@@ -1,55 +1,551 @@
-use std::{thread, time};
-use spec::{
-    types::{Address, Transaction},
-    validators::BLOCK_SUBSIDY,
+use std::{
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use crate::{block_miner::mine_block, cli::MinerArgs, node_client::NodeClient};
+use spec::types::{Address, Block, Network};
+use thiserror::Error;
 
-pub fn run_mining_loop(args: MinerArgs, node_client: impl NodeClient) {
+use crate::{
+    block_miner::{mine_block, CancellationToken},
+    cli::{MinerArgs, MiningFailurePolicy},
+    node_client::{BlockTemplateError, NodeClient, SubmitBlockError},
+};
+
+#[derive(Error, PartialEq, Eq, Debug)]
+pub enum MiningError {
+    #[error("Failed to mine {0} blocks in a row, try with a higher max nonce")]
+    TooManyFailures(u32),
+
+    #[error("Could not reach the node after {0} attempts")]
+    NodeUnreachable(u32),
+
+    #[error("No valid nonce was found for the block")]
+    NonceNotFound,
+
+    #[error("Mining was cancelled")]
+    Cancelled,
+
+    #[error("{0}")]
+    BlockRejected(SubmitBlockError),
+
+    #[error("Mismatched difficulty: the miner uses {miner} but the node requires {node}, use --force-difficulty to mine anyway")]
+    MismatchedDifficulty { miner: u32, node: u32 },
+
+    #[error("The node is in read-only mode, the miner needs a node that accepts blocks")]
+    ReadOnlyNode,
+
+    #[error("{0}")]
+    TemplateUnavailable(String),
+}
+
+impl From<BlockTemplateError> for MiningError {
+    fn from(error: BlockTemplateError) -> Self {
+        match error {
+            BlockTemplateError::ReadOnlyNode => MiningError::ReadOnlyNode,
+            BlockTemplateError::Unavailable(_) => {
+                MiningError::TemplateUnavailable(error.to_string())
+            }
+        }
+    }
+}
+
+// Longest wait between two attempts to contact the node at startup
+const MAX_NODE_CONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+// Mine blocks until the limits of the arguments are reached, or the mining is cancelled
+pub fn run_mining_loop(
+    args: MinerArgs,
+    node_client: &impl NodeClient,
+    cancellation: &CancellationToken,
+) -> Result<(), MiningError> {
     let mut blocks_mined: u64 = 0;
+    let mut consecutive_failures: u32 = 0;
+    let start_time = Instant::now();
+    let deadline = match args.max_runtime_secs {
+        0 => None,
+        secs => Some(start_time + Duration::from_secs(secs)),
+    };
+
+    // blocks must be mined with the proof of work algorithm of the network
+    let network = wait_for_node(&args, node_client)?;
+    check_difficulty(&args, node_client)?;
+    let pow_algorithm = network.pow_algorithm;
 
-    while should_keep_mining(blocks_mined, &args) {
-        // The block template already includes the correct index, previous_hash and transactions for the next valid block
-        let mut block_template = node_client.get_block_template();
+    while should_keep_mining(blocks_mined, start_time.elapsed(), &args)
+        && !cancellation.is_cancelled()
+    {
+        // The block template already includes the correct index, previous_hash, coinbase and transactions for the next valid block
+        let coinbase_recipient = get_coinbase_recipient(&args, blocks_mined);
+        let mut block_template = node_client.get_block_template(coinbase_recipient)?;
 
-        //if there are no transaction - dont mine new block
-        if block_template.transactions.is_empty(){
-            thread::sleep(time::Duration::from_secs(10));
+        // If there are no transactions besides the coinbase - don't mine a new block
+        // The genesis block is the exception, as it's needed to bootstrap the chain,
+        // and so are the warmup blocks, which the node keeps coinbase-only
+        let is_coinbase_only = block_template.index > 0 && block_template.transactions.len() <= 1;
+        let is_warmup = block_template.index < network.warmup_blocks;
+        if is_coinbase_only && !is_warmup {
+            thread::sleep(Duration::from_secs(10));
             continue;
         }
 
-        // Add the coinbase transaction as the first transaction in the block
-        let coinbase = create_coinbase_transaction(args.miner_address.clone());
-        block_template.transactions.insert(0, coinbase);
-        block_template.hash = block_template.calculate_hash();
-
         // Try to mine the new block
-        let mining_result = mine_block(&args, &block_template);
+        refresh_timestamp(&mut block_template);
+        let attempt_deadline = get_attempt_deadline(&args, deadline);
+        let mining_result = mine_block(
+            &args,
+            pow_algorithm,
+            &block_template,
+            attempt_deadline,
+            cancellation,
+        );
         match mining_result {
-            Some(new_block) => {
-                println!("Block mined");
-                node_client.submit_block(&new_block);
-                blocks_mined += 1;
+            Some(new_block) => match node_client.submit_block(&new_block) {
+                Ok(()) => {
+                    println!("Block mined");
+                    blocks_mined += 1;
+                    consecutive_failures = 0;
+                }
+                // another block extended the tip while mining, which is not a failure of the miner
+                Err(SubmitBlockError::Stale(_)) => {
+                    println!("Block is stale, mining on a new template");
+                    continue;
+                }
+                Err(error) => {
+                    println!("{}", error);
+                    consecutive_failures += 1;
+                }
+            },
+            None if cancellation.is_cancelled() => break,
+            // the template got old without finding a nonce, which is up to luck rather than a failure
+            None if is_past(attempt_deadline) => {
+                println!("Mining attempt timed out, mining on a new template");
+                continue;
             }
             None => {
                 println!("Error mining block");
+                consecutive_failures += 1;
+            }
+        }
+
+        // Without a limit, a miner that cannot find valid nonces would keep spinning
+        if consecutive_failures >= args.max_mining_failures {
+            match args.on_mining_failures {
+                MiningFailurePolicy::Retry => {}
+                MiningFailurePolicy::Backoff => {
+                    println!("Too many mining failures, waiting before mining again");
+                    thread::sleep(Duration::from_secs(args.mining_failure_backoff_secs));
+                }
+                MiningFailurePolicy::Exit => {
+                    return Err(MiningError::TooManyFailures(consecutive_failures))
+                }
+            }
+            consecutive_failures = 0;
+        }
+    }
+
+    Ok(())
+}
+
+// Mine a single block on the current template and submit it, whether it has pending transactions or not,
+// which is the minimal step for scripted tests and to bootstrap a chain
+pub fn mine_one_block(
+    args: &MinerArgs,
+    node_client: &impl NodeClient,
+    cancellation: &CancellationToken,
+) -> Result<Block, MiningError> {
+    let network = wait_for_node(args, node_client)?;
+    check_difficulty(args, node_client)?;
+    let pow_algorithm = network.pow_algorithm;
+
+    loop {
+        let mut block_template = node_client.get_block_template(&args.miner_address)?;
+        refresh_timestamp(&mut block_template);
+        let attempt_deadline = get_attempt_deadline(args, None);
+        let new_block = mine_block(
+            args,
+            pow_algorithm,
+            &block_template,
+            attempt_deadline,
+            cancellation,
+        );
+        let new_block = match new_block {
+            Some(value) => value,
+            None if cancellation.is_cancelled() => return Err(MiningError::Cancelled),
+            None if is_past(attempt_deadline) => {
+                println!("Mining attempt timed out, mining on a new template");
+                continue;
             }
+            None => return Err(MiningError::NonceNotFound),
+        };
+
+        match node_client.submit_block(&new_block) {
+            Ok(()) => return Ok(new_block),
+            Err(SubmitBlockError::Stale(_)) => println!("Block is stale, mining on a new template"),
+            Err(error) => return Err(MiningError::BlockRejected(error)),
+        }
+    }
+}
+
+// The miner may be started before the node, so the network is requested until the node responds
+fn wait_for_node(args: &MinerArgs, node_client: &impl NodeClient) -> Result<Network, MiningError> {
+    let mut backoff = Duration::from_millis(args.node_connect_backoff_ms);
+    for attempt in 1..=args.node_connect_attempts {
+        match node_client.get_network() {
+            Ok(network) => return Ok(network),
+            Err(error) => println!("Waiting for node at {}: {}", args.node_url, error),
+        }
+
+        if attempt < args.node_connect_attempts {
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_NODE_CONNECT_BACKOFF);
+        }
+    }
+
+    Err(MiningError::NodeUnreachable(args.node_connect_attempts))
+}
+
+// Blocks mined with a different difficulty than the one the node requires would all be rejected,
+// so the miner only starts with a mismatching difficulty when explicitly forced to
+// The node difficulty is the one of the next block, as the chain may have retargeted since the genesis
+pub(crate) fn check_difficulty(
+    args: &MinerArgs,
+    node_client: &impl NodeClient,
+) -> Result<(), MiningError> {
+    let node_difficulty = match node_client.get_required_difficulty() {
+        Ok(difficulty) => difficulty,
+        Err(error) => {
+            println!("Could not get the difficulty of the node: {}", error);
+            return Ok(());
         }
+    };
+    if args.difficulty == node_difficulty {
+        return Ok(());
+    }
+
+    println!(
+        "WARNING: the miner difficulty {} does not match the node difficulty {}",
+        args.difficulty, node_difficulty
+    );
+    if !args.force_difficulty {
+        return Err(MiningError::MismatchedDifficulty {
+            miner: args.difficulty,
+            node: node_difficulty,
+        });
+    }
+
+    Ok(())
+}
+
+// The rewards of each mined block go to the next address of the rotation, starting with the miner address
+fn get_coinbase_recipient(args: &MinerArgs, blocks_mined: u64) -> &Address {
+    let num_addresses = args.payout_addresses.len() as u64 + 1;
+    match (blocks_mined % num_addresses) as usize {
+        0 => &args.miner_address,
+        position => &args.payout_addresses[position - 1],
     }
 }
 
-pub fn create_coinbase_transaction(miner_address: Address) -> Transaction {
-    Transaction {
-        sender: Address::default(),
-        recipient: miner_address,
-        amount: BLOCK_SUBSIDY,
+// The node keeps the timestamp of the template until the tip changes, so the current time is used instead,
+// both for an accurate block time and for each attempt to try other hashes
+fn refresh_timestamp(block_template: &mut Block) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as i64);
+    if now > block_template.timestamp {
+        block_template.timestamp = now;
+        block_template.hash = block_template.calculate_hash();
+    }
+}
+
+// A single template is mined until the attempt timeout, without going beyond the runtime deadline
+fn get_attempt_deadline(args: &MinerArgs, deadline: Option<Instant>) -> Option<Instant> {
+    let attempt_deadline = match args.mining_attempt_timeout_ms {
+        0 => None,
+        timeout_ms => Some(Instant::now() + Duration::from_millis(timeout_ms)),
+    };
+
+    match (attempt_deadline, deadline) {
+        (Some(attempt_deadline), Some(deadline)) => Some(attempt_deadline.min(deadline)),
+        _ => attempt_deadline.or(deadline),
     }
 }
 
-fn should_keep_mining(blocks_mined: u64, args: &MinerArgs) -> bool {
+fn is_past(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+fn should_keep_mining(blocks_mined: u64, elapsed: Duration, args: &MinerArgs) -> bool {
+    if args.max_runtime_secs != 0 && elapsed >= Duration::from_secs(args.max_runtime_secs) {
+        return false;
+    }
+
     if args.max_blocks == 0 {
         return true;
     }
     blocks_mined < args.max_blocks
 }
+
+#[cfg(test)]
+mod tests {
+    use spec::types::hash::ConsensusHash;
+
+    use crate::node_client::mock::MockNodeClient;
+
+    use super::*;
+
+    #[test]
+    fn should_stop_after_max_runtime() {
+        let args = create_mock_args(0, 60);
+
+        assert!(should_keep_mining(100, Duration::from_secs(59), &args));
+        assert!(!should_keep_mining(100, Duration::from_secs(60), &args));
+    }
+
+    #[test]
+    fn should_stop_after_max_blocks_before_max_runtime() {
+        let args = create_mock_args(2, 60);
+
+        assert!(should_keep_mining(1, Duration::ZERO, &args));
+        assert!(!should_keep_mining(2, Duration::ZERO, &args));
+    }
+
+    #[test]
+    fn should_keep_mining_forever_without_limits() {
+        let args = create_mock_args(0, 0);
+
+        assert!(should_keep_mining(u64::MAX, Duration::MAX, &args));
+    }
+
+    #[test]
+    fn should_limit_each_attempt_to_the_attempt_timeout() {
+        let mut args = create_mock_args(0, 0);
+        let deadline = Instant::now() + Duration::from_secs(60);
+        assert_eq!(get_attempt_deadline(&args, None), None);
+        assert_eq!(get_attempt_deadline(&args, Some(deadline)), Some(deadline));
+
+        args.mining_attempt_timeout_ms = 1000;
+        let attempt_deadline = get_attempt_deadline(&args, Some(deadline)).unwrap();
+        assert!(attempt_deadline < deadline);
+        assert!(attempt_deadline <= Instant::now() + Duration::from_secs(1));
+
+        // the attempt never goes beyond the runtime deadline
+        let close_deadline = Instant::now();
+        assert_eq!(
+            get_attempt_deadline(&args, Some(close_deadline)),
+            Some(close_deadline)
+        );
+    }
+
+    #[test]
+    fn should_rotate_the_coinbase_recipients() {
+        let node_client = MockNodeClient::new(0);
+        let addresses: Vec<Address> = (1..=3).map(|byte| ConsensusHash::digest(&[byte])).collect();
+        let mut args = create_mock_args(5, 0);
+        args.miner_address = addresses[0].clone();
+        args.payout_addresses = addresses[1..].to_vec();
+
+        run_mining_loop(args, &node_client, &CancellationToken::default()).unwrap();
+
+        // each address receives the rewards of a block in turn
+        let recipients: Vec<Address> = node_client
+            .submitted_blocks
+            .borrow()
+            .iter()
+            .map(|block| block.transactions[0].recipient.clone())
+            .collect();
+        let expected_recipients = [0, 1, 2, 0, 1].map(|i| addresses[i].clone());
+        assert_eq!(recipients, expected_recipients);
+    }
+
+    #[test]
+    fn should_mine_on_a_new_template_after_a_stale_block() {
+        let node_client = MockNodeClient::new(0);
+        node_client.stale_submissions.set(2);
+        // a single failure would stop the miner
+        let mut args = create_mock_args(1, 0);
+        args.max_mining_failures = 1;
+        args.on_mining_failures = MiningFailurePolicy::Exit;
+
+        run_mining_loop(args, &node_client, &CancellationToken::default()).unwrap();
+
+        // a new template is requested after each stale block, until one is accepted
+        assert_eq!(node_client.template_requests.get(), 3);
+        assert_eq!(node_client.submitted_blocks.borrow().len(), 1);
+    }
+
+    #[test]
+    fn should_mine_the_coinbase_only_warmup_blocks() {
+        let mut node_client = MockNodeClient::new(0);
+        node_client.network.warmup_blocks = 3;
+        node_client.indexed_templates.set(true);
+        let args = create_mock_args(3, 0);
+
+        // the blocks after the genesis hold no transactions, but they are mined anyway
+        run_mining_loop(args, &node_client, &CancellationToken::default()).unwrap();
+
+        let submitted_blocks = node_client.submitted_blocks.borrow();
+        let indexes: Vec<u64> = submitted_blocks.iter().map(|block| block.index).collect();
+        assert_eq!(indexes, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn should_mine_one_block_without_pending_transactions() {
+        let node_client = MockNodeClient::new(0);
+        node_client.stale_submissions.set(1);
+        let args = create_mock_args(0, 0);
+
+        let block = mine_one_block(&args, &node_client, &CancellationToken::default()).unwrap();
+
+        // the stale block is mined again on a new template, and then the miner stops
+        assert_eq!(node_client.template_requests.get(), 2);
+        assert_eq!(*node_client.submitted_blocks.borrow(), vec![block]);
+    }
+
+    #[test]
+    fn should_exit_after_too_many_failures() {
+        let node_client = MockNodeClient::new(0);
+        let args = create_failing_args(MiningFailurePolicy::Exit);
+
+        let err = run_mining_loop(args, &node_client, &CancellationToken::default()).unwrap_err();
+
+        assert_eq!(err, MiningError::TooManyFailures(3));
+        assert_eq!(node_client.template_requests.get(), 3);
+    }
+
+    #[test]
+    fn should_not_count_the_timed_out_attempts_as_failures() {
+        let node_client = MockNodeClient::new(0);
+        let mut args = create_failing_args(MiningFailurePolicy::Exit);
+        args.max_nonce = u64::MAX;
+        args.mining_attempt_timeout_ms = 50;
+        args.max_runtime_secs = 1;
+
+        // no nonce is ever found, but each attempt times out before exhausting the nonces
+        run_mining_loop(args, &node_client, &CancellationToken::default()).unwrap();
+
+        assert!(node_client.template_requests.get() > 3);
+        assert!(node_client.submitted_blocks.borrow().is_empty());
+    }
+
+    #[test]
+    fn should_back_off_after_too_many_failures() {
+        let node_client = MockNodeClient::new(0);
+        let mut args = create_failing_args(MiningFailurePolicy::Backoff);
+        args.max_runtime_secs = 1;
+        args.mining_failure_backoff_secs = 1;
+
+        run_mining_loop(args, &node_client, &CancellationToken::default()).unwrap();
+
+        // the miner waited out the whole runtime after the failures, instead of spinning
+        assert_eq!(node_client.template_requests.get(), 3);
+    }
+
+    #[test]
+    fn should_retry_a_refused_connection_until_the_node_responds() {
+        let node_client = MockNodeClient::new(0);
+        node_client.refused_connections.set(2);
+        let mut args = create_mock_args(0, 0);
+        args.node_connect_backoff_ms = 1;
+
+        let network = wait_for_node(&args, &node_client).unwrap();
+
+        assert_eq!(network.description, node_client.network.description);
+        assert_eq!(node_client.network_requests.get(), 3);
+    }
+
+    #[test]
+    fn should_give_up_on_an_unreachable_node() {
+        let node_client = MockNodeClient::new(0);
+        node_client.refused_connections.set(u32::MAX);
+        let mut args = create_mock_args(1, 0);
+        args.node_connect_backoff_ms = 1;
+
+        let err =
+            run_mining_loop(args.clone(), &node_client, &CancellationToken::default()).unwrap_err();
+
+        assert_eq!(
+            err,
+            MiningError::NodeUnreachable(args.node_connect_attempts)
+        );
+        assert_eq!(
+            node_client.network_requests.get(),
+            args.node_connect_attempts
+        );
+        assert_eq!(node_client.template_requests.get(), 0);
+    }
+
+    #[test]
+    fn should_refuse_to_start_with_a_mismatching_difficulty() {
+        let node_client = MockNodeClient::new(20);
+        let mut args = create_mock_args(1, 0);
+        args.difficulty = 0;
+
+        let err =
+            run_mining_loop(args.clone(), &node_client, &CancellationToken::default()).unwrap_err();
+
+        assert_eq!(
+            err,
+            MiningError::MismatchedDifficulty { miner: 0, node: 20 }
+        );
+        assert_eq!(node_client.template_requests.get(), 0);
+
+        // unless the difficulty is forced, where the blocks are mined with the miner one
+        args.force_difficulty = true;
+        run_mining_loop(args, &node_client, &CancellationToken::default()).unwrap();
+        assert_eq!(node_client.submitted_blocks.borrow().len(), 1);
+    }
+
+    #[test]
+    fn should_check_the_difficulty_of_a_retargeted_chain() {
+        // the chain retargeted from the network difficulty
+        let node_client = MockNodeClient::new(0);
+        node_client.required_difficulty.set(2);
+        let mut args = create_mock_args(1, 0);
+        args.difficulty = 0;
+
+        let err =
+            run_mining_loop(args.clone(), &node_client, &CancellationToken::default()).unwrap_err();
+        assert_eq!(err, MiningError::MismatchedDifficulty { miner: 0, node: 2 });
+
+        // so the miner has to use the current difficulty instead
+        args.difficulty = 2;
+        run_mining_loop(args, &node_client, &CancellationToken::default()).unwrap();
+        assert_eq!(node_client.submitted_blocks.borrow().len(), 1);
+    }
+
+    #[test]
+    fn should_stop_mining_on_a_read_only_node() {
+        let node_client = MockNodeClient::new(0);
+        node_client.read_only.set(true);
+        let args = create_mock_args(1, 0);
+
+        let err =
+            run_mining_loop(args.clone(), &node_client, &CancellationToken::default()).unwrap_err();
+        assert_eq!(err, MiningError::ReadOnlyNode);
+
+        let err = mine_one_block(&args, &node_client, &CancellationToken::default()).unwrap_err();
+        assert_eq!(err, MiningError::ReadOnlyNode);
+        assert!(node_client.submitted_blocks.borrow().is_empty());
+    }
+
+    // Mining always fails, as no nonce can meet the difficulty
+    fn create_failing_args(on_mining_failures: MiningFailurePolicy) -> MinerArgs {
+        let mut args = create_mock_args(0, 0);
+        args.difficulty = 256;
+        args.force_difficulty = true;
+        args.max_nonce = 1;
+        args.max_extranonce_rolls = 1;
+        args.on_mining_failures = on_mining_failures;
+        args
+    }
+
+    fn create_mock_args(max_blocks: u64, max_runtime_secs: u64) -> MinerArgs {
+        MinerArgs {
+            difficulty: 0,
+            max_blocks,
+            max_runtime_secs,
+            ..MinerArgs::with_defaults(&Address::default())
+        }
+    }
+}
@@ -1,9 +1,36 @@
 use isahc::{ReadResponseExt, Request};
-use spec::types::Block;
+use serde_json::Value;
+use spec::types::{Address, Block, Network, Transaction};
+use thiserror::Error;
+
+#[derive(Error, PartialEq, Eq, Debug)]
+pub enum SubmitBlockError {
+    #[error("Block is stale, the tip of the node moved: {0}")]
+    Stale(String),
+
+    #[error("Block was rejected: {0}")]
+    Rejected(String),
+
+    #[error("Could not reach the node to submit the block: {0}")]
+    NodeUnreachable(String),
+}
+
+#[derive(Error, PartialEq, Eq, Debug)]
+pub enum BlockTemplateError {
+    #[error("The node is in read-only mode, so it does not serve block templates")]
+    ReadOnlyNode,
+
+    #[error("Could not get a block template: {0}")]
+    Unavailable(String),
+}
 
 pub trait NodeClient {
-    fn get_block_template(&self) -> Block;
-    fn submit_block(&self, block: &Block);
+    fn get_network(&self) -> Result<Network, String>;
+    // Difficulty required for the next block, which differs from the network one once the chain retargets
+    fn get_required_difficulty(&self) -> Result<u32, String>;
+    fn get_block_template(&self, miner_address: &Address) -> Result<Block, BlockTemplateError>;
+    fn submit_block(&self, block: &Block) -> Result<(), SubmitBlockError>;
+    fn submit_transaction(&self, transaction: &Transaction) -> Result<(), String>;
 }
 
 pub struct NetworkNodeClient {
@@ -17,19 +44,63 @@ impl NetworkNodeClient {
 }
 
 impl NodeClient for NetworkNodeClient {
-    fn get_block_template(&self) -> Block {
-        let uri = format!("{}/block_template", self.node_url);
-        let mut response = isahc::get(uri).unwrap();
+    // It is the first request of the miner, so failures are returned to wait for the node to start
+    fn get_network(&self) -> Result<Network, String> {
+        let uri = format!("{}/network", self.node_url);
+        let mut response = isahc::get(uri).map_err(|error| error.to_string())?;
 
         // check that the response is sucessful
-        assert_eq!(response.status().as_u16(), 200);
+        if response.status().as_u16() != 200 {
+            return Err(format!("Unexpected status {}", response.status()));
+        }
+
+        // parse and return the network definition
+        let raw_body = response.text().map_err(|error| error.to_string())?;
+        serde_json::from_str(&raw_body).map_err(|error| error.to_string())
+    }
+
+    fn get_required_difficulty(&self) -> Result<u32, String> {
+        let uri = format!("{}/stats", self.node_url);
+        let mut response = isahc::get(uri).map_err(|error| error.to_string())?;
+
+        // check that the response is sucessful
+        if response.status().as_u16() != 200 {
+            return Err(format!("Unexpected status {}", response.status()));
+        }
+
+        // only the difficulty of the chain stats is needed
+        let raw_body = response.text().map_err(|error| error.to_string())?;
+        let stats: Value = serde_json::from_str(&raw_body).map_err(|error| error.to_string())?;
+        stats["difficulty"]
+            .as_u64()
+            .and_then(|difficulty| u32::try_from(difficulty).ok())
+            .ok_or_else(|| "Missing difficulty in the chain stats".to_string())
+    }
+
+    fn get_block_template(&self, miner_address: &Address) -> Result<Block, BlockTemplateError> {
+        let uri = format!("{}/block_template?address={}", self.node_url, miner_address);
+        let mut response =
+            isahc::get(uri).map_err(|error| BlockTemplateError::Unavailable(error.to_string()))?;
+
+        // read-only nodes refuse to serve templates, as they would reject the mined blocks anyway
+        match response.status().as_u16() {
+            200 => {}
+            403 => return Err(BlockTemplateError::ReadOnlyNode),
+            status => {
+                let message = format!("Unexpected status {}", status);
+                return Err(BlockTemplateError::Unavailable(message));
+            }
+        }
 
         // parse and return block template
-        let raw_body = response.text().unwrap();
-        serde_json::from_str(&raw_body).unwrap()
+        let raw_body = response
+            .text()
+            .map_err(|error| BlockTemplateError::Unavailable(error.to_string()))?;
+        serde_json::from_str(&raw_body)
+            .map_err(|error| BlockTemplateError::Unavailable(error.to_string()))
     }
 
-    fn submit_block(&self, block: &Block) {
+    fn submit_block(&self, block: &Block) -> Result<(), SubmitBlockError> {
         let uri = format!("{}/blocks", self.node_url);
         let body = serde_json::to_string(block).unwrap();
 
@@ -38,6 +109,150 @@ impl NodeClient for NetworkNodeClient {
             .body(body)
             .unwrap();
 
-        isahc::send(request).unwrap();
+        let mut response = isahc::send(request)
+            .map_err(|error| SubmitBlockError::NodeUnreachable(error.to_string()))?;
+        if response.status().is_success() {
+            return Ok(());
+        }
+
+        // the node explains in a structured error why the block was rejected
+        let raw_body = response.text().unwrap_or_default();
+        parse_block_rejection(&raw_body)
+    }
+
+    fn submit_transaction(&self, transaction: &Transaction) -> Result<(), String> {
+        let uri = format!("{}/transactions", self.node_url);
+        let body = serde_json::to_string(transaction).unwrap();
+
+        let request = Request::post(uri)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .unwrap();
+
+        let mut response = isahc::send(request).map_err(|error| error.to_string())?;
+
+        // the node explains in the body why the transaction was rejected
+        if !response.status().is_success() {
+            return Err(response.text().unwrap_or_default());
+        }
+
+        Ok(())
+    }
+}
+
+// A block that the node already has (e.g. relayed by a peer first) is part of the chain all the same
+fn parse_block_rejection(raw_body: &str) -> Result<(), SubmitBlockError> {
+    let api_error: Value = serde_json::from_str(raw_body).unwrap_or_default();
+    let message = api_error["message"]
+        .as_str()
+        .unwrap_or(raw_body)
+        .to_string();
+
+    match api_error["code"].as_str() {
+        Some("duplicate_block") => Ok(()),
+        Some("stale_block") => Err(SubmitBlockError::Stale(message)),
+        _ => Err(SubmitBlockError::Rejected(message)),
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use std::cell::{Cell, RefCell};
+
+    use spec::types::hash::ConsensusHash;
+
+    use super::*;
+
+    // Node client that records the submissions instead of sending them to a node
+    pub struct MockNodeClient {
+        pub network: Network,
+        pub submitted_blocks: RefCell<Vec<Block>>,
+        pub submitted_transactions: RefCell<Vec<Transaction>>,
+        pub template_requests: Cell<u32>,
+        // the first network requests fail, as if the node was not started yet
+        pub refused_connections: Cell<u32>,
+        pub network_requests: Cell<u32>,
+        // the first submitted blocks are rejected, as if another miner had extended the tip
+        pub stale_submissions: Cell<u32>,
+        // templates follow the submitted blocks, instead of always being for the genesis block
+        pub indexed_templates: Cell<bool>,
+        // difficulty of the next block, as if the chain had retargeted away from the network one
+        pub required_difficulty: Cell<u32>,
+        // templates are refused, as by a node in read-only mode
+        pub read_only: Cell<bool>,
+    }
+
+    impl MockNodeClient {
+        pub fn new(difficulty: u32) -> Self {
+            let network = Network {
+                difficulty,
+                ..Network::test_default()
+            };
+
+            Self {
+                network,
+                submitted_blocks: RefCell::new(vec![]),
+                submitted_transactions: RefCell::new(vec![]),
+                template_requests: Cell::new(0),
+                refused_connections: Cell::new(0),
+                network_requests: Cell::new(0),
+                stale_submissions: Cell::new(0),
+                indexed_templates: Cell::new(false),
+                required_difficulty: Cell::new(difficulty),
+                read_only: Cell::new(false),
+            }
+        }
+    }
+
+    impl NodeClient for MockNodeClient {
+        fn get_network(&self) -> Result<Network, String> {
+            self.network_requests.set(self.network_requests.get() + 1);
+            if self.network_requests.get() <= self.refused_connections.get() {
+                return Err("Connection refused".to_string());
+            }
+
+            Ok(self.network.clone())
+        }
+
+        fn get_required_difficulty(&self) -> Result<u32, String> {
+            Ok(self.required_difficulty.get())
+        }
+
+        fn get_block_template(&self, miner_address: &Address) -> Result<Block, BlockTemplateError> {
+            self.template_requests.set(self.template_requests.get() + 1);
+            if self.read_only.get() {
+                return Err(BlockTemplateError::ReadOnlyNode);
+            }
+
+            let coinbase = Transaction::new_coinbase(miner_address.clone());
+            let index = if self.indexed_templates.get() {
+                self.submitted_blocks.borrow().len() as u64
+            } else {
+                0
+            };
+            Ok(Block::new(
+                index,
+                0,
+                ConsensusHash::default(),
+                vec![coinbase],
+            ))
+        }
+
+        fn submit_block(&self, block: &Block) -> Result<(), SubmitBlockError> {
+            if self.stale_submissions.get() > 0 {
+                self.stale_submissions.set(self.stale_submissions.get() - 1);
+                return Err(SubmitBlockError::Stale("Invalid index".to_string()));
+            }
+
+            self.submitted_blocks.borrow_mut().push(block.clone());
+            Ok(())
+        }
+
+        fn submit_transaction(&self, transaction: &Transaction) -> Result<(), String> {
+            self.submitted_transactions
+                .borrow_mut()
+                .push(transaction.clone());
+            Ok(())
+        }
     }
 }
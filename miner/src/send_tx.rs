@@ -0,0 +1,53 @@
+use spec::types::Transaction;
+
+use crate::{cli::SendTxArgs, node_client::NodeClient};
+
+// Build a transaction from the arguments and submit it to the node
+pub fn send_transaction(
+    args: &SendTxArgs,
+    node_client: &impl NodeClient,
+) -> Result<Transaction, String> {
+    let transaction = Transaction::new(args.from.clone(), args.to.clone(), args.amount);
+    node_client.submit_transaction(&transaction)?;
+
+    Ok(transaction)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use spec::types::Address;
+
+    use crate::node_client::mock::MockNodeClient;
+
+    use super::*;
+
+    #[test]
+    fn should_submit_well_formed_transaction() {
+        let node_client = MockNodeClient::new(0);
+        let args = SendTxArgs {
+            from: Address::from_str(
+                "fe8aa8cc6011898d49bdacd0ab52075e92e1dfb2915bb9223528a5737583731d",
+            )
+            .unwrap(),
+            to: Address::from_str(
+                "f780b958227ff0bf5795ede8f9f7eaac67e7e06666b043a400026cbd421ce28e",
+            )
+            .unwrap(),
+            amount: 10,
+            node_url: "http://localhost:8000".to_string(),
+        };
+
+        let transaction = send_transaction(&args, &node_client).unwrap();
+
+        // the transaction matches the arguments...
+        assert_eq!(transaction.sender, args.from);
+        assert_eq!(transaction.recipient, args.to);
+        assert_eq!(transaction.amount, args.amount);
+
+        // ...and it's the one submitted to the node
+        let submitted_transactions = node_client.submitted_transactions.borrow();
+        assert_eq!(*submitted_transactions, vec![transaction]);
+    }
+}
@@ -1,98 +1,1348 @@
-use crate::{database::ConcurrentNodeDatabase, util::execution::Runnable};
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+mod coin_format;
+mod pretty_json;
+pub(crate) mod rate_limiter;
+mod server_settings;
+mod write_queue;
+
+use crate::{
+    database::{
+        is_already_known_transaction, BlockLocator, ChainStats, ChainTip, ChainVerificationReport,
+        ConcurrentNodeDatabase, ForkTip, HistoryDirection, HistoryEntry, NodeDatabaseError,
+        TransactionStatus, Utilization,
+    },
+    peer::{
+        clock::NodeTime,
+        stats::{ConcurrentPeerStats, PeerStats},
+    },
+    util::{config::Config, execution::Runnable},
+};
+use actix_cors::Cors;
+use actix_web::{
+    dev::Service,
+    error::{InternalError, JsonPayloadError},
+    guard,
+    http::{header, Method},
+    middleware, web, App, HttpRequest, HttpResponse, HttpServer, Resource, Responder,
+};
 use anyhow::Result;
+use futures::{future, stream, StreamExt};
+use serde::{Deserialize, Serialize};
 use spec::{
-    types::{Block, Transaction},
+    types::{
+        hash::{ConsensusHash, ConsensusHashable},
+        Address, Block, Coin, Network, Transaction,
+    },
+    validators::{estimate_mining_time_secs, get_next_difficulty, ChainError},
     Database as SpecDatabase,
 };
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use self::{
+    coin_format::CoinFormat,
+    pretty_json::{format_json_response, PrettyJson},
+    rate_limiter::RateLimiter,
+    server_settings::ServerSettings,
+    write_queue::{WriteQueue, WriteQueueError},
+};
+
+// Machine-readable description of the REST API, served at "/openapi.json"
+// Errors are returned as a plain text body with the reason,
+// except malformed bodies and rejected blocks (see "ApiError")
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        get_openapi,
+        get_network,
+        get_genesis,
+        get_block_template,
+        stream_block_templates,
+        estimate_mining_time,
+        get_blocks,
+        get_latest_block,
+        locate_blocks,
+        get_chain_tip,
+        add_block,
+        get_transactions,
+        get_pending_transaction_count,
+        add_transaction,
+        add_raw_transaction,
+        delete_transaction,
+        get_transaction_status,
+        validate_block,
+        validate_transaction,
+        simulate_transactions,
+        get_balance,
+        get_history,
+        get_peer_stats,
+        get_chain_stats,
+        get_time,
+        get_node_settings,
+        get_forks,
+        get_orphans,
+        export_chain,
+        verify_chain,
+    ),
+    components(schemas(
+        Block,
+        Transaction,
+        Network,
+        Genesis,
+        MiningTimeEstimate,
+        ValidationResult,
+        Balance,
+        HistoryEntry,
+        SimulatedBalance,
+        PendingTransactionCount,
+        TransactionStatus,
+        ChainTip,
+        BlockLocator,
+        ForkTip,
+        PeerStats,
+        NodeTime,
+        NodeSettings,
+        ApiError,
+        ChainVerificationReport,
+        ChainStats,
+        Utilization,
+    ))
+)]
+pub struct ApiDoc;
 
 pub struct Api {
-    port: u16,
+    config: Config,
     database: ConcurrentNodeDatabase,
+    peer_stats: ConcurrentPeerStats,
 }
 
 impl Runnable for Api {
+    fn name(&self) -> &str {
+        "api"
+    }
+
     fn run(&self) -> Result<()> {
-        start_server(self.port, &self.database)
+        start_server(&self.config, &self.database, &self.peer_stats)
     }
 }
 
 impl Api {
-    pub fn new(port: u16, database: &ConcurrentNodeDatabase) -> Api {
+    pub fn new(
+        config: &Config,
+        database: &ConcurrentNodeDatabase,
+        peer_stats: &ConcurrentPeerStats,
+    ) -> Api {
         Api {
-            port,
+            config: config.clone(),
             database: database.clone(),
+            peer_stats: peer_stats.clone(),
         }
     }
 }
 
 #[actix_web::main]
-async fn start_server(port: u16, database: &ConcurrentNodeDatabase) -> Result<()> {
-    let url = format!("localhost:{}", port);
+async fn start_server(
+    config: &Config,
+    database: &ConcurrentNodeDatabase,
+    peer_stats: &ConcurrentPeerStats,
+) -> Result<()> {
+    let url = format!("localhost:{}", config.port);
     let state = web::Data::new(database.clone());
+    let peer_stats = web::Data::new(peer_stats.clone());
+
+    // only the write and the expensive endpoints are rate limited
+    let rate_limiter = web::Data::new(RateLimiter::new(
+        config.rate_limit_burst,
+        config.rate_limit_per_second,
+    ));
+
+    let chain_verifier = web::Data::new(ChainVerifier::default());
+    let chain_exporter = web::Data::new(ChainExporter::default());
+    let read_only = web::Data::new(ReadOnlyMode(config.read_only));
+    let admin_endpoints = web::Data::new(AdminEndpoints(config.admin_endpoints));
+    let coin_format = web::Data::new(CoinFormat::new(config.coins_as_strings));
+    let pretty_json = web::Data::new(PrettyJson::new(config.pretty_json));
+    let node_settings = web::Data::new(NodeSettings::new(config, &database.get_network()));
+
+    // the writes are done in their own thread, one at a time, so the handlers never block the runtime
+    let (write_queue, write_worker) = WriteQueue::new(config.write_queue_capacity);
+    let write_queue = web::Data::new(write_queue);
+    thread::spawn(move || write_worker.run());
+
+    let cors_config = config.clone();
+    let settings = ServerSettings::new(config.api_workers, config.api_backlog);
 
     HttpServer::new(move || {
         App::new()
+            // JSON is indented before being compressed, when requested with "?pretty=true"
+            .wrap_fn({
+                let pretty_json = pretty_json.clone();
+                move |request, service| {
+                    let pretty = pretty_json.is_requested(&request);
+                    let response = service.call(request);
+                    async move { format_json_response(response.await?, pretty).await }
+                }
+            })
+            // responses are compressed when the client supports it (i.e. sends "Accept-Encoding")
+            .wrap(middleware::Compress::default())
             .app_data(state.clone())
-            .route("/block_template", web::get().to(get_block_template))
-            .route("/blocks", web::get().to(get_blocks))
-            .route("/blocks", web::post().to(add_block))
-            .route("/transactions", web::get().to(get_transactions))
-            .route("/transactions", web::post().to(add_transaction))
+            .app_data(rate_limiter.clone())
+            .app_data(write_queue.clone())
+            .app_data(chain_verifier.clone())
+            .app_data(chain_exporter.clone())
+            .app_data(peer_stats.clone())
+            .app_data(read_only.clone())
+            .app_data(admin_endpoints.clone())
+            .app_data(coin_format.clone())
+            .app_data(node_settings.clone())
+            .app_data(web::JsonConfig::default().error_handler(handle_json_error))
+            // the writes are registered first and outside of the CORS scope, so the browsers of other origins
+            // can never send them, whatever the allowed methods: their preflight requests get no CORS headers
+            .service(write_resource("/blocks", Method::POST).route(web::post().to(add_block)))
+            .service(
+                write_resource("/transactions", Method::POST)
+                    .route(web::post().to(add_transaction)),
+            )
+            .service(
+                write_resource("/transactions/raw", Method::POST)
+                    .route(web::post().to(add_raw_transaction)),
+            )
+            .service(
+                write_resource("/transactions/{id}", Method::DELETE)
+                    .route(web::delete().to(delete_transaction)),
+            )
+            .service(
+                web::scope("")
+                    .wrap(build_cors(&cors_config))
+                    .route("/openapi.json", web::get().to(get_openapi))
+                    .route("/network", web::get().to(get_network))
+                    .route("/genesis", web::get().to(get_genesis))
+                    .route("/block_template", web::get().to(get_block_template))
+                    .route(
+                        "/events/block_template",
+                        web::get().to(stream_block_templates),
+                    )
+                    .route("/estimate_mining_time", web::get().to(estimate_mining_time))
+                    .route("/blocks", web::get().to(get_blocks))
+                    .route("/blocks/latest", web::get().to(get_latest_block))
+                    .route("/blocks/locate", web::post().to(locate_blocks))
+                    .route("/tip", web::get().to(get_chain_tip))
+                    .route("/transactions", web::get().to(get_transactions))
+                    .route(
+                        "/transactions/pending/count",
+                        web::get().to(get_pending_transaction_count),
+                    )
+                    .route(
+                        "/transactions/{id}/status",
+                        web::get().to(get_transaction_status),
+                    )
+                    .route("/validate/block", web::post().to(validate_block))
+                    .route(
+                        "/validate/transaction",
+                        web::post().to(validate_transaction),
+                    )
+                    .route("/simulate", web::post().to(simulate_transactions))
+                    .route("/accounts/{address}/balance", web::get().to(get_balance))
+                    .route("/accounts/{address}/history", web::get().to(get_history))
+                    .route("/peers/stats", web::get().to(get_peer_stats))
+                    .route("/stats", web::get().to(get_chain_stats))
+                    .route("/time", web::get().to(get_time))
+                    .route("/config", web::get().to(get_node_settings))
+                    .route("/forks", web::get().to(get_forks))
+                    .route("/orphans", web::get().to(get_orphans))
+                    .route("/chain/export", web::get().to(export_chain))
+                    .route("/chain/verify", web::get().to(verify_chain)),
+            )
     })
-    .bind(url)
-    .unwrap()
+    .workers(settings.workers)
+    // the backlog only applies to the sockets bound after setting it
+    .backlog(settings.backlog)
+    .bind(url)?
     .run()
     .await?;
 
     Ok(())
 }
 
-async fn get_block_template(database: web::Data<ConcurrentNodeDatabase>) -> impl Responder {
-    let template_block = Block::new_template(database.as_ref());
+// Structured error returned when the request cannot be processed
+#[derive(Serialize, ToSchema)]
+pub struct ApiError {
+    code: String,
+    message: String,
+}
+
+impl ApiError {
+    fn new(code: &str, message: String) -> Self {
+        ApiError {
+            code: code.to_string(),
+            message,
+        }
+    }
+}
+
+// Bodies that are not valid JSON (or do not match the expected type) are reported with the parse detail
+fn handle_json_error(error: JsonPayloadError, _request: &HttpRequest) -> actix_web::Error {
+    let api_error = ApiError::new("malformed_request_body", error.to_string());
+    let response = HttpResponse::BadRequest().json(api_error);
+
+    InternalError::from_response(error, response).into()
+}
+
+// Nodes in read-only mode (e.g. explorer backends) reject mining and any submitted data
+struct ReadOnlyMode(bool);
+
+fn read_only_response() -> HttpResponse {
+    HttpResponse::Forbidden().body("The node is in read-only mode")
+}
+
+// The endpoints that alter the node for everyone are only served when explicitly enabled
+struct AdminEndpoints(bool);
+
+fn admin_disabled_response() -> HttpResponse {
+    HttpResponse::Forbidden().body("The admin endpoints are disabled")
+}
+
+fn write_queue_response(error: WriteQueueError) -> HttpResponse {
+    match error {
+        WriteQueueError::QueueFull => HttpResponse::TooManyRequests().body(error.to_string()),
+        WriteQueueError::WriterStopped => {
+            HttpResponse::ServiceUnavailable().body(error.to_string())
+        }
+    }
+}
+
+// Browser clients from other origins (e.g. explorers) can only use the allowed origins and methods
+// By default, only requests from the same origin are allowed
+// Resource of a write endpoint, matching only its method and the preflight requests for it,
+// so the other requests on the same path (e.g. "GET /transactions") are still routed to the reads
+fn write_resource(path: &str, method: Method) -> Resource {
+    let guard = guard::fn_guard(move |context| {
+        let head = context.head();
+        let is_preflight = head.method == Method::OPTIONS
+            && head
+                .headers()
+                .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+                .is_some_and(|requested| requested == method.as_str());
+        head.method == method || is_preflight
+    });
+
+    web::resource(path).guard(guard)
+}
+
+fn build_cors(config: &Config) -> Cors {
+    let mut cors = Cors::default().allowed_methods(
+        config
+            .cors_allowed_methods
+            .iter()
+            .map(|method| method.as_str()),
+    );
+
+    for origin in config.cors_allowed_origins.iter() {
+        cors = cors.allowed_origin(origin);
+    }
+
+    cors
+}
+
+// Returns the OpenAPI document describing all the routes of the API
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    responses((status = 200, description = "OpenAPI 3 document", body = Object))
+)]
+async fn get_openapi() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+// Returns the definition of the network that the node belongs to
+#[utoipa::path(
+    get,
+    path = "/network",
+    responses((status = 200, description = "Definition of the network", body = Network))
+)]
+async fn get_network(
+    coin_format: web::Data<CoinFormat>,
+    database: web::Data<ConcurrentNodeDatabase>,
+) -> impl Responder {
+    let network = database.get_network();
+
+    HttpResponse::Ok().json(coin_format.to_json(&network))
+}
+
+#[derive(Serialize, ToSchema)]
+struct Genesis {
+    // Hash of the network definition, committed by the genesis block as its previous hash
+    #[schema(value_type = String)]
+    network_hash: ConsensusHash,
+    network: Network,
+    // Not present until the genesis block is mined
+    block: Option<Block>,
+}
+
+// Returns the genesis block along with the network it commits to,
+// so peers and clients can check that they belong to the same network before syncing
+#[utoipa::path(
+    get,
+    path = "/genesis",
+    responses((status = 200, description = "Genesis block and network definition", body = Genesis))
+)]
+async fn get_genesis(
+    coin_format: web::Data<CoinFormat>,
+    database: web::Data<ConcurrentNodeDatabase>,
+) -> impl Responder {
+    let network = database.get_network();
+    let genesis = Genesis {
+        network_hash: network.consensus_hash(),
+        network,
+        block: database.get_block_by_index(0),
+    };
+
+    HttpResponse::Ok().json(coin_format.to_json(&genesis))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct BlockTemplateQuery {
+    // address that receives the coinbase reward
+    #[param(value_type = Option<String>)]
+    address: Option<Address>,
+}
+
+// Returns the template of the next block to be mined
+// When the miner address is indicated, the template already includes the coinbase transaction
+#[utoipa::path(
+    get,
+    path = "/block_template",
+    params(BlockTemplateQuery),
+    responses(
+        (status = 200, description = "Template of the next block", body = Block),
+        (status = 403, description = "The node is in read-only mode", body = String, content_type = "text/plain")
+    )
+)]
+async fn get_block_template(
+    read_only: web::Data<ReadOnlyMode>,
+    coin_format: web::Data<CoinFormat>,
+    database: web::Data<ConcurrentNodeDatabase>,
+    query: web::Query<BlockTemplateQuery>,
+) -> impl Responder {
+    if read_only.0 {
+        return read_only_response();
+    }
+
+    let template_block = build_block_template(database.as_ref(), query.into_inner().address);
+
+    HttpResponse::Ok().json(coin_format.to_json(&template_block))
+}
+
+fn build_block_template(database: &ConcurrentNodeDatabase, address: Option<Address>) -> Block {
+    match address {
+        Some(address) => Block::new_template_with_coinbase(database, address),
+        None => Block::new_template(database),
+    }
+}
+
+// Streams the template of the next block as server-sent events, so miners can restart right away
+// The current template is sent on connection, and a new one every time the tip or the mempool change
+#[utoipa::path(
+    get,
+    path = "/events/block_template",
+    params(BlockTemplateQuery),
+    responses(
+        (status = 200, description = "Stream of templates, each one as a JSON \"data\" event", body = String, content_type = "text/event-stream"),
+        (status = 403, description = "The node is in read-only mode", body = String, content_type = "text/plain")
+    )
+)]
+async fn stream_block_templates(
+    read_only: web::Data<ReadOnlyMode>,
+    coin_format: web::Data<CoinFormat>,
+    database: web::Data<ConcurrentNodeDatabase>,
+    query: web::Query<BlockTemplateQuery>,
+) -> impl Responder {
+    if read_only.0 {
+        return read_only_response();
+    }
+
+    // the current template goes first, and then a new one for each change
+    // until the client disconnects, when the stream is dropped along with the subscription
+    let address = query.into_inner().address;
+    let template_changes = database.subscribe_template_changes();
+    let events = stream::once(future::ready(()))
+        .chain(template_changes)
+        .map(move |_| {
+            let template_block = build_block_template(database.as_ref(), address.clone());
+            let event = format!("data: {}\n\n", coin_format.to_json(&template_block));
+            Ok::<_, actix_web::Error>(web::Bytes::from(event))
+        });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // compressed events could be held in the encoder buffers, delaying them
+        .insert_header((header::CONTENT_ENCODING, "identity"))
+        .streaming(events)
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct EstimateMiningTimeQuery {
+    hashrate: f64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MiningTimeEstimate {
+    difficulty: u32,
+    hashrate: f64,
+    expected_seconds: f64,
+}
+
+// Returns the expected time to mine the next block, given the hashrate (hashes per second) of the miner
+#[utoipa::path(
+    get,
+    path = "/estimate_mining_time",
+    params(EstimateMiningTimeQuery),
+    responses(
+        (status = 200, description = "Expected mining time", body = MiningTimeEstimate),
+        (status = 400, description = "Invalid hashrate", body = String, content_type = "text/plain")
+    )
+)]
+async fn estimate_mining_time(
+    database: web::Data<ConcurrentNodeDatabase>,
+    query: web::Query<EstimateMiningTimeQuery>,
+) -> impl Responder {
+    let hashrate = query.into_inner().hashrate;
+    if !hashrate.is_finite() || hashrate <= 0.0 {
+        return HttpResponse::BadRequest().body("The hashrate must be a positive number");
+    }
+
+    let difficulty = get_next_difficulty(database.as_ref());
+    let estimate = MiningTimeEstimate {
+        difficulty,
+        hashrate,
+        expected_seconds: estimate_mining_time_secs(difficulty, hashrate),
+    };
+
+    HttpResponse::Ok().json(&estimate)
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct BlocksQuery {
+    // index of the first block to return
+    from: Option<u64>,
+    // maximum number of blocks to return
+    limit: Option<u64>,
+}
+
+// Returns a list of all the blocks in the blockchain, or only a range of them
+#[utoipa::path(
+    get,
+    path = "/blocks",
+    params(BlocksQuery),
+    responses(
+        (status = 200, description = "The requested blocks of the blockchain", body = [Block]),
+        (status = 304, description = "The blocks did not change since the \"If-None-Match\" ETag")
+    )
+)]
+async fn get_blocks(
+    request: HttpRequest,
+    coin_format: web::Data<CoinFormat>,
+    database: web::Data<ConcurrentNodeDatabase>,
+    query: web::Query<BlocksQuery>,
+) -> impl Responder {
+    let from = query.from.unwrap_or(0);
+    let limit = query.limit.unwrap_or(u64::MAX);
+
+    // the list of blocks only changes when the tip changes,
+    // so clients that already have the latest list do not need to download it again
+    let etag = get_blocks_etag(database.as_ref(), from, limit);
+    let if_none_match = request.headers().get(header::IF_NONE_MATCH);
+    if let Some(value) = if_none_match {
+        if value.to_str().unwrap_or_default() == etag {
+            return HttpResponse::NotModified()
+                .insert_header((header::ETAG, etag))
+                .finish();
+        }
+    }
+
+    let blocks = database.get_blocks_range(from, limit);
+
+    HttpResponse::Ok()
+        .insert_header((header::ETAG, etag))
+        .json(coin_format.to_json(&blocks))
+}
+
+// Returns the blocks after the last one in common with the locator of a peer,
+// which finds where a peer on a competing branch diverged without downloading the whole blockchain
+#[utoipa::path(
+    post,
+    path = "/blocks/locate",
+    request_body = BlockLocator,
+    responses(
+        (status = 200, description = "The blocks after the last one in common, or from the genesis if there is none", body = [Block]),
+        (status = 400, description = "Malformed body", body = ApiError)
+    )
+)]
+async fn locate_blocks(
+    coin_format: web::Data<CoinFormat>,
+    database: web::Data<ConcurrentNodeDatabase>,
+    locator_json: web::Json<BlockLocator>,
+) -> impl Responder {
+    let locator = locator_json.into_inner();
+    let limit = locator.limit.unwrap_or(u64::MAX);
+    let blocks = database.get_blocks_after_locator(&locator.hashes, limit);
+
+    HttpResponse::Ok().json(coin_format.to_json(&blocks))
+}
+
+// Returns the tip block, without downloading the whole blockchain
+#[utoipa::path(
+    get,
+    path = "/blocks/latest",
+    responses(
+        (status = 200, description = "The tip block", body = Block),
+        (status = 404, description = "The blockchain is empty")
+    )
+)]
+async fn get_latest_block(
+    coin_format: web::Data<CoinFormat>,
+    database: web::Data<ConcurrentNodeDatabase>,
+) -> impl Responder {
+    match database.get_tip_block() {
+        Some(block) => HttpResponse::Ok().json(coin_format.to_json(&block)),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
 
-    HttpResponse::Ok().json(&template_block)
+// Returns the index, hash and total work of the tip, so peers can decide to sync without fetching blocks
+#[utoipa::path(
+    get,
+    path = "/tip",
+    responses(
+        (status = 200, description = "Header of the tip block", body = ChainTip),
+        (status = 404, description = "The blockchain is empty")
+    )
+)]
+async fn get_chain_tip(database: web::Data<ConcurrentNodeDatabase>) -> impl Responder {
+    match database.get_chain_tip() {
+        Some(chain_tip) => HttpResponse::Ok().json(chain_tip),
+        None => HttpResponse::NotFound().finish(),
+    }
 }
 
-// Returns a list of all the blocks in the blockchain
-async fn get_blocks(database: web::Data<ConcurrentNodeDatabase>) -> impl Responder {
-    let blocks = database.get_all_blocks();
+// The ETag of the list of blocks is derived from the hash of the tip block
+// Ranges of blocks are different resources, so they also include the range
+fn get_blocks_etag(database: &ConcurrentNodeDatabase, from: u64, limit: u64) -> String {
+    let tip = match database.get_tip_block() {
+        Some(tip_block) => tip_block.hash.to_string(),
+        None => "empty".to_string(),
+    };
 
-    HttpResponse::Ok().json(&blocks)
+    match (from, limit) {
+        (0, u64::MAX) => format!("\"{}\"", tip),
+        _ => format!("\"{}-{}-{}\"", tip, from, limit),
+    }
 }
 
 // Adds a new block to the blockchain
+#[utoipa::path(
+    post,
+    path = "/blocks",
+    request_body = Block,
+    responses(
+        (status = 200, description = "The block was added"),
+        (
+            status = 400,
+            description = "Stale block (\"stale_block\"), invalid block or malformed body",
+            body = ApiError
+        ),
+        (status = 409, description = "The block is already in the chain (\"duplicate_block\")", body = ApiError),
+        (status = 403, description = "The node is in read-only mode", body = String, content_type = "text/plain"),
+        (status = 429, description = "Too many requests, or too many pending writes")
+    )
+)]
 async fn add_block(
+    request: HttpRequest,
+    rate_limiter: web::Data<RateLimiter>,
+    read_only: web::Data<ReadOnlyMode>,
+    write_queue: web::Data<WriteQueue>,
     database: web::Data<ConcurrentNodeDatabase>,
     block_json: web::Json<Block>,
 ) -> HttpResponse {
+    if !is_request_allowed(&request, &rate_limiter) {
+        return HttpResponse::TooManyRequests().finish();
+    }
+
+    if read_only.0 {
+        return read_only_response();
+    }
+
     let block = block_json.into_inner();
-    let result = database.append_block(&block);
+    let index = block.index;
+    let appending_database = database.get_ref().clone();
+    let result = match write_queue
+        .execute(move || appending_database.append_block(&block))
+        .await
+    {
+        Ok(result) => result,
+        Err(error) => return write_queue_response(error),
+    };
 
     match result {
         Ok(_) => {
-            info!("Received new block {}", block.index);
+            info!("Received new block {}", index);
             HttpResponse::Ok().finish()
         }
-        Err(error) => HttpResponse::BadRequest().body(error.to_string()),
+        Err(error) if is_duplicated_block(&error) => {
+            HttpResponse::Conflict().json(ApiError::new("duplicate_block", error.to_string()))
+        }
+        Err(error) => {
+            let num_blocks = database.get_block_count();
+            HttpResponse::BadRequest().json(get_block_rejection(&error, index, num_blocks))
+        }
     }
 }
 
+fn is_duplicated_block(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref(),
+        Some(NodeDatabaseError::BlockAlreadyExists)
+    )
+}
+
+// Blocks that do not follow our tip were most likely mined on a template that became stale,
+// so they are told apart for the miners to request a new template instead of giving up
+// Only the blocks for an index that the chain already reached can be stale, the ones beyond our tip are invalid
+fn get_block_rejection(error: &anyhow::Error, index: u64, num_blocks: u64) -> ApiError {
+    let is_stale = index < num_blocks
+        && matches!(
+            error.downcast_ref(),
+            Some(ChainError::InvalidIndex | ChainError::InvalidPreviousHash)
+        );
+    let code = if is_stale {
+        "stale_block"
+    } else {
+        "invalid_block"
+    };
+
+    ApiError::new(code, error.to_string())
+}
+
 // Returns a list of all the transactions that are not yet included into a block
-async fn get_transactions(database: web::Data<ConcurrentNodeDatabase>) -> impl Responder {
+#[utoipa::path(
+    get,
+    path = "/transactions",
+    responses((status = 200, description = "Transactions in the pool", body = [Transaction]))
+)]
+async fn get_transactions(
+    coin_format: web::Data<CoinFormat>,
+    database: web::Data<ConcurrentNodeDatabase>,
+) -> impl Responder {
     let transactions = database.get_mempool_transactions();
-    HttpResponse::Ok().json(&transactions)
+    HttpResponse::Ok().json(coin_format.to_json(&transactions))
+}
+
+#[derive(Serialize, ToSchema)]
+struct PendingTransactionCount {
+    count: usize,
+}
+
+// Returns the number of transactions in the pool, so pollers can check if there is work
+// without receiving all the transactions
+#[utoipa::path(
+    get,
+    path = "/transactions/pending/count",
+    responses((status = 200, description = "Number of transactions in the pool", body = PendingTransactionCount))
+)]
+async fn get_pending_transaction_count(
+    database: web::Data<ConcurrentNodeDatabase>,
+) -> impl Responder {
+    let count = database.get_mempool_transaction_count();
+    HttpResponse::Ok().json(PendingTransactionCount { count })
 }
 
 // Adds a new transaction to the pool, to be included on the next block
+#[utoipa::path(
+    post,
+    path = "/transactions",
+    request_body = Transaction,
+    responses(
+        (status = 200, description = "The transaction was added to the pool"),
+        (
+            status = 400,
+            description = "Invalid transaction or malformed body",
+            content(("text/plain" = String), ("application/json" = ApiError))
+        ),
+        (status = 409, description = "The transaction is already pending or recently confirmed", body = String, content_type = "text/plain"),
+        (status = 403, description = "The node is in read-only mode", body = String, content_type = "text/plain"),
+        (status = 429, description = "Too many requests, or too many pending writes")
+    )
+)]
 async fn add_transaction(
+    request: HttpRequest,
+    rate_limiter: web::Data<RateLimiter>,
+    read_only: web::Data<ReadOnlyMode>,
+    write_queue: web::Data<WriteQueue>,
     database: web::Data<ConcurrentNodeDatabase>,
     transaction_json: web::Json<Transaction>,
 ) -> impl Responder {
+    if !is_request_allowed(&request, &rate_limiter) {
+        return HttpResponse::TooManyRequests().finish();
+    }
+
+    if read_only.0 {
+        return read_only_response();
+    }
+
     let transaction = transaction_json.into_inner();
-    let result = database.add_mempool_transaction(transaction);
+    admit_transaction(&write_queue, &database, transaction).await
+}
+
+// Adds a transaction encoded with bincode and written in hex (e.g. by an offline signer) to the pool,
+// with the same validations as the JSON transactions
+#[utoipa::path(
+    post,
+    path = "/transactions/raw",
+    request_body(content = String, content_type = "text/plain", description = "Hex of the bincode encoded transaction"),
+    responses(
+        (status = 200, description = "The transaction was added to the pool"),
+        (
+            status = 400,
+            description = "Invalid transaction, or malformed transaction (\"malformed_transaction\")",
+            content(("text/plain" = String), ("application/json" = ApiError))
+        ),
+        (status = 409, description = "The transaction is already pending or recently confirmed", body = String, content_type = "text/plain"),
+        (status = 403, description = "The node is in read-only mode", body = String, content_type = "text/plain"),
+        (status = 429, description = "Too many requests, or too many pending writes")
+    )
+)]
+async fn add_raw_transaction(
+    request: HttpRequest,
+    rate_limiter: web::Data<RateLimiter>,
+    read_only: web::Data<ReadOnlyMode>,
+    write_queue: web::Data<WriteQueue>,
+    database: web::Data<ConcurrentNodeDatabase>,
+    body: String,
+) -> impl Responder {
+    if !is_request_allowed(&request, &rate_limiter) {
+        return HttpResponse::TooManyRequests().finish();
+    }
+
+    if read_only.0 {
+        return read_only_response();
+    }
+
+    let transaction = match decode_raw_transaction(&body) {
+        Ok(value) => value,
+        Err(message) => {
+            return HttpResponse::BadRequest().json(ApiError::new("malformed_transaction", message))
+        }
+    };
+    admit_transaction(&write_queue, &database, transaction).await
+}
+
+fn decode_raw_transaction(body: &str) -> Result<Transaction, String> {
+    let bytes = hex::decode(body.trim()).map_err(|error| format!("Invalid hex: {}", error))?;
+    bincode::deserialize(&bytes).map_err(|error| format!("Invalid transaction encoding: {}", error))
+}
+
+async fn admit_transaction(
+    write_queue: &WriteQueue,
+    database: &ConcurrentNodeDatabase,
+    transaction: Transaction,
+) -> HttpResponse {
+    let database = database.clone();
+    let result = match write_queue
+        .execute(move || database.add_mempool_transaction(transaction))
+        .await
+    {
+        Ok(result) => result,
+        Err(error) => return write_queue_response(error),
+    };
+
     match result {
         Ok(_) => HttpResponse::Ok().finish(),
+        // told apart from invalid transactions, as clients can safely ignore them when resubmitting
+        Err(error) if is_already_known_transaction(&error) => {
+            HttpResponse::Conflict().body(error.to_string())
+        }
+        Err(error) => HttpResponse::BadRequest().body(error.to_string()),
+    }
+}
+
+// Returns whether a transaction is pending in the pool, confirmed in a block, or unknown to the node
+#[utoipa::path(
+    get,
+    path = "/transactions/{id}/status",
+    params(("id" = String, Path, description = "Hash of the transaction")),
+    responses(
+        (status = 200, description = "Status of the transaction, with its block when confirmed", body = TransactionStatus),
+        (status = 400, description = "Invalid hash", body = String, content_type = "text/plain")
+    )
+)]
+async fn get_transaction_status(
+    database: web::Data<ConcurrentNodeDatabase>,
+    id: web::Path<String>,
+) -> impl Responder {
+    let id = match id.parse::<ConsensusHash>() {
+        Ok(value) => value,
+        Err(error) => return HttpResponse::BadRequest().body(error.to_string()),
+    };
+
+    let status = database.get_transaction_status(&id);
+
+    HttpResponse::Ok().json(&status)
+}
+
+// Removes a transaction from the pool, so it will not be included on the next block
+#[utoipa::path(
+    delete,
+    path = "/transactions/{id}",
+    params(("id" = String, Path, description = "Hash of the transaction")),
+    responses(
+        (status = 200, description = "The transaction was removed from the pool"),
+        (status = 400, description = "Invalid hash", body = String, content_type = "text/plain"),
+        (status = 404, description = "The transaction is not in the pool"),
+        (status = 403, description = "The admin endpoints are disabled, or the node is in read-only mode", body = String, content_type = "text/plain"),
+        (status = 429, description = "Too many requests, or too many pending writes")
+    )
+)]
+async fn delete_transaction(
+    request: HttpRequest,
+    rate_limiter: web::Data<RateLimiter>,
+    read_only: web::Data<ReadOnlyMode>,
+    admin_endpoints: web::Data<AdminEndpoints>,
+    write_queue: web::Data<WriteQueue>,
+    database: web::Data<ConcurrentNodeDatabase>,
+    id: web::Path<String>,
+) -> impl Responder {
+    if !admin_endpoints.0 {
+        return admin_disabled_response();
+    }
+
+    if !is_request_allowed(&request, &rate_limiter) {
+        return HttpResponse::TooManyRequests().finish();
+    }
+
+    if read_only.0 {
+        return read_only_response();
+    }
+
+    let id = match id.parse::<ConsensusHash>() {
+        Ok(value) => value,
+        Err(error) => return HttpResponse::BadRequest().body(error.to_string()),
+    };
+
+    let database = database.get_ref().clone();
+    let found = match write_queue
+        .execute(move || database.remove_mempool_transaction(&id))
+        .await
+    {
+        Ok(found) => found,
+        Err(error) => return write_queue_response(error),
+    };
+
+    if !found {
+        return HttpResponse::NotFound().finish();
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[derive(Serialize, ToSchema)]
+struct ValidationResult {
+    valid: bool,
+    error: Option<String>,
+}
+
+impl From<Result<()>> for ValidationResult {
+    fn from(result: Result<()>) -> Self {
+        ValidationResult {
+            valid: result.is_ok(),
+            error: result.err().map(|error| error.to_string()),
+        }
+    }
+}
+
+// Checks if a block would be accepted by the node, without adding it to the blockchain
+#[utoipa::path(
+    post,
+    path = "/validate/block",
+    request_body = Block,
+    responses(
+        (status = 200, description = "Result of the validation", body = ValidationResult),
+        (status = 400, description = "Malformed body", body = ApiError)
+    )
+)]
+async fn validate_block(
+    database: web::Data<ConcurrentNodeDatabase>,
+    block_json: web::Json<Block>,
+) -> impl Responder {
+    let result = database.check_block(&block_json.into_inner());
+
+    HttpResponse::Ok().json(ValidationResult::from(result))
+}
+
+// Checks if a transaction would be accepted by the node, without adding it to the pool
+#[utoipa::path(
+    post,
+    path = "/validate/transaction",
+    request_body = Transaction,
+    responses(
+        (status = 200, description = "Result of the validation", body = ValidationResult),
+        (status = 400, description = "Malformed body", body = ApiError)
+    )
+)]
+async fn validate_transaction(
+    database: web::Data<ConcurrentNodeDatabase>,
+    transaction_json: web::Json<Transaction>,
+) -> impl Responder {
+    let result = database.check_transaction(&transaction_json.into_inner());
+
+    HttpResponse::Ok().json(ValidationResult::from(result))
+}
+
+#[derive(Serialize, ToSchema)]
+struct SimulatedBalance {
+    #[schema(value_type = String)]
+    address: Address,
+    balance: Coin,
+}
+
+// Maximum number of transactions of a single simulation, as each one is validated against the chain
+const MAX_SIMULATED_TRANSACTIONS: usize = 100;
+
+// Returns the balances of the involved accounts after applying the transactions in order,
+// without modifying the state of the node nor adding them to the pool
+#[utoipa::path(
+    post,
+    path = "/simulate",
+    request_body = [Transaction],
+    responses(
+        (status = 200, description = "Resulting balances", body = [SimulatedBalance]),
+        (
+            status = 400,
+            description = "Invalid transactions, too many of them or malformed body",
+            content(("text/plain" = String), ("application/json" = ApiError))
+        ),
+        (status = 429, description = "Too many requests")
+    )
+)]
+async fn simulate_transactions(
+    request: HttpRequest,
+    rate_limiter: web::Data<RateLimiter>,
+    coin_format: web::Data<CoinFormat>,
+    database: web::Data<ConcurrentNodeDatabase>,
+    transactions_json: web::Json<Vec<Transaction>>,
+) -> impl Responder {
+    if !is_request_allowed(&request, &rate_limiter) {
+        return HttpResponse::TooManyRequests().finish();
+    }
+
+    if transactions_json.len() > MAX_SIMULATED_TRANSACTIONS {
+        return HttpResponse::BadRequest().body(format!(
+            "At most {} transactions can be simulated at once",
+            MAX_SIMULATED_TRANSACTIONS
+        ));
+    }
+
+    match database.simulate_transactions(&transactions_json) {
+        Ok(balances) => {
+            let balances: Vec<_> = balances
+                .into_iter()
+                .map(|(address, balance)| SimulatedBalance { address, balance })
+                .collect();
+            HttpResponse::Ok().json(coin_format.to_json(&balances))
+        }
         Err(error) => HttpResponse::BadRequest().body(error.to_string()),
     }
 }
+
+#[derive(Serialize, ToSchema)]
+struct Balance {
+    total: Coin,
+    spendable: Coin,
+}
+
+// Returns the balance of an account, both in total and excluding immature coinbase rewards
+#[utoipa::path(
+    get,
+    path = "/accounts/{address}/balance",
+    params(("address" = String, Path, description = "Address of the account")),
+    responses(
+        (status = 200, description = "Balance of the account", body = Balance),
+        (status = 400, description = "Invalid address", body = String, content_type = "text/plain"),
+        (status = 404, description = "The account does not exist")
+    )
+)]
+async fn get_balance(
+    coin_format: web::Data<CoinFormat>,
+    database: web::Data<ConcurrentNodeDatabase>,
+    address: web::Path<String>,
+) -> impl Responder {
+    let address = match address.parse::<Address>() {
+        Ok(value) => value,
+        Err(error) => return HttpResponse::BadRequest().body(error.to_string()),
+    };
+
+    let total = database.get_account_balance(&address);
+    let spendable = database.get_spendable_balance(&address, database.get_block_count());
+    match (total, spendable) {
+        (Some(total), Some(spendable)) => {
+            HttpResponse::Ok().json(coin_format.to_json(&Balance { total, spendable }))
+        }
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+struct HistoryQuery {
+    // "sent", "received" or "all" (the default)
+    #[param(value_type = Option<String>)]
+    direction: Option<HistoryDirection>,
+    // number of matching transactions to skip
+    from: Option<u64>,
+    // maximum number of transactions to return
+    limit: Option<u64>,
+}
+
+// Returns the transactions in the chain sent and/or received by an account, oldest first
+#[utoipa::path(
+    get,
+    path = "/accounts/{address}/history",
+    params(("address" = String, Path, description = "Address of the account"), HistoryQuery),
+    responses(
+        (status = 200, description = "Transactions of the account, with the index of their block", body = [HistoryEntry]),
+        (status = 400, description = "Invalid address or query", body = String, content_type = "text/plain")
+    )
+)]
+async fn get_history(
+    coin_format: web::Data<CoinFormat>,
+    database: web::Data<ConcurrentNodeDatabase>,
+    address: web::Path<String>,
+    query: web::Query<HistoryQuery>,
+) -> impl Responder {
+    let address = match address.parse::<Address>() {
+        Ok(value) => value,
+        Err(error) => return HttpResponse::BadRequest().body(error.to_string()),
+    };
+
+    let direction = query.direction.unwrap_or_default();
+    let from = query.from.unwrap_or(0);
+    let limit = query.limit.unwrap_or(u64::MAX);
+    let history = database.get_address_history(&address, direction, from, limit);
+
+    HttpResponse::Ok().json(coin_format.to_json(&history))
+}
+
+// Returns the tips of the main chain and of the competing branches known by the node
+#[utoipa::path(
+    get,
+    path = "/forks",
+    responses((status = 200, description = "Tips of all known branches", body = [ForkTip]))
+)]
+async fn get_forks(database: web::Data<ConcurrentNodeDatabase>) -> impl Responder {
+    let fork_tips = database.get_fork_tips();
+
+    HttpResponse::Ok().json(&fork_tips)
+}
+
+// Returns the blocks received whose parent is unknown
+#[utoipa::path(
+    get,
+    path = "/orphans",
+    responses((status = 200, description = "Orphan blocks", body = [Block]))
+)]
+async fn get_orphans(
+    coin_format: web::Data<CoinFormat>,
+    database: web::Data<ConcurrentNodeDatabase>,
+) -> impl Responder {
+    let orphans = database.get_orphan_blocks();
+
+    HttpResponse::Ok().json(coin_format.to_json(&orphans))
+}
+
+// Allows only one run at a time of an expensive operation over the whole chain
+#[derive(Default)]
+struct SingleFlight {
+    is_running: AtomicBool,
+}
+
+impl SingleFlight {
+    fn try_start(&self) -> Option<SingleFlightRun<'_>> {
+        let was_running = self.is_running.swap(true, Ordering::SeqCst);
+        (!was_running).then_some(SingleFlightRun(self))
+    }
+}
+
+// The run is over when dropped, even if the client disconnected in the middle
+struct SingleFlightRun<'a>(&'a SingleFlight);
+
+impl Drop for SingleFlightRun<'_> {
+    fn drop(&mut self) {
+        self.0.is_running.store(false, Ordering::SeqCst);
+    }
+}
+
+// Each verification of the chain replays all the blocks
+#[derive(Default)]
+struct ChainVerifier(SingleFlight);
+
+// Each export of the chain copies and encodes all the blocks
+#[derive(Default)]
+struct ChainExporter(SingleFlight);
+
+// Returns all the blocks of the chain in binary, so new nodes can import them instead of syncing from peers
+// It is expensive for long chains, so it is both rate limited and run once at a time
+#[utoipa::path(
+    get,
+    path = "/chain/export",
+    responses(
+        (status = 200, description = "Bincode-encoded blocks of the chain", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 429, description = "Too many requests, or another export is running")
+    )
+)]
+async fn export_chain(
+    request: HttpRequest,
+    rate_limiter: web::Data<RateLimiter>,
+    chain_exporter: web::Data<ChainExporter>,
+    database: web::Data<ConcurrentNodeDatabase>,
+) -> impl Responder {
+    if !is_request_allowed(&request, &rate_limiter) {
+        return HttpResponse::TooManyRequests().finish();
+    }
+
+    let Some(_export) = chain_exporter.0.try_start() else {
+        return HttpResponse::TooManyRequests().body("Another export of the chain is running");
+    };
+
+    // the copy and encoding of the blocks do not block the runtime
+    let database = database.get_ref().clone();
+    match web::block(move || database.export_chain().to_bytes()).await {
+        Ok(Ok(bytes)) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(bytes),
+        Ok(Err(error)) => HttpResponse::InternalServerError().body(error.to_string()),
+        Err(error) => HttpResponse::InternalServerError().body(error.to_string()),
+    }
+}
+
+// Validates the whole chain again and reports the first error found, if any
+// It is expensive for long chains, so it has its own limit instead of sharing the one of the writes
+#[utoipa::path(
+    get,
+    path = "/chain/verify",
+    responses(
+        (status = 200, description = "Result of the verification", body = ChainVerificationReport),
+        (status = 429, description = "Another verification is running")
+    )
+)]
+async fn verify_chain(
+    chain_verifier: web::Data<ChainVerifier>,
+    database: web::Data<ConcurrentNodeDatabase>,
+) -> impl Responder {
+    let Some(_verification) = chain_verifier.0.try_start() else {
+        return HttpResponse::TooManyRequests()
+            .body("Another verification of the chain is running");
+    };
+
+    // the replay does not block the runtime, so the other requests are still served meanwhile
+    let database = database.get_ref().clone();
+    match web::block(move || database.verify_chain()).await {
+        Ok(report) => HttpResponse::Ok().json(&report),
+        Err(error) => HttpResponse::InternalServerError().body(error.to_string()),
+    }
+}
+
+// Returns the synchronization statistics of each peer
+#[utoipa::path(
+    get,
+    path = "/peers/stats",
+    responses(
+        (status = 200, description = "Statistics indexed by peer address", body = BTreeMap<String, PeerStats>)
+    )
+)]
+async fn get_peer_stats(peer_stats: web::Data<ConcurrentPeerStats>) -> impl Responder {
+    let all_stats = peer_stats.get_all();
+
+    HttpResponse::Ok().json(&all_stats)
+}
+
+// Returns a summary of the current state of the chain, used by the "stats" command
+#[utoipa::path(
+    get,
+    path = "/stats",
+    responses((status = 200, description = "Summary of the chain", body = ChainStats))
+)]
+async fn get_chain_stats(
+    coin_format: web::Data<CoinFormat>,
+    database: web::Data<ConcurrentNodeDatabase>,
+) -> impl Responder {
+    let stats = database.get_chain_stats();
+
+    HttpResponse::Ok().json(coin_format.to_json(&stats))
+}
+
+// Returns the current time of the node, so peers can detect if their clocks are too far apart
+#[utoipa::path(
+    get,
+    path = "/time",
+    responses((status = 200, description = "Current time of the node", body = NodeTime))
+)]
+async fn get_time() -> impl Responder {
+    HttpResponse::Ok().json(NodeTime::now())
+}
+
+// Settings of the node that are safe to share, to check that the nodes of a network are configured alike
+// Webhook URLs are left out, as they often embed access tokens
+#[derive(Serialize, ToSchema)]
+struct NodeSettings {
+    port: u16,
+    tcp_port: u16,
+    peers: Vec<String>,
+    read_only: bool,
+    max_mempool_bytes: u64,
+    max_future_blocks: u64,
+    // Hash of the network definition, the same for every node of the network
+    #[schema(value_type = String)]
+    network_id: ConsensusHash,
+    difficulty: u32,
+    target_block_time_ms: u64,
+    max_block_weight: u64,
+}
+
+impl NodeSettings {
+    fn new(config: &Config, network: &Network) -> Self {
+        NodeSettings {
+            port: config.port,
+            tcp_port: config.tcp_port,
+            peers: config.peers.clone(),
+            read_only: config.read_only,
+            max_mempool_bytes: config.max_mempool_bytes,
+            max_future_blocks: config.max_future_blocks,
+            network_id: network.consensus_hash(),
+            difficulty: network.difficulty,
+            target_block_time_ms: network.target_block_time_ms,
+            max_block_weight: network.max_block_weight,
+        }
+    }
+}
+
+// Returns the non-sensitive settings of the node
+#[utoipa::path(
+    get,
+    path = "/config",
+    responses((status = 200, description = "Settings of the node", body = NodeSettings))
+)]
+async fn get_node_settings(node_settings: web::Data<NodeSettings>) -> impl Responder {
+    HttpResponse::Ok().json(node_settings.as_ref())
+}
+
+// Clients are identified by their IP address, requests without one are always allowed
+fn is_request_allowed(request: &HttpRequest, rate_limiter: &RateLimiter) -> bool {
+    match request.peer_addr() {
+        Some(address) => rate_limiter.try_acquire(address.ip()),
+        None => true,
+    }
+}
@@ -0,0 +1,83 @@
+use serde::Serialize;
+use serde_json::Value;
+
+// Fields of the API responses that hold coin amounts
+// (transaction and premine amounts, balances, and the total supply of the chain stats)
+const COIN_FIELDS: [&str; 5] = ["amount", "balance", "total", "spendable", "total_supply"];
+
+// How coin amounts are written in the JSON responses
+// JavaScript clients lose precision on numbers above 2^53, so amounts can be sent as decimal strings
+// Amounts sent as strings are parsed back by the nodes and miners reading them
+pub struct CoinFormat {
+    as_strings: bool,
+}
+
+impl CoinFormat {
+    pub fn new(as_strings: bool) -> Self {
+        Self { as_strings }
+    }
+
+    pub fn to_json<T: Serialize>(&self, body: &T) -> Value {
+        let mut value = serde_json::to_value(body).unwrap_or_default();
+        if self.as_strings {
+            coins_to_strings(&mut value);
+        }
+
+        value
+    }
+}
+
+fn coins_to_strings(value: &mut Value) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(coins_to_strings),
+        Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                match field {
+                    Value::Number(number) if COIN_FIELDS.contains(&name.as_str()) => {
+                        *field = Value::String(number.to_string());
+                    }
+                    _ => coins_to_strings(field),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use spec::types::{Address, Coin, Transaction};
+
+    use super::*;
+
+    #[test]
+    fn should_keep_numbers_by_default() {
+        let transaction = Transaction::new(Address::default(), Address::default(), 10);
+
+        let value = CoinFormat::new(false).to_json(&transaction);
+
+        assert_eq!(value["amount"], 10);
+    }
+
+    #[test]
+    fn should_write_nested_amounts_as_strings() {
+        let transactions = vec![Transaction::new(Address::default(), Address::default(), 10)];
+
+        let value = CoinFormat::new(true).to_json(&json!({ "transactions": transactions }));
+
+        assert_eq!(value["transactions"][0]["amount"], "10");
+    }
+
+    #[test]
+    fn should_round_trip_big_balances_exactly() {
+        let total: Coin = (1 << 53) + 1;
+        let balance = json!({ "total": total, "spendable": total });
+
+        let value = CoinFormat::new(true).to_json(&balance);
+
+        let total_str = value["total"].as_str().unwrap();
+        assert_eq!(total_str, "9007199254740993");
+        assert_eq!(total_str.parse::<Coin>().unwrap(), total);
+    }
+}
@@ -0,0 +1,118 @@
+use actix_web::{
+    body::{to_bytes, BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    error::ErrorInternalServerError,
+    http::{header, Method},
+    web, Error,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Deserialize)]
+struct PrettyQuery {
+    pretty: Option<bool>,
+}
+
+// Whether the JSON responses of the read endpoints are indented, for humans debugging with curl
+// Responses stay compact by default, and each request can override it with "?pretty=true|false"
+pub struct PrettyJson {
+    default: bool,
+}
+
+impl PrettyJson {
+    pub fn new(default: bool) -> Self {
+        Self { default }
+    }
+
+    pub fn is_requested(&self, request: &ServiceRequest) -> bool {
+        if request.method() != Method::GET {
+            return false;
+        }
+
+        web::Query::<PrettyQuery>::from_query(request.query_string())
+            .ok()
+            .and_then(|query| query.pretty)
+            .unwrap_or(self.default)
+    }
+}
+
+// Indent the body of JSON responses, leaving any other response untouched
+pub async fn format_json_response<B: MessageBody + 'static>(
+    response: ServiceResponse<B>,
+    pretty: bool,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let response = response.map_into_boxed_body();
+    if !pretty || !is_json(&response) {
+        return Ok(response);
+    }
+
+    let (request, response) = response.into_parts();
+    let (response, body) = response.into_parts();
+    let bytes = to_bytes(body)
+        .await
+        .map_err(|error| ErrorInternalServerError(error.to_string()))?;
+
+    let body = match prettify(&bytes) {
+        Some(pretty_body) => BoxBody::new(pretty_body),
+        None => BoxBody::new(bytes),
+    };
+
+    Ok(ServiceResponse::new(request, response.set_body(body)))
+}
+
+fn is_json(response: &ServiceResponse<BoxBody>) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/json"))
+}
+
+fn prettify(bytes: &[u8]) -> Option<String> {
+    let value: Value = serde_json::from_slice(bytes).ok()?;
+    serde_json::to_string_pretty(&value).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn should_indent_json_bodies() {
+        let pretty_body = prettify(br#"{"name":"test","values":[1,2]}"#).unwrap();
+
+        assert_eq!(
+            pretty_body,
+            "{\n  \"name\": \"test\",\n  \"values\": [\n    1,\n    2\n  ]\n}"
+        );
+    }
+
+    #[test]
+    fn should_leave_invalid_json_untouched() {
+        assert_eq!(prettify(b"not json"), None);
+    }
+
+    #[test]
+    fn should_let_the_query_override_the_default() {
+        let pretty_json = PrettyJson::new(false);
+        let request = TestRequest::get().uri("/blocks?pretty=true&limit=1");
+        assert!(pretty_json.is_requested(&request.to_srv_request()));
+
+        let pretty_json = PrettyJson::new(true);
+        let request = TestRequest::get().uri("/blocks?pretty=false");
+        assert!(!pretty_json.is_requested(&request.to_srv_request()));
+
+        let request = TestRequest::get().uri("/blocks");
+        assert!(pretty_json.is_requested(&request.to_srv_request()));
+    }
+
+    #[test]
+    fn should_only_format_reads() {
+        let pretty_json = PrettyJson::new(true);
+        let request = TestRequest::post().uri("/transactions?pretty=true");
+
+        assert!(!pretty_json.is_requested(&request.to_srv_request()));
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// How often the buckets of the clients that stopped making requests are evicted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+// Limits the amount of requests that each client (identified by its IP address) can make
+pub struct RateLimiter {
+    burst: u32,
+    per_second: u32,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    by_ip: HashMap<IpAddr, TokenBucket>,
+    last_prune: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(burst: u32, per_second: u32) -> Self {
+        Self {
+            burst,
+            per_second,
+            buckets: Mutex::new(Buckets {
+                by_ip: HashMap::new(),
+                last_prune: Instant::now(),
+            }),
+        }
+    }
+
+    // Returns whether the client is allowed to make a new request
+    pub fn try_acquire(&self, ip: IpAddr) -> bool {
+        self.try_acquire_at(ip, Instant::now())
+    }
+
+    fn try_acquire_at(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // a bucket that refilled completely is the same as a new one, so it can be dropped
+        // otherwise the map would keep growing with every client that ever made a request
+        if now.saturating_duration_since(buckets.last_prune) >= PRUNE_INTERVAL {
+            buckets.by_ip.retain(|_, bucket| !bucket.is_full(now));
+            buckets.last_prune = now;
+        }
+
+        let bucket = buckets
+            .by_ip
+            .entry(ip)
+            .or_insert_with(|| TokenBucket::new(self.burst, self.per_second));
+
+        bucket.try_consume(now)
+    }
+}
+
+// Each request consumes a token, and tokens are refilled at a constant rate up to the bucket capacity
+struct TokenBucket {
+    capacity: f64,
+    refill_per_second: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: u32, refill_per_second: u32) -> Self {
+        Self {
+            capacity: capacity.into(),
+            refill_per_second: refill_per_second.into(),
+            tokens: capacity.into(),
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn try_consume(&mut self, now: Instant) -> bool {
+        self.refill(now);
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+
+    fn is_full(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens + elapsed.as_secs_f64() * self.refill_per_second >= self.capacity
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let new_tokens = elapsed.as_secs_f64() * self.refill_per_second;
+        self.tokens = (self.tokens + new_tokens).min(self.capacity);
+        self.last_refill = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn should_reject_bursts_beyond_capacity() {
+        let mut bucket = TokenBucket::new(2, 1);
+        let now = bucket.last_refill;
+
+        assert!(bucket.try_consume(now));
+        assert!(bucket.try_consume(now));
+        assert!(!bucket.try_consume(now));
+    }
+
+    #[test]
+    fn should_refill_over_time() {
+        let mut bucket = TokenBucket::new(2, 1);
+        let now = bucket.last_refill;
+
+        // empty the bucket
+        assert!(bucket.try_consume(now));
+        assert!(bucket.try_consume(now));
+        assert!(!bucket.try_consume(now));
+
+        // after one second, a single token is available again
+        let now = now + Duration::from_secs(1);
+        assert!(bucket.try_consume(now));
+        assert!(!bucket.try_consume(now));
+
+        // the bucket never refills beyond its capacity
+        let now = now + Duration::from_secs(60);
+        assert!(bucket.try_consume(now));
+        assert!(bucket.try_consume(now));
+        assert!(!bucket.try_consume(now));
+    }
+
+    #[test]
+    fn should_evict_the_clients_that_stopped_making_requests() {
+        let rate_limiter = RateLimiter::new(2, 1);
+        let now = rate_limiter.buckets.lock().unwrap().last_prune;
+        let idle_client = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let active_client = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+        assert!(rate_limiter.try_acquire_at(idle_client, now));
+        assert!(rate_limiter.try_acquire_at(active_client, now));
+        assert_eq!(rate_limiter.buckets.lock().unwrap().by_ip.len(), 2);
+
+        // the active client empties its bucket right before the buckets are pruned
+        let now = now + PRUNE_INTERVAL - Duration::from_millis(100);
+        assert!(rate_limiter.try_acquire_at(active_client, now));
+        assert!(rate_limiter.try_acquire_at(active_client, now));
+
+        // so only the idle client, whose bucket is full again, is evicted
+        let now = now + Duration::from_millis(100);
+        assert!(!rate_limiter.try_acquire_at(active_client, now));
+        let buckets = rate_limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.by_ip.len(), 1);
+        assert!(buckets.by_ip.contains_key(&active_client));
+    }
+}
@@ -0,0 +1,40 @@
+use std::thread;
+
+// Tuning of the HTTP server, so operators can adapt it to their load
+pub struct ServerSettings {
+    pub workers: usize,
+    pub backlog: u32,
+}
+
+impl ServerSettings {
+    // Without a configured number of workers, there is one per available CPU
+    pub fn new(workers: usize, backlog: u32) -> Self {
+        let workers = match workers {
+            0 => thread::available_parallelism().map_or(1, |count| count.get()),
+            _ => workers,
+        };
+
+        Self { workers, backlog }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_use_the_configured_workers() {
+        let settings = ServerSettings::new(3, 64);
+
+        assert_eq!(settings.workers, 3);
+        assert_eq!(settings.backlog, 64);
+    }
+
+    #[test]
+    fn should_use_one_worker_per_cpu_by_default() {
+        let settings = ServerSettings::new(0, 64);
+
+        let cpus = thread::available_parallelism().unwrap().get();
+        assert_eq!(settings.workers, cpus);
+    }
+}
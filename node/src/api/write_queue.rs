@@ -0,0 +1,139 @@
+use futures::channel::oneshot;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use thiserror::Error;
+
+type WriteJob = Box<dyn FnOnce() + Send>;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum WriteQueueError {
+    #[error("Too many pending writes, try again later")]
+    QueueFull,
+
+    #[error("The database writer has stopped")]
+    WriterStopped,
+}
+
+// Funnels the database writes of the API handlers to a dedicated writer thread,
+// so the async runtime never blocks waiting for the database write lock
+#[derive(Clone)]
+pub struct WriteQueue {
+    sender: SyncSender<WriteJob>,
+}
+
+impl WriteQueue {
+    // The queue holds up to "capacity" pending writes, the next ones are rejected until there is room
+    pub fn new(capacity: usize) -> (Self, WriteWorker) {
+        let (sender, receiver) = sync_channel(capacity.max(1));
+        (Self { sender }, WriteWorker { receiver })
+    }
+
+    // Enqueues the write and waits for its result, without waiting for room in the queue
+    pub async fn execute<T, F>(&self, write: F) -> Result<T, WriteQueueError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (result_sender, result_receiver) = oneshot::channel();
+        let job: WriteJob = Box::new(move || {
+            // the handler may be gone (e.g. the client disconnected), but the write is still done
+            let _ = result_sender.send(write());
+        });
+
+        match self.sender.try_send(job) {
+            Ok(_) => {}
+            Err(TrySendError::Full(_)) => return Err(WriteQueueError::QueueFull),
+            Err(TrySendError::Disconnected(_)) => return Err(WriteQueueError::WriterStopped),
+        }
+
+        result_receiver
+            .await
+            .map_err(|_| WriteQueueError::WriterStopped)
+    }
+}
+
+pub struct WriteWorker {
+    receiver: Receiver<WriteJob>,
+}
+
+impl WriteWorker {
+    // Runs the writes one at a time, in the order they were enqueued,
+    // until every queue handle is dropped (i.e. the server stopped)
+    pub fn run(self) {
+        for job in self.receiver {
+            job();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{executor::block_on, poll};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn should_run_concurrent_writes_one_at_a_time() {
+        let (queue, worker) = WriteQueue::new(100);
+        let writer = thread::spawn(move || worker.run());
+
+        let running = Arc::new(AtomicUsize::new(0));
+        let written = Arc::new(Mutex::new(Vec::new()));
+
+        let handles: Vec<_> = (0..8)
+            .map(|value| {
+                let queue = queue.clone();
+                let running = running.clone();
+                let written = written.clone();
+                thread::spawn(move || {
+                    block_on(queue.execute(move || {
+                        // no other write can be running at the same time
+                        assert_eq!(running.fetch_add(1, Ordering::SeqCst), 0);
+                        thread::sleep(Duration::from_millis(5));
+                        written.lock().unwrap().push(value);
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        value * 2
+                    }))
+                })
+            })
+            .collect();
+
+        // each submitter receives the result of its own write
+        for (value, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().unwrap(), Ok(value * 2));
+        }
+
+        let mut written = written.lock().unwrap().clone();
+        written.sort();
+        assert_eq!(written, (0..8).collect::<Vec<_>>());
+
+        // the writer stops once all the queue handles are dropped
+        drop(queue);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn should_reject_writes_when_the_queue_is_full() {
+        let (queue, worker) = WriteQueue::new(1);
+
+        block_on(async {
+            // without a running writer, the first write waits in the queue
+            let mut pending_write = Box::pin(queue.execute(|| 1));
+            assert!(poll!(&mut pending_write).is_pending());
+
+            // so the next one is rejected right away
+            assert_eq!(queue.execute(|| 2).await, Err(WriteQueueError::QueueFull));
+
+            // and the pending write completes once the writer runs
+            thread::spawn(move || worker.run());
+            assert_eq!(pending_write.await, Ok(1));
+        });
+    }
+}
@@ -1,17 +1,254 @@
 mod accounts;
 mod blocks;
+mod export;
+mod forks;
 mod mempool;
 
-use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::{
+    collections::HashSet,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, RwLock, RwLockReadGuard,
+    },
+};
 
 use anyhow::Result;
-use spec::types::{Address, Block, Coin, Network, Transaction};
-use spec::validators::{validate_block, validate_transaction};
+use chrono::Utc;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use serde::{Deserialize, Serialize};
+use spec::types::{
+    deserialize_coin, hash::ConsensusHash, Address, Block, Coin, Network, Transaction,
+};
+use spec::validators::{
+    calculate_block_weight, calculate_total_supply, calculate_work, get_difficulty_at,
+    get_next_difficulty, validate_block, validate_block_hash, validate_pow, validate_transaction,
+    ChainError,
+};
 use spec::Database as SpecDatabase;
+use thiserror::Error;
+use utoipa::ToSchema;
 
-use self::accounts::AccountDatabase;
+use crate::util::{
+    config::{Config, MempoolConflictPolicy},
+    shutdown::Shutdownable,
+};
+
+use self::accounts::{AccountDatabase, AccountDatabaseError};
 use self::blocks::BlockDatabase;
-use self::mempool::Mempool;
+pub use self::export::{ChainExport, ChainExportError};
+use self::forks::ForkDatabase;
+use self::mempool::{Mempool, DEFAULT_MAX_MEMPOOL_BYTES};
+
+#[derive(Error, PartialEq, Eq, Debug)]
+pub enum NodeDatabaseError {
+    #[error("Block already exists")]
+    BlockAlreadyExists,
+
+    #[error("Genesis block does not match the local one, the networks are incompatible")]
+    GenesisMismatch,
+
+    #[error("Transaction already exists")]
+    TransactionAlreadyExists,
+
+    #[error("Transaction already confirmed in block {block_index}")]
+    TransactionAlreadyConfirmed { block_index: u64 },
+
+    #[error("Block index is too far beyond the tip")]
+    BlockTooFarInFuture,
+
+    #[error("The chain diverged from the local one")]
+    ChainDiverged,
+
+    #[error("Account balances do not match the blocks")]
+    BalancesMismatch,
+
+    #[error("Invalid transaction {transaction_index} in block {block_index}: {source}")]
+    InvalidTransaction {
+        block_index: u64,
+        transaction_index: usize,
+        source: AccountDatabaseError,
+    },
+}
+
+// Transactions that are pending or recently confirmed, so submitting them again has no effect
+pub fn is_already_known_transaction(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref(),
+        Some(
+            NodeDatabaseError::TransactionAlreadyExists
+                | NodeDatabaseError::TransactionAlreadyConfirmed { .. }
+        )
+    )
+}
+
+// Settings of the node database that are local to each node, unlike the network consensus rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatabaseOptions {
+    pub mempool_conflict_policy: MempoolConflictPolicy,
+    pub max_mempool_bytes: u64,
+    // blocks whose index exceeds the tip by more than this are not even tracked as orphans
+    pub max_future_blocks: u64,
+    // transactions confirmed in this number of latest blocks are rejected when submitted again
+    pub dedup_depth: u64,
+}
+
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        DatabaseOptions {
+            mempool_conflict_policy: MempoolConflictPolicy::default(),
+            max_mempool_bytes: DEFAULT_MAX_MEMPOOL_BYTES,
+            max_future_blocks: 100,
+            dedup_depth: 100,
+        }
+    }
+}
+
+impl From<&Config> for DatabaseOptions {
+    fn from(config: &Config) -> Self {
+        DatabaseOptions {
+            mempool_conflict_policy: config.mempool_conflict_policy,
+            max_mempool_bytes: config.max_mempool_bytes,
+            max_future_blocks: config.max_future_blocks,
+            dedup_depth: config.dedup_depth,
+        }
+    }
+}
+
+// Summary of a block that just became the tip of the chain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TipHeader {
+    pub index: u64,
+    pub timestamp: i64,
+    pub previous_hash: ConsensusHash,
+    pub hash: ConsensusHash,
+}
+
+impl From<&Block> for TipHeader {
+    fn from(block: &Block) -> Self {
+        TipHeader {
+            index: block.index,
+            timestamp: block.timestamp,
+            previous_hash: block.previous_hash.clone(),
+            hash: block.hash.clone(),
+        }
+    }
+}
+
+// Header of the tip of the main chain, so peers can tell if they are behind without downloading blocks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ChainTip {
+    pub index: u64,
+    #[schema(value_type = String)]
+    pub hash: ConsensusHash,
+    pub total_work: u128,
+}
+
+// Hashes of our chain, so a peer can send the blocks after the last one in common (see `get_locator`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct BlockLocator {
+    #[schema(value_type = Vec<String>)]
+    pub hashes: Vec<ConsensusHash>,
+    // maximum number of blocks to return
+    pub limit: Option<u64>,
+}
+
+// The tip of a known branch of the blockchain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ForkTip {
+    #[schema(value_type = String)]
+    pub hash: ConsensusHash,
+    // index of the tip block of the branch
+    pub height: u64,
+    pub total_work: u128,
+    pub main: bool,
+}
+
+// Which transactions of an address are included in its history
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryDirection {
+    Sent,
+    Received,
+    #[default]
+    All,
+}
+
+// A transaction sent or received by an address, along with the block that includes it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct HistoryEntry {
+    pub block_index: u64,
+    #[schema(value_type = String)]
+    pub transaction_id: ConsensusHash,
+    pub transaction: Transaction,
+}
+
+// Where a transaction is, as far as this node knows
+// Transactions that were never received or were dropped from the pool are unknown
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum TransactionStatus {
+    Pending,
+    Confirmed {
+        block_index: u64,
+        #[schema(value_type = String)]
+        block_hash: ConsensusHash,
+    },
+    Unknown,
+}
+
+// Number of recent blocks averaged to report how full the blocks are
+const UTILIZATION_WINDOW: u64 = 100;
+
+// Summary of the current state of the chain, for monitoring
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ChainStats {
+    // index of the tip block, as everywhere else (0 for an empty chain as well as for only the genesis block)
+    pub height: u64,
+    #[schema(value_type = Option<String>)]
+    pub tip_hash: Option<ConsensusHash>,
+    // difficulty required for the next block
+    pub difficulty: u32,
+    pub mempool_size: usize,
+    #[serde(deserialize_with = "deserialize_coin")]
+    pub total_supply: Coin,
+    pub utilization: Utilization,
+}
+
+// How full the recent blocks and the mempool are compared to their limits, to tune them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Utilization {
+    // number of blocks averaged, the most recent ones
+    pub recent_blocks: usize,
+    pub average_block_weight: f64,
+    // 0 when the blocks have no weight limit
+    pub max_block_weight: u64,
+    // average fraction of the weight limit used by the recent blocks, if there is a limit
+    pub average_block_fullness: Option<f64>,
+    pub mempool_bytes: u64,
+    pub max_mempool_bytes: u64,
+}
+
+// Result of validating the whole chain again, from the genesis block up to the tip
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ChainVerificationReport {
+    pub ok: bool,
+    // index of the last block that was valid, see `get_height`
+    pub height: u64,
+    // index of the first invalid block, if any (the balances may still mismatch when all the blocks are valid)
+    pub failed_index: Option<u64>,
+    pub first_error: Option<String>,
+}
+
+impl ChainVerificationReport {
+    fn new(height: u64, failed_index: Option<u64>, first_error: Option<anyhow::Error>) -> Self {
+        ChainVerificationReport {
+            ok: first_error.is_none(),
+            height,
+            failed_index,
+            first_error: first_error.map(|error| error.to_string()),
+        }
+    }
+}
 
 // by only exporting this struct, the caller does not need to worry about concurrency implementation
 // as concurrent atomic writes from different threads are supported
@@ -19,8 +256,8 @@ use self::mempool::Mempool;
 pub struct ConcurrentNodeDatabase(Arc<RwLock<NodeDatabase>>);
 
 impl ConcurrentNodeDatabase {
-    pub fn new(network: Network) -> Self {
-        let database = NodeDatabase::new(network);
+    pub fn new(network: Network, options: DatabaseOptions) -> Self {
+        let database = NodeDatabase::new(network, options);
         let arc_rwlock_database = Arc::new(RwLock::new(database));
 
         Self(arc_rwlock_database)
@@ -30,15 +267,153 @@ impl ConcurrentNodeDatabase {
         self.0.write().unwrap().append_block(block)
     }
 
+    // Check if a block would be appended, without modifying the database
+    pub fn check_block(&self, block: &Block) -> Result<()> {
+        self.get_read_lock().check_block(block)?;
+
+        Ok(())
+    }
+
+    // Check if a transaction would be added to the mempool, without modifying the database
+    pub fn check_transaction(&self, transaction: &Transaction) -> Result<()> {
+        self.get_read_lock().check_admission(transaction)
+    }
+
+    pub fn check_genesis(&self, genesis: &Block) -> Result<(), NodeDatabaseError> {
+        self.get_read_lock().check_genesis(genesis)
+    }
+
+    // Returns the balances of the involved accounts after the transactions, without modifying the database
+    pub fn simulate_transactions(
+        &self,
+        transactions: &[Transaction],
+    ) -> Result<Vec<(Address, Coin)>> {
+        self.get_read_lock().simulate_transactions(transactions)
+    }
+
+    // Returns a channel that receives the new tip every time a block is added to the blockchain,
+    // so any subsystem (API, peers, miners...) can react to it without polling
+    pub fn subscribe_tip_changes(&self) -> Receiver<TipHeader> {
+        let (sender, receiver) = mpsc::channel();
+        self.0.write().unwrap().tip_listeners.push(sender);
+
+        receiver
+    }
+
+    // Returns a channel notified every time the next block template changes,
+    // i.e. when the tip changes or the transactions of the mempool change
+    // The channel can be awaited, so the API does not need to block a thread for each subscriber
+    pub fn subscribe_template_changes(&self) -> UnboundedReceiver<()> {
+        let (sender, receiver) = unbounded();
+        self.0.write().unwrap().template_listeners.push(sender);
+
+        receiver
+    }
+
     pub fn add_mempool_transaction(&self, transaction: Transaction) -> Result<()> {
-        self.0.write().unwrap().add_mempool_transaction(transaction)
+        self.0.write().unwrap().validate_and_admit(transaction)
+    }
+
+    pub fn get_chain_stats(&self) -> ChainStats {
+        self.get_read_lock().get_chain_stats()
     }
 
-    fn get_read_lock(&self) -> RwLockReadGuard<NodeDatabase> {
+    // Cheaper than listing the mempool transactions, as they are not cloned
+    pub fn get_mempool_transaction_count(&self) -> usize {
+        self.get_read_lock().mempool.get_transaction_count()
+    }
+
+    pub fn remove_mempool_transaction(&self, id: &ConsensusHash) -> bool {
+        self.0.write().unwrap().remove_mempool_transaction(id)
+    }
+
+    // Drop the mempool transactions already confirmed in the latest `depth` blocks, returning how many
+    pub fn reconcile_mempool(&self, depth: u64) -> usize {
+        self.0.write().unwrap().reconcile_mempool(depth)
+    }
+
+    pub fn get_chain_tip(&self) -> Option<ChainTip> {
+        self.get_read_lock().get_chain_tip()
+    }
+
+    pub fn get_fork_tips(&self) -> Vec<ForkTip> {
+        self.get_read_lock().get_fork_tips()
+    }
+
+    pub fn get_block_locator(&self) -> Vec<ConsensusHash> {
+        self.get_read_lock().block_db.get_locator()
+    }
+
+    // Returns the blocks that a peer with the locator is missing, starting after the last block in common
+    pub fn get_blocks_after_locator(&self, locator: &[ConsensusHash], limit: u64) -> Vec<Block> {
+        self.get_read_lock()
+            .block_db
+            .get_blocks_after_locator(locator, limit)
+    }
+
+    pub fn export_chain(&self) -> ChainExport {
+        self.get_read_lock().export_chain()
+    }
+
+    // This is expensive, as every block is validated and its transactions processed again
+    pub fn verify_chain(&self) -> ChainVerificationReport {
+        self.get_read_lock().verify_chain()
+    }
+
+    // This is expensive, as every block is validated and its transactions processed again
+    pub fn import_chain(&self, export: ChainExport) -> Result<()> {
+        self.0.write().unwrap().import_chain(export)
+    }
+
+    pub fn get_orphan_blocks(&self) -> Vec<Block> {
+        self.get_read_lock().fork_db.get_orphans()
+    }
+
+    pub fn is_side_block(&self, hash: &ConsensusHash) -> bool {
+        self.get_read_lock().fork_db.get_side_block(hash).is_some()
+    }
+
+    pub fn get_transaction_status(&self, id: &ConsensusHash) -> TransactionStatus {
+        self.get_read_lock().get_transaction_status(id)
+    }
+
+    // Returns a page of the transactions in the chain sent and/or received by an address, oldest first
+    pub fn get_address_history(
+        &self,
+        address: &Address,
+        direction: HistoryDirection,
+        from: u64,
+        limit: u64,
+    ) -> Vec<HistoryEntry> {
+        self.get_read_lock()
+            .block_db
+            .get_address_history(address, direction, from, limit)
+    }
+
+    fn get_read_lock(&self) -> RwLockReadGuard<'_, NodeDatabase> {
         self.0.read().unwrap()
     }
 }
 
+// The chain and the mempool are only kept in memory, so there is nothing to flush yet,
+// but the pending transactions that are lost with the node are reported
+impl Shutdownable for ConcurrentNodeDatabase {
+    fn name(&self) -> &str {
+        "database"
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        let database = self.get_read_lock();
+        info!(
+            "Database stopped at height {}, discarding {} pending transactions",
+            database.get_height(),
+            database.mempool.get_transaction_count()
+        );
+
+        Ok(())
+    }
+}
+
 impl SpecDatabase for ConcurrentNodeDatabase {
     fn get_network(&self) -> Network {
         self.get_read_lock().get_network()
@@ -48,10 +423,22 @@ impl SpecDatabase for ConcurrentNodeDatabase {
         self.get_read_lock().get_all_blocks()
     }
 
+    fn get_block_by_index(&self, index: u64) -> Option<Block> {
+        self.get_read_lock().get_block_by_index(index)
+    }
+
+    fn get_blocks_range(&self, from: u64, limit: u64) -> Vec<Block> {
+        self.get_read_lock().get_blocks_range(from, limit)
+    }
+
     fn get_tip_block(&self) -> Option<Block> {
         self.get_read_lock().get_tip_block()
     }
 
+    fn get_block_count(&self) -> u64 {
+        self.get_read_lock().get_block_count()
+    }
+
     fn get_account_balance(&self, address: &Address) -> Option<Coin> {
         self.get_read_lock().get_account_balance(address)
     }
@@ -59,70 +446,475 @@ impl SpecDatabase for ConcurrentNodeDatabase {
     fn get_mempool_transactions(&self) -> Vec<Transaction> {
         self.get_read_lock().get_mempool_transactions()
     }
+
+    fn get_template_timestamp(&self) -> i64 {
+        self.get_read_lock().get_template_timestamp()
+    }
+
+    fn get_block_difficulty(&self, index: u64) -> Option<u32> {
+        self.get_read_lock().get_block_difficulty(index)
+    }
 }
 
 // The non-concurrent implementation of the database is not exported
 #[derive(Clone)]
 struct NodeDatabase {
     network: Network,
+    max_future_blocks: u64,
+    dedup_depth: u64,
     block_db: BlockDatabase,
     account_db: AccountDatabase,
+    fork_db: ForkDatabase,
     mempool: Mempool,
+    // fixed until the tip changes, so templates only change along with the chain or the mempool
+    template_timestamp: i64,
+    tip_listeners: Vec<Sender<TipHeader>>,
+    template_listeners: Vec<UnboundedSender<()>>,
 }
 
 impl NodeDatabase {
-    pub fn new(network: Network) -> Self {
+    pub fn new(network: Network, options: DatabaseOptions) -> Self {
         Self {
             network,
+            max_future_blocks: options.max_future_blocks,
+            dedup_depth: options.dedup_depth,
             block_db: BlockDatabase::default(),
             account_db: AccountDatabase::default(),
-            mempool: Mempool::default(),
+            fork_db: ForkDatabase::default(),
+            mempool: Mempool::new(options.mempool_conflict_policy, options.max_mempool_bytes),
+            template_timestamp: Utc::now().timestamp_millis(),
+            tip_listeners: vec![],
+            template_listeners: vec![],
         }
     }
 
     pub fn append_block(&mut self, block: &Block) -> Result<()> {
-        // make sure the block is valid before any other operation
-        validate_block(self, block)?;
+        let account_db = match self.check_block(block) {
+            Ok(value) => value,
+            Err(error) => {
+                self.track_fork_block(block, &error);
+                return Err(error);
+            }
+        };
 
         // append the new block to the end of the chain
-        self.block_db.append_block(block.clone());
-
-        // update account balances
-        Self::process_transactions(&mut self.account_db, block)?;
+        let difficulty = get_difficulty_at(self, block.index);
+        self.block_db.append_block(block.clone(), difficulty);
+        self.account_db = account_db;
 
         // submitted transactions should be removed from the mempool
         self.mempool.remove_transactions(&block.transactions);
 
+        self.notify_tip_change(block);
+
+        Ok(())
+    }
+
+    // Validate a block, returning the account balances after applying its transactions
+    fn check_block(&self, block: &Block) -> Result<AccountDatabase> {
+        // blocks we already have are not invalid, so the caller may want to handle them differently
+        if self.block_db.contains_block(block) {
+            return Err(NodeDatabaseError::BlockAlreadyExists.into());
+        }
+
+        // blocks far ahead of our tip are rejected before they can fill the orphan storage
+        let max_index = self
+            .get_block_count()
+            .saturating_add(self.max_future_blocks);
+        if block.index >= max_index {
+            return Err(NodeDatabaseError::BlockTooFarInFuture.into());
+        }
+
+        // a different genesis means that the block comes from another network,
+        // so the rest of the validations would only report confusing errors
+        if block.index == 0 {
+            self.check_genesis(block)?;
+        }
+
+        // make sure the block is valid before any other operation
+        validate_block(self, block)?;
+
+        // update account balances on a copy, so nothing changes if any transaction can not be applied
+        let mut account_db = self.account_db.clone();
+        Self::process_transactions(&mut account_db, block)?;
+        if block.index == 0 {
+            self.process_premine(&mut account_db)?;
+        }
+
+        Ok(account_db)
+    }
+
+    // Listeners that were dropped are not notified anymore
+    fn notify_tip_change(&mut self, tip_block: &Block) {
+        // the next block can not be older than the tip, even if our clock is behind
+        self.template_timestamp = Utc::now().timestamp_millis().max(tip_block.timestamp);
+
+        let tip_header = TipHeader::from(tip_block);
+        self.tip_listeners
+            .retain(|listener| listener.send(tip_header.clone()).is_ok());
+        self.notify_template_change();
+    }
+
+    fn notify_template_change(&mut self) {
+        self.template_listeners
+            .retain(|listener| listener.unbounded_send(()).is_ok());
+    }
+
+    // Blocks that do not follow our tip may belong to a competing branch, or be waiting for their parent
+    fn track_fork_block(&mut self, block: &Block, error: &anyhow::Error) {
+        let is_out_of_chain = matches!(
+            error.downcast_ref(),
+            Some(ChainError::InvalidIndex | ChainError::InvalidPreviousHash)
+        );
+        if !is_out_of_chain || block.index == 0 {
+            return;
+        }
+
+        // only blocks with a valid proof of work are tracked, to avoid spam
+        let difficulty = get_difficulty_at(self, block.index);
+        let pow_algorithm = self.network.pow_algorithm;
+        if validate_block_hash(block).is_err()
+            || validate_pow(pow_algorithm, difficulty, block).is_err()
+        {
+            return;
+        }
+
+        match self.get_known_parent(block) {
+            Some(_) => self.fork_db.add_side_block(block.clone()),
+            None => self.fork_db.add_orphan(block.clone()),
+        }
+    }
+
+    // Returns the parent of a block, if it is in the main chain or in a competing branch
+    fn get_known_parent(&self, block: &Block) -> Option<Block> {
+        let parent_index = block.index.checked_sub(1)?;
+
+        let main_parent = self.block_db.get_block_by_index(parent_index);
+        let parent = match main_parent {
+            Some(parent) if parent.hash == block.previous_hash => Some(parent),
+            _ => self.fork_db.get_side_block(&block.previous_hash),
+        };
+
+        parent.filter(|parent| parent.index == parent_index)
+    }
+
+    fn get_chain_tip(&self) -> Option<ChainTip> {
+        let tip_block = self.block_db.get_tip_block()?;
+
+        Some(ChainTip {
+            index: tip_block.index,
+            total_work: self.calculate_chain_work(tip_block.index),
+            hash: tip_block.hash,
+        })
+    }
+
+    // Returns the tip of the main chain, along with the tips of all the competing branches
+    fn get_fork_tips(&self) -> Vec<ForkTip> {
+        let mut fork_tips = vec![];
+
+        if let Some(tip_block) = self.block_db.get_tip_block() {
+            fork_tips.push(ForkTip {
+                hash: tip_block.hash.clone(),
+                height: tip_block.index,
+                total_work: self.calculate_chain_work(tip_block.index),
+                main: true,
+            });
+        }
+
+        let side_blocks = self.fork_db.get_side_blocks();
+        let branch_tips = side_blocks
+            .iter()
+            .filter(|block| !side_blocks.iter().any(|b| b.previous_hash == block.hash));
+
+        for branch_tip in branch_tips {
+            // walk back the branch until reaching the main chain
+            let mut branch_work = 0_u128;
+            let mut current_block = Some(branch_tip.clone());
+            while let Some(block) = current_block {
+                if self.block_db.contains_block(&block) {
+                    fork_tips.push(ForkTip {
+                        hash: branch_tip.hash.clone(),
+                        height: branch_tip.index,
+                        total_work: branch_work
+                            .saturating_add(self.calculate_chain_work(block.index)),
+                        main: false,
+                    });
+                    break;
+                }
+
+                let difficulty = get_difficulty_at(self, block.index);
+                branch_work = branch_work.saturating_add(calculate_work(difficulty));
+                current_block = self.get_known_parent(&block);
+            }
+        }
+
+        fork_tips
+    }
+
+    // Total expected work to mine all the blocks of the main chain up to the indicated index
+    fn calculate_chain_work(&self, last_index: u64) -> u128 {
+        self.block_db.get_chain_work(last_index).unwrap_or_default()
+    }
+
+    // Replay all the blocks on an empty database, checking that they lead to the same balances
+    fn verify_chain(&self) -> ChainVerificationReport {
+        let options = DatabaseOptions {
+            max_future_blocks: self.max_future_blocks,
+            ..DatabaseOptions::default()
+        };
+        let mut replayed_database = NodeDatabase::new(self.network.clone(), options);
+
+        for block in self.block_db.get_all_blocks() {
+            if let Err(error) = replayed_database.append_block(&block) {
+                return ChainVerificationReport::new(
+                    replayed_database.get_height(),
+                    Some(block.index),
+                    Some(error),
+                );
+            }
+        }
+
+        let height = replayed_database.get_height();
+        if replayed_database.account_db != self.account_db {
+            let error = NodeDatabaseError::BalancesMismatch.into();
+            return ChainVerificationReport::new(height, None, Some(error));
+        }
+
+        ChainVerificationReport::new(height, None, None)
+    }
+
+    fn export_chain(&self) -> ChainExport {
+        ChainExport::new(self.block_db.get_all_blocks())
+    }
+
+    // The blocks of the export are not trusted, the balances result from processing them again
+    fn import_chain(&mut self, export: ChainExport) -> Result<()> {
+        if self.block_db.get_block_count() > 0 {
+            return Err(ChainExportError::DatabaseNotEmpty.into());
+        }
+
+        // the blocks are validated (linkage, hashes, proof of work and transactions) on a copy,
+        // so nothing changes if any of them is invalid
+        let mut imported = self.clone();
+        for block in export.blocks {
+            imported.account_db = imported.check_block(&block)?;
+            let difficulty = get_difficulty_at(&imported, block.index);
+            imported.block_db.append_block(block, difficulty);
+        }
+
+        self.block_db = imported.block_db;
+        self.account_db = imported.account_db;
+
+        if let Some(tip_block) = self.block_db.get_tip_block() {
+            self.notify_tip_change(&tip_block);
+        }
+
         Ok(())
     }
 
-    fn process_transactions(account_db: &mut AccountDatabase, block: &Block) -> Result<()> {
-        // we know that at this point the block was already fully validated
-        // so we just update the balances without further validation
-        let mut transactions = block.transactions.iter();
+    // Check that a genesis block is the same as ours, if we already have one
+    pub fn check_genesis(&self, genesis: &Block) -> Result<(), NodeDatabaseError> {
+        match self.block_db.get_block_by_index(0) {
+            Some(local_genesis) if local_genesis.hash != genesis.hash => {
+                Err(NodeDatabaseError::GenesisMismatch)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn get_chain_stats(&self) -> ChainStats {
+        ChainStats {
+            height: self.get_height(),
+            tip_hash: self.block_db.get_tip_block().map(|block| block.hash),
+            difficulty: get_next_difficulty(self),
+            mempool_size: self.mempool.get_transaction_count(),
+            total_supply: calculate_total_supply(self),
+            utilization: self.get_utilization(),
+        }
+    }
+
+    fn get_utilization(&self) -> Utilization {
+        let from = self.get_block_count().saturating_sub(UTILIZATION_WINDOW);
+        let recent_blocks = self.block_db.get_blocks_range(from, UTILIZATION_WINDOW);
+        let total_weight: u64 = recent_blocks
+            .iter()
+            .map(|block| calculate_block_weight(&block.transactions))
+            .sum();
+        let average_block_weight = match recent_blocks.len() {
+            0 => 0.0,
+            num_blocks => total_weight as f64 / num_blocks as f64,
+        };
+
+        let max_block_weight = self.network.max_block_weight;
+        Utilization {
+            recent_blocks: recent_blocks.len(),
+            average_block_weight,
+            max_block_weight,
+            average_block_fullness: (max_block_weight > 0)
+                .then(|| average_block_weight / max_block_weight as f64),
+            mempool_bytes: self.mempool.get_total_bytes(),
+            max_mempool_bytes: self.mempool.get_max_bytes(),
+        }
+    }
+
+    // Confirmed transactions are looked up first, as they may still be in the pool of other nodes
+    fn get_transaction_status(&self, id: &ConsensusHash) -> TransactionStatus {
+        if let Some(block) = self.block_db.get_transaction_block(id) {
+            return TransactionStatus::Confirmed {
+                block_index: block.index,
+                block_hash: block.hash.clone(),
+            };
+        }
+
+        if self.mempool.contains_transaction(id) {
+            return TransactionStatus::Pending;
+        }
 
-        // process the coinbase transaction, rewarding the miner
-        let coinbase = transactions.next().unwrap();
-        account_db.add_funds(&coinbase.recipient, coinbase.amount);
+        TransactionStatus::Unknown
+    }
+
+    // The premine is part of the network definition, which the genesis block commits to
+    fn process_premine(
+        &self,
+        account_db: &mut AccountDatabase,
+    ) -> Result<(), AccountDatabaseError> {
+        for allocation in &self.network.premine {
+            account_db.add_funds(&allocation.address, allocation.amount)?;
+        }
+
+        Ok(())
+    }
+
+    fn process_transactions(
+        account_db: &mut AccountDatabase,
+        block: &Block,
+    ) -> Result<(), NodeDatabaseError> {
+        // the block was already validated, but transactions are validated against the balances
+        // before the block, so we still need to make sure that no balance underflows or overflows
+        for (transaction_index, transaction) in block.transactions.iter().enumerate() {
+            let result = match transaction_index {
+                // process the coinbase transaction, rewarding the miner
+                0 => account_db.add_funds(&transaction.recipient, transaction.amount),
+                // process transfers between accounts
+                _ => account_db.transfer(
+                    &transaction.sender,
+                    &transaction.recipient,
+                    transaction.amount,
+                ),
+            };
+
+            result.map_err(|source| NodeDatabaseError::InvalidTransaction {
+                block_index: block.index,
+                transaction_index,
+                source,
+            })?;
+        }
+
+        Ok(())
+    }
 
-        // process transfers between accounts
+    // Transactions are applied in order to a copy of the balances
+    // Like in blocks, each one is validated against the current state and then it must not overspend
+    // the balances left by the previous ones
+    fn simulate_transactions(&self, transactions: &[Transaction]) -> Result<Vec<(Address, Coin)>> {
+        let mut account_db = self.account_db.clone();
+        // the addresses are returned in the order they first appear, the set only avoids duplicates
+        let mut addresses: Vec<Address> = vec![];
+        let mut seen_addresses: HashSet<Address> = HashSet::new();
         for transaction in transactions {
+            validate_transaction(self, transaction)?;
             account_db.transfer(
                 &transaction.sender,
                 &transaction.recipient,
                 transaction.amount,
             )?;
+
+            for address in [&transaction.sender, &transaction.recipient] {
+                if seen_addresses.insert(address.clone()) {
+                    addresses.push(address.clone());
+                }
+            }
         }
 
+        let balances = addresses
+            .into_iter()
+            .map(|address| {
+                let balance = account_db.get_account_balance(&address).unwrap_or_default();
+                (address, balance)
+            })
+            .collect();
+
+        Ok(balances)
+    }
+
+    // Every transaction entering the mempool goes through here, whether it was submitted to the API
+    // or relayed by a peer (over HTTP or TCP), so the same rules apply regardless of its origin
+    pub fn validate_and_admit(&mut self, transaction: Transaction) -> Result<()> {
+        let sender_balance = self.validate_admission(&transaction)?;
+        self.mempool.add_transaction(transaction, sender_balance)?;
+        self.notify_template_change();
+
         Ok(())
     }
 
-    pub fn add_mempool_transaction(&mut self, transaction: Transaction) -> Result<()> {
-        validate_transaction(self, &transaction)?;
-        self.mempool.add_transaction(transaction);
+    fn remove_mempool_transaction(&mut self, id: &ConsensusHash) -> bool {
+        let is_removed = self.mempool.remove_transaction_by_id(id);
+        if is_removed {
+            self.notify_template_change();
+        }
+
+        is_removed
+    }
+
+    // Appending a block only removes its own transactions from the mempool, so a transaction that
+    // was admitted again after being confirmed (e.g. relayed late by a peer) would stay pending forever
+    fn reconcile_mempool(&mut self, depth: u64) -> usize {
+        let confirmed_ids = self.block_db.get_recent_transaction_ids(depth);
+        let num_removed = self.mempool.remove_transactions_by_id(&confirmed_ids);
+        if num_removed > 0 {
+            info!(
+                "Removed {} confirmed transactions from the mempool",
+                num_removed
+            );
+            self.notify_template_change();
+        }
+
+        num_removed
+    }
+
+    // Same rules as "validate_and_admit", without modifying the mempool
+    fn check_admission(&self, transaction: &Transaction) -> Result<()> {
+        let sender_balance = self.validate_admission(transaction)?;
+        self.mempool
+            .check_transaction(transaction, sender_balance)?;
 
         Ok(())
     }
+
+    // Returns the spendable balance of the sender, needed by the mempool to admit the transaction
+    fn validate_admission(&self, transaction: &Transaction) -> Result<Coin> {
+        // transactions are relayed between peers, so we may receive the same one many times
+        if self.mempool.contains_transaction(&transaction.id()) {
+            return Err(NodeDatabaseError::TransactionAlreadyExists.into());
+        }
+
+        // transactions have no nonce, so an identical transfer can only be sent again after a while
+        let confirmed_block = self
+            .block_db
+            .get_recent_transaction_block(&transaction.id(), self.dedup_depth);
+        if let Some(block_index) = confirmed_block {
+            return Err(NodeDatabaseError::TransactionAlreadyConfirmed { block_index }.into());
+        }
+
+        validate_transaction(self, transaction)?;
+
+        // the transaction was validated, so the sender account exists
+        let sender_balance = self
+            .get_spendable_balance(&transaction.sender, self.get_block_count())
+            .unwrap_or_default();
+
+        Ok(sender_balance)
+    }
 }
 
 // this trait is necessary for the spec validators to run
@@ -135,10 +927,22 @@ impl SpecDatabase for NodeDatabase {
         self.block_db.get_all_blocks()
     }
 
+    fn get_block_by_index(&self, index: u64) -> Option<Block> {
+        self.block_db.get_block_by_index(index)
+    }
+
+    fn get_blocks_range(&self, from: u64, limit: u64) -> Vec<Block> {
+        self.block_db.get_blocks_range(from, limit)
+    }
+
     fn get_tip_block(&self) -> Option<Block> {
         self.block_db.get_tip_block()
     }
 
+    fn get_block_count(&self) -> u64 {
+        self.block_db.get_block_count()
+    }
+
     fn get_account_balance(&self, address: &Address) -> Option<Coin> {
         self.account_db.get_account_balance(address)
     }
@@ -146,4 +950,851 @@ impl SpecDatabase for NodeDatabase {
     fn get_mempool_transactions(&self) -> Vec<Transaction> {
         self.mempool.get_transactions()
     }
+
+    fn get_template_timestamp(&self) -> i64 {
+        self.template_timestamp
+    }
+
+    fn get_block_difficulty(&self, index: u64) -> Option<u32> {
+        self.block_db.get_difficulty(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use spec::{
+        types::{hash::ConsensusHashable, GenesisAllocation},
+        validators::{BlockError, GenesisError, TransactionError, BLOCK_SUBSIDY},
+    };
+
+    use super::*;
+
+    #[test]
+    fn should_get_block_by_index() {
+        let database = create_mock_database(3);
+
+        for index in 0..3 {
+            let block = database.get_block_by_index(index).unwrap();
+            assert_eq!(block.index, index);
+        }
+        assert!(database.get_block_by_index(3).is_none());
+        assert!(database.get_block_by_index(u64::MAX).is_none());
+    }
+
+    #[test]
+    fn should_get_blocks_range() {
+        let database = create_mock_database(5);
+
+        // a range fully inside the chain
+        let blocks = database.get_blocks_range(1, 3);
+        assert_eq!(get_indexes(&blocks), vec![1, 2, 3]);
+
+        // a range that goes beyond the tip is truncated
+        let blocks = database.get_blocks_range(3, 10);
+        assert_eq!(get_indexes(&blocks), vec![3, 4]);
+
+        // a range that starts after the tip is empty
+        assert!(database.get_blocks_range(5, 10).is_empty());
+        assert!(database.get_blocks_range(u64::MAX, u64::MAX).is_empty());
+
+        // an empty limit returns no blocks
+        assert!(database.get_blocks_range(0, 0).is_empty());
+    }
+
+    #[test]
+    fn should_locate_the_blocks_after_the_last_one_in_common() {
+        let database = create_mock_database(20);
+        let blocks = database.get_all_blocks();
+        assert!(
+            ConcurrentNodeDatabase::new(create_mock_network(), DatabaseOptions::default())
+                .get_block_locator()
+                .is_empty()
+        );
+
+        // the locator starts dense at the tip, and always ends with the genesis
+        let locator = database.get_block_locator();
+        let expected_locator: Vec<_> = [19, 18, 17, 16, 15, 14, 13, 12, 11, 10, 8, 4, 0]
+            .iter()
+            .map(|&index| blocks[index].hash.clone())
+            .collect();
+        assert_eq!(locator, expected_locator);
+
+        // the blocks after the first known hash of the locator are returned
+        let other_locator = vec![ConsensusHash::digest(b"unknown"), blocks[12].hash.clone()];
+        let located_blocks = database.get_blocks_after_locator(&other_locator, 3);
+        assert_eq!(get_indexes(&located_blocks), vec![13, 14, 15]);
+
+        // without any block in common, the blocks start at the genesis
+        let located_blocks = database.get_blocks_after_locator(&[], 2);
+        assert_eq!(get_indexes(&located_blocks), vec![0, 1]);
+    }
+
+    #[test]
+    fn should_filter_the_history_of_an_address_by_direction() {
+        let (database, miner, alice) =
+            create_mock_database_with_transfers(DatabaseOptions::default());
+
+        // the miner received the genesis coinbase and sent both transfers
+        let history = database.get_address_history(&miner, HistoryDirection::Sent, 0, 10);
+        assert_eq!(get_history_amounts(&history), vec![20, 10]);
+        assert!(history.iter().all(|entry| entry.block_index == 1));
+
+        let history = database.get_address_history(&miner, HistoryDirection::Received, 0, 10);
+        assert_eq!(get_history_amounts(&history), vec![BLOCK_SUBSIDY]);
+        assert_eq!(history[0].block_index, 0);
+        assert_eq!(history[0].transaction_id, history[0].transaction.id());
+
+        // alice only received funds
+        let history = database.get_address_history(&alice, HistoryDirection::Received, 0, 10);
+        assert_eq!(get_history_amounts(&history), vec![BLOCK_SUBSIDY, 20, 10]);
+        assert!(database
+            .get_address_history(&alice, HistoryDirection::Sent, 0, 10)
+            .is_empty());
+    }
+
+    #[test]
+    fn should_get_the_history_of_an_address_in_pages() {
+        let (database, _, alice) = create_mock_database_with_transfers(DatabaseOptions::default());
+        let get_page = |from, limit| {
+            let history = database.get_address_history(&alice, HistoryDirection::All, from, limit);
+            get_history_amounts(&history)
+        };
+
+        assert_eq!(get_page(0, 2), vec![BLOCK_SUBSIDY, 20]);
+        assert_eq!(get_page(2, 2), vec![10]);
+
+        // pages beyond the history or without a limit are empty
+        assert!(get_page(3, 2).is_empty());
+        assert!(get_page(u64::MAX, u64::MAX).is_empty());
+        assert!(get_page(0, 0).is_empty());
+
+        // unknown addresses have no history
+        let history =
+            database.get_address_history(&Address::default(), HistoryDirection::Received, 0, 10);
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn should_get_the_status_of_transactions() {
+        let (database, miner, alice) =
+            create_mock_database_with_transfers(DatabaseOptions::default());
+
+        // a transfer included in the second block
+        let confirmed = Transaction::new(miner.clone(), alice.clone(), 10);
+        let tip_block = database.get_tip_block().unwrap();
+        assert_eq!(
+            database.get_transaction_status(&confirmed.id()),
+            TransactionStatus::Confirmed {
+                block_index: 1,
+                block_hash: tip_block.hash,
+            }
+        );
+
+        // a transfer in the pool
+        let pending = Transaction::new(miner, alice, 30);
+        database.add_mempool_transaction(pending.clone()).unwrap();
+        assert_eq!(
+            database.get_transaction_status(&pending.id()),
+            TransactionStatus::Pending
+        );
+
+        // a transaction the node never received
+        let unknown = pending.with_data(vec![1]);
+        assert_eq!(
+            database.get_transaction_status(&unknown.id()),
+            TransactionStatus::Unknown
+        );
+    }
+
+    #[test]
+    fn should_purge_confirmed_transactions_from_the_mempool() {
+        let options = DatabaseOptions {
+            dedup_depth: 1,
+            ..DatabaseOptions::default()
+        };
+        let (database, miner, alice) = create_mock_database_with_transfers(options);
+
+        // a transfer confirmed in the second block is received again after a third block
+        let tip_block = database.get_tip_block().unwrap();
+        let coinbase = Transaction::new_coinbase(alice.clone());
+        let block = Block::new(2, 0, tip_block.hash, vec![coinbase]);
+        database.append_block(&block).unwrap();
+
+        let confirmed = Transaction::new(miner.clone(), alice.clone(), 10);
+        let pending = Transaction::new(miner, alice, 30);
+        database.add_mempool_transaction(confirmed.clone()).unwrap();
+        database.add_mempool_transaction(pending.clone()).unwrap();
+
+        // it is only purged if its block is within the depth
+        assert_eq!(database.reconcile_mempool(1), 0);
+        assert_eq!(database.get_mempool_transaction_count(), 2);
+
+        assert_eq!(database.reconcile_mempool(10), 1);
+        assert_eq!(database.get_mempool_transactions(), vec![pending]);
+    }
+
+    #[test]
+    fn should_reject_transactions_submitted_again() {
+        let (database, miner, alice) =
+            create_mock_database_with_transfers(DatabaseOptions::default());
+
+        // a transaction that is still pending
+        let pending = Transaction::new(miner.clone(), alice.clone(), 30);
+        database.add_mempool_transaction(pending.clone()).unwrap();
+        let err = database.add_mempool_transaction(pending).unwrap_err();
+        let inner_err = err.downcast::<NodeDatabaseError>().unwrap();
+        assert_eq!(inner_err, NodeDatabaseError::TransactionAlreadyExists);
+
+        // a transaction confirmed in a recent block
+        let confirmed = Transaction::new(miner, alice, 10);
+        let err = database.add_mempool_transaction(confirmed).unwrap_err();
+        let inner_err = err.downcast::<NodeDatabaseError>().unwrap();
+        assert_eq!(
+            inner_err,
+            NodeDatabaseError::TransactionAlreadyConfirmed { block_index: 1 }
+        );
+        assert_eq!(database.get_mempool_transaction_count(), 1);
+    }
+
+    #[test]
+    fn should_accept_identical_transactions_confirmed_before_the_dedup_depth() {
+        let miner = miner_address();
+        let options = DatabaseOptions {
+            dedup_depth: 1,
+            ..DatabaseOptions::default()
+        };
+        let (database, genesis) = create_mock_database_with_genesis(options);
+
+        let transfer = Transaction::new(miner.clone(), Address::default(), 10);
+        let transactions = vec![Transaction::new_coinbase(miner.clone()), transfer.clone()];
+        let block = Block::new(1, 0, genesis.hash, transactions);
+        database.append_block(&block).unwrap();
+        assert!(database.add_mempool_transaction(transfer.clone()).is_err());
+
+        // once its block is beyond the depth, the same transfer can be sent again
+        let block = Block::new(2, 0, block.hash, vec![Transaction::new_coinbase(miner)]);
+        database.append_block(&block).unwrap();
+        database.add_mempool_transaction(transfer).unwrap();
+    }
+
+    #[test]
+    fn should_get_height() {
+        let database = create_mock_database(0);
+        assert_eq!(database.get_height(), 0);
+        assert_eq!(database.get_block_count(), 0);
+
+        // the height always follows the tip block
+        for num_blocks in 1..4 {
+            let database = create_mock_database(num_blocks);
+            let tip_block = database.get_tip_block().unwrap();
+            assert_eq!(database.get_height(), tip_block.index);
+            assert_eq!(database.get_block_count(), tip_block.index + 1);
+        }
+    }
+
+    #[test]
+    fn should_keep_the_difficulty_and_work_of_a_retargeted_chain() {
+        let network = Network {
+            retarget_window: 2,
+            ..create_mock_network()
+        };
+        let database = ConcurrentNodeDatabase::new(network.clone(), DatabaseOptions::default());
+
+        // blocks are mined much faster than the target, so the difficulty goes up on every window
+        let mut previous_hash = network.consensus_hash();
+        for index in 0..6 {
+            let difficulty = get_next_difficulty(&database);
+            let coinbase = Transaction::new_coinbase(Address::default());
+            let block = (0..)
+                .map(|nonce| {
+                    Block::new(index, nonce, previous_hash.clone(), vec![coinbase.clone()])
+                })
+                .find(|block| block.hash.leading_zeros() >= difficulty)
+                .unwrap();
+            database.append_block(&block).unwrap();
+            previous_hash = block.hash;
+        }
+
+        let difficulties: Vec<_> = (0..6)
+            .map(|index| database.get_block_difficulty(index).unwrap())
+            .collect();
+        assert_eq!(difficulties, vec![0, 0, 1, 1, 2, 2]);
+        let expected_work: u128 = difficulties.into_iter().map(calculate_work).sum();
+        assert_eq!(
+            database.get_read_lock().calculate_chain_work(5),
+            expected_work
+        );
+    }
+
+    #[test]
+    fn should_only_accept_a_valid_genesis_on_an_empty_database() {
+        let network = create_mock_network();
+        let database = ConcurrentNodeDatabase::new(network.clone(), DatabaseOptions::default());
+        let coinbase = Transaction::new_coinbase(Address::default());
+
+        // the first block must be a genesis block
+        let block = Block::new(1, 0, network.consensus_hash(), vec![coinbase.clone()]);
+        let err = database.append_block(&block).unwrap_err();
+        let inner_err = err.downcast::<ChainError>().unwrap();
+        assert_eq!(inner_err, ChainError::BlockchainIsEmpty);
+
+        // which must belong to our network
+        let other_network = Network {
+            description: "Other network".to_string(),
+            ..network.clone()
+        };
+        let block = Block::new(0, 0, other_network.consensus_hash(), vec![coinbase.clone()]);
+        let err = database.append_block(&block).unwrap_err();
+        let inner_err = err.downcast::<GenesisError>().unwrap();
+        assert_eq!(inner_err, GenesisError::MismatchedNetwork);
+        assert_eq!(database.get_block_count(), 0);
+
+        // a proper genesis block is accepted
+        let genesis = Block::new(0, 0, network.consensus_hash(), vec![coinbase]);
+        database.append_block(&genesis).unwrap();
+        assert_eq!(database.get_tip_block(), Some(genesis));
+    }
+
+    #[test]
+    fn should_reject_mismatched_genesis() {
+        let database = create_mock_database(1);
+
+        // build a genesis block that is valid but different from ours
+        let network = database.get_network();
+        let coinbase = Transaction::new(Address::default(), Address::default(), BLOCK_SUBSIDY)
+            .with_data(vec![1]);
+        let other_genesis = Block::new(0, 0, network.consensus_hash(), vec![coinbase]);
+
+        let err = database.append_block(&other_genesis).unwrap_err();
+        let inner_err = err.downcast::<NodeDatabaseError>().unwrap();
+        assert_eq!(inner_err, NodeDatabaseError::GenesisMismatch);
+    }
+
+    #[test]
+    fn should_fail_replay_when_a_balance_underflows() {
+        let miner = miner_address();
+        let alice = alice_address();
+        // the miner gets funds in the genesis block
+        let (database, genesis) = create_mock_database_with_genesis(DatabaseOptions::default());
+
+        // each transfer is valid on its own, but together they spend more than the miner balance
+        let transfer = Transaction::new(miner.clone(), alice.clone(), BLOCK_SUBSIDY);
+        let double_spend = transfer.clone().with_data(vec![1]);
+        let coinbase = Transaction::new_coinbase(alice.clone());
+        let mut transactions = vec![coinbase, transfer, double_spend];
+        transactions[1..].sort_by_key(|transaction| transaction.id());
+        let block = Block::new(1, 0, genesis.hash.clone(), transactions);
+
+        // replaying the chain stops at the second transfer of the block
+        let err = database.append_block(&block).unwrap_err();
+        let inner_err = err.downcast::<NodeDatabaseError>().unwrap();
+        assert_eq!(
+            inner_err,
+            NodeDatabaseError::InvalidTransaction {
+                block_index: 1,
+                transaction_index: 2,
+                source: AccountDatabaseError::InsufficientFunds,
+            }
+        );
+
+        // and the state is not modified by the offending block
+        assert_eq!(database.get_block_count(), 1);
+        assert_eq!(database.get_account_balance(&miner), Some(BLOCK_SUBSIDY));
+        assert_eq!(database.get_account_balance(&alice), None);
+    }
+
+    #[test]
+    fn should_reject_blocks_too_far_beyond_the_tip() {
+        let database = create_mock_database(2);
+        let coinbase = Transaction::new_coinbase(Address::default());
+
+        // a block a few positions ahead is kept as an orphan
+        let orphan_block = Block::new(5, 0, ConsensusHash::default(), vec![coinbase.clone()]);
+        database.append_block(&orphan_block).unwrap_err();
+        assert_eq!(database.get_orphan_blocks(), vec![orphan_block]);
+
+        // but a block far beyond the tip is rejected outright
+        let future_block = Block::new(1000, 0, ConsensusHash::default(), vec![coinbase]);
+        let err = database.append_block(&future_block).unwrap_err();
+        let inner_err = err.downcast::<NodeDatabaseError>().unwrap();
+        assert_eq!(inner_err, NodeDatabaseError::BlockTooFarInFuture);
+        assert_eq!(database.get_orphan_blocks().len(), 1);
+    }
+
+    #[test]
+    fn should_reject_transactions_out_of_canonical_order() {
+        let miner = miner_address();
+        let (database, genesis) = create_mock_database_with_genesis(DatabaseOptions::default());
+
+        // two valid transfers, sorted in descending id order
+        let mut transfers = vec![
+            Transaction::new(miner.clone(), Address::default(), 10),
+            Transaction::new(miner.clone(), Address::default(), 20),
+        ];
+        transfers.sort_by_key(|transaction| transaction.id());
+        transfers.reverse();
+
+        let coinbase = Transaction::new_coinbase(miner);
+        let mut transactions = vec![coinbase];
+        transactions.extend(transfers);
+        let block = Block::new(1, 0, genesis.hash.clone(), transactions.clone());
+
+        let err = database.append_block(&block).unwrap_err();
+        let inner_err = err.downcast::<BlockError>().unwrap();
+        assert_eq!(inner_err, BlockError::InvalidTransactionOrder);
+
+        // the same transactions in canonical order are valid
+        transactions[1..].reverse();
+        let block = Block::new(1, 0, genesis.hash, transactions);
+        database.append_block(&block).unwrap();
+    }
+
+    #[test]
+    fn should_verify_a_healthy_chain() {
+        let database = create_mock_database(3);
+
+        let report = database.verify_chain();
+
+        assert!(report.ok);
+        assert_eq!(report.height, 2);
+        assert!(report.failed_index.is_none());
+        assert!(report.first_error.is_none());
+    }
+
+    #[test]
+    fn should_report_the_first_tampered_block() {
+        let database = create_mock_database(4);
+
+        // tamper with the transactions of a block, so its hash is no longer valid
+        let mut blocks = database.get_all_blocks();
+        blocks[2].transactions[0].amount += 1;
+        database.0.write().unwrap().block_db = BlockDatabase::from_blocks(blocks, 0);
+
+        let report = database.verify_chain();
+
+        assert!(!report.ok);
+        assert_eq!(report.height, 1);
+        assert_eq!(report.failed_index, Some(2));
+        assert_eq!(report.first_error.unwrap(), "Invalid hash");
+    }
+
+    #[test]
+    fn should_report_tampered_balances() {
+        let database = create_mock_database(2);
+        database
+            .0
+            .write()
+            .unwrap()
+            .account_db
+            .add_funds(&Address::default(), 1)
+            .unwrap();
+
+        let report = database.verify_chain();
+
+        // all the blocks are valid, but they do not lead to the same balances
+        assert!(!report.ok);
+        assert_eq!(report.height, 1);
+        assert!(report.failed_index.is_none());
+        assert_eq!(
+            report.first_error.unwrap(),
+            NodeDatabaseError::BalancesMismatch.to_string()
+        );
+    }
+
+    #[test]
+    fn should_notify_tip_changes_to_subscribers() {
+        let database = create_mock_database(1);
+        let subscriber = database.subscribe_tip_changes();
+
+        // append a new block after subscribing
+        let tip_block = database.get_tip_block().unwrap();
+        let coinbase = Transaction::new_coinbase(Address::default());
+        let block = Block::new(1, 0, tip_block.hash, vec![coinbase]);
+        database.append_block(&block).unwrap();
+
+        // the subscriber receives the new tip, and only that
+        let tip_header = subscriber.try_recv().unwrap();
+        assert_eq!(tip_header, TipHeader::from(&block));
+        assert_eq!(tip_header.index, 1);
+        assert!(subscriber.try_recv().is_err());
+
+        // invalid blocks do not change the tip
+        database.append_block(&block).unwrap_err();
+        assert!(subscriber.try_recv().is_err());
+    }
+
+    #[test]
+    fn should_notify_template_changes_to_subscribers() {
+        let miner = miner_address();
+        let (database, genesis) = create_mock_database_with_genesis(DatabaseOptions::default());
+        let mut subscriber = database.subscribe_template_changes();
+
+        // a new tip changes the template
+        let block = Block::new(
+            1,
+            0,
+            genesis.hash,
+            vec![Transaction::new_coinbase(miner.clone())],
+        );
+        database.append_block(&block).unwrap();
+        subscriber.try_next().unwrap();
+
+        // and so does a new transaction in the mempool, but not a rejected one
+        let transaction = Transaction::new(miner, Address::default(), 10);
+        database
+            .add_mempool_transaction(transaction.clone())
+            .unwrap();
+        subscriber.try_next().unwrap();
+        database
+            .add_mempool_transaction(transaction.clone())
+            .unwrap_err();
+        assert!(subscriber.try_next().is_err());
+
+        // nor does removing a transaction that is not in the mempool, unlike a pending one
+        assert!(!database.remove_mempool_transaction(&ConsensusHash::default()));
+        assert!(subscriber.try_next().is_err());
+        assert!(database.remove_mempool_transaction(&transaction.id()));
+        subscriber.try_next().unwrap();
+
+        // subscribers that are gone are not notified anymore
+        drop(subscriber);
+        database.add_mempool_transaction(transaction).unwrap();
+        assert!(database.get_read_lock().template_listeners.is_empty());
+    }
+
+    #[test]
+    fn should_reject_duplicated_mempool_transactions() {
+        let miner = miner_address();
+        let (database, _) = create_mock_database_with_genesis(DatabaseOptions::default());
+
+        let transaction = Transaction::new(miner, Address::default(), 10);
+        database
+            .add_mempool_transaction(transaction.clone())
+            .unwrap();
+
+        let err = database.add_mempool_transaction(transaction).unwrap_err();
+        let inner_err = err.downcast::<NodeDatabaseError>().unwrap();
+        assert_eq!(inner_err, NodeDatabaseError::TransactionAlreadyExists);
+        assert_eq!(database.get_mempool_transactions().len(), 1);
+    }
+
+    #[test]
+    fn should_check_transactions_with_the_same_rules_as_added_ones() {
+        let miner = miner_address();
+        let alice = alice_address();
+        let (database, _) = create_mock_database_with_genesis(DatabaseOptions::default());
+
+        let transaction = Transaction::new(miner.clone(), alice.clone(), BLOCK_SUBSIDY);
+        database
+            .add_mempool_transaction(transaction.clone())
+            .unwrap();
+
+        let rejected_transactions = [
+            // already in the mempool
+            transaction.clone(),
+            // overspends the balance together with the pending one
+            Transaction::new(miner.clone(), alice.clone(), 1),
+            // invalid on its own
+            Transaction::new(alice.clone(), miner.clone(), 1),
+            Transaction::new(Address::default(), miner, 1),
+        ];
+        for transaction in rejected_transactions {
+            let check_err = database.check_transaction(&transaction).unwrap_err();
+            let add_err = database.add_mempool_transaction(transaction).unwrap_err();
+            assert_eq!(check_err.to_string(), add_err.to_string());
+        }
+        assert_eq!(database.get_mempool_transactions(), vec![transaction]);
+    }
+
+    #[test]
+    fn should_simulate_transactions_without_modifying_balances() {
+        let miner = miner_address();
+        let alice = alice_address();
+        let (database, _) = create_mock_database_with_genesis(DatabaseOptions::default());
+
+        let transactions = [
+            Transaction::new(miner.clone(), alice.clone(), 10),
+            Transaction::new(miner.clone(), alice.clone(), 20),
+        ];
+        let balances = database.simulate_transactions(&transactions).unwrap();
+
+        assert_eq!(
+            balances,
+            vec![(miner.clone(), BLOCK_SUBSIDY - 30), (alice.clone(), 30)]
+        );
+        assert_eq!(database.get_account_balance(&miner), Some(BLOCK_SUBSIDY));
+        assert_eq!(database.get_account_balance(&alice), None);
+
+        // together, the transactions cannot spend more than the balance
+        let transactions = [
+            Transaction::new(miner.clone(), alice.clone(), BLOCK_SUBSIDY),
+            Transaction::new(miner, alice, 1),
+        ];
+        assert!(database.simulate_transactions(&transactions).is_err());
+    }
+
+    #[test]
+    fn should_lock_vested_premine_until_its_unlock_height() {
+        let treasury = alice_address();
+        let mut network = create_mock_network();
+        network.premine = vec![GenesisAllocation {
+            address: treasury.clone(),
+            amount: 1000,
+            unlock_height: 3,
+        }];
+        let database = ConcurrentNodeDatabase::new(network.clone(), DatabaseOptions::default());
+
+        // the genesis block gives the premine to the treasury
+        let mut previous_hash = network.consensus_hash();
+        let transaction = Transaction::new(treasury.clone(), Address::default(), 10);
+        for index in 0..2 {
+            let coinbase = Transaction::new_coinbase(Address::default());
+            let block = Block::new(index, 0, previous_hash, vec![coinbase]);
+            database.append_block(&block).unwrap();
+            previous_hash = block.hash;
+
+            // but it can not be spent before the unlock height
+            assert_eq!(database.get_account_balance(&treasury), Some(1000));
+            let err = database.check_transaction(&transaction).unwrap_err();
+            let inner_err = err.downcast::<TransactionError>().unwrap();
+            assert_eq!(inner_err, TransactionError::InsufficientFunds);
+        }
+
+        // once the chain reaches it, the whole premine is spendable
+        let coinbase = Transaction::new_coinbase(Address::default());
+        let block = Block::new(2, 0, previous_hash, vec![coinbase]);
+        database.append_block(&block).unwrap();
+        assert_eq!(database.get_block_count(), 3);
+        assert_eq!(
+            database.get_spendable_balance(&treasury, database.get_block_count()),
+            Some(1000)
+        );
+        database.check_transaction(&transaction).unwrap();
+    }
+
+    #[test]
+    fn should_only_include_payable_transactions_in_template() {
+        let miner = miner_address();
+        let (database, _) = create_mock_database_with_genesis(DatabaseOptions {
+            mempool_conflict_policy: MempoolConflictPolicy::KeepBoth,
+            ..DatabaseOptions::default()
+        });
+
+        // both transactions are kept in the pool, even if the miner can only pay for one of them
+        let transaction = Transaction::new(miner.clone(), Address::default(), BLOCK_SUBSIDY);
+        let conflicting_transaction = transaction.clone().with_data(vec![1]);
+        database
+            .add_mempool_transaction(transaction.clone())
+            .unwrap();
+        database
+            .add_mempool_transaction(conflicting_transaction)
+            .unwrap();
+        assert_eq!(database.get_mempool_transactions().len(), 2);
+
+        // but the template only includes the first one, so the block is valid
+        let template = Block::new_template_with_coinbase(&database, miner);
+        assert_eq!(template.transactions.len(), 2);
+        assert_eq!(template.transactions[1], transaction);
+        database.check_block(&template).unwrap();
+    }
+
+    #[test]
+    fn should_build_the_same_template_until_the_state_changes() {
+        let (database, miner, alice) =
+            create_mock_database_with_transfers(DatabaseOptions::default());
+
+        // templates built at different times on the same state are identical
+        let template = Block::new_template(&database);
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(Block::new_template(&database), template);
+
+        // a new transaction changes the template, but not its timestamp
+        let transaction = Transaction::new(miner, alice.clone(), 30);
+        database.add_mempool_transaction(transaction).unwrap();
+        let new_template = Block::new_template(&database);
+        assert_ne!(new_template.hash, template.hash);
+        assert_eq!(new_template.timestamp, template.timestamp);
+
+        // a new tip changes the timestamp, which is never older than the tip
+        let tip_block = database.get_tip_block().unwrap();
+        let future_timestamp = template.timestamp + 60_000;
+        let mut block = Block::new(2, 0, tip_block.hash, vec![Transaction::new_coinbase(alice)]);
+        block.timestamp = future_timestamp;
+        block.hash = block.calculate_hash();
+        database.append_block(&block).unwrap();
+        assert_eq!(Block::new_template(&database).timestamp, future_timestamp);
+    }
+
+    #[test]
+    fn should_import_a_chain_into_a_fresh_database() {
+        let miner = miner_address();
+        let alice = alice_address();
+
+        // a synced node where the miner sent some funds to alice
+        let (database, genesis) = create_mock_database_with_genesis(DatabaseOptions::default());
+        let coinbase = Transaction::new_coinbase(miner.clone());
+        let transfer = Transaction::new(miner.clone(), alice.clone(), 10);
+        let block = Block::new(1, 0, genesis.hash, vec![coinbase, transfer]);
+        database.append_block(&block).unwrap();
+
+        // import the chain from its binary encoding
+        let bytes = database.export_chain().to_bytes().unwrap();
+        let export = ChainExport::from_reader(bytes.as_slice()).unwrap();
+        let new_database =
+            ConcurrentNodeDatabase::new(database.get_network(), DatabaseOptions::default());
+        new_database.import_chain(export).unwrap();
+
+        // the new node has the same tip and balances
+        assert_eq!(new_database.get_tip_block(), database.get_tip_block());
+        assert_eq!(new_database.get_block_count(), 2);
+        for address in [&miner, &alice] {
+            assert_eq!(
+                new_database.get_account_balance(address),
+                database.get_account_balance(address)
+            );
+        }
+
+        // and a chain can not be imported over an existing one
+        let err = new_database
+            .import_chain(database.export_chain())
+            .unwrap_err();
+        let inner_err = err.downcast::<ChainExportError>().unwrap();
+        assert_eq!(inner_err, ChainExportError::DatabaseNotEmpty);
+    }
+
+    #[test]
+    fn should_reject_an_export_with_invalid_blocks() {
+        let database = create_mock_database(3);
+        let new_database =
+            ConcurrentNodeDatabase::new(create_mock_network(), DatabaseOptions::default());
+
+        // a block that does not follow the previous one, even with a valid hash of its own
+        let mut blocks = database.get_all_blocks();
+        blocks[2].previous_hash = ConsensusHash::default();
+        blocks[2].hash = blocks[2].calculate_hash();
+        let err = new_database
+            .import_chain(ChainExport::new(blocks))
+            .unwrap_err();
+        let inner_err = err.downcast::<ChainError>().unwrap();
+        assert_eq!(inner_err, ChainError::InvalidPreviousHash);
+
+        // none of the blocks is imported, not even the valid ones before it
+        assert_eq!(new_database.get_block_count(), 0);
+    }
+
+    #[test]
+    fn should_report_the_utilization_of_the_blocks_and_mempool() {
+        let (database, miner, alice) =
+            create_mock_database_with_transfers(DatabaseOptions::default());
+
+        // and a transfer waiting in the mempool
+        let pending = Transaction::new(miner, alice, 30);
+        database.add_mempool_transaction(pending.clone()).unwrap();
+
+        let utilization = database.get_chain_stats().utilization;
+        let total_weight: u64 = database
+            .get_all_blocks()
+            .iter()
+            .flat_map(|block| &block.transactions)
+            .map(Transaction::weight)
+            .sum();
+        let expected_weight = total_weight as f64 / 2.0;
+        assert_eq!(utilization.recent_blocks, 2);
+        assert_eq!(utilization.average_block_weight, expected_weight);
+        assert_eq!(
+            utilization.average_block_fullness,
+            Some(expected_weight / 10000.0)
+        );
+        assert_eq!(utilization.mempool_bytes, pending.weight());
+        assert_eq!(utilization.max_mempool_bytes, DEFAULT_MAX_MEMPOOL_BYTES);
+    }
+
+    // Create a database with a chain of valid blocks
+    fn create_mock_database(num_blocks: u64) -> ConcurrentNodeDatabase {
+        let network = create_mock_network();
+        let database = ConcurrentNodeDatabase::new(network.clone(), DatabaseOptions::default());
+
+        let mut previous_hash = network.consensus_hash();
+        for index in 0..num_blocks {
+            let coinbase = Transaction::new(Address::default(), Address::default(), BLOCK_SUBSIDY);
+            let block = Block::new(index, 0, previous_hash, vec![coinbase]);
+            database.append_block(&block).unwrap();
+            previous_hash = block.hash;
+        }
+
+        database
+    }
+
+    // Create a database with only the genesis block, whose coinbase goes to the miner
+    fn create_mock_database_with_genesis(
+        options: DatabaseOptions,
+    ) -> (ConcurrentNodeDatabase, Block) {
+        let network = create_mock_network();
+        let database = ConcurrentNodeDatabase::new(network.clone(), options);
+        let genesis_coinbase = Transaction::new_coinbase(miner_address());
+        let genesis = Block::new(0, 0, network.consensus_hash(), vec![genesis_coinbase]);
+        database.append_block(&genesis).unwrap();
+
+        (database, genesis)
+    }
+
+    // Create a database where the miner receives the genesis coinbase and then sends two transfers to alice,
+    // which also receives the coinbase of the second block (in canonical order, the transfer of 20 goes first)
+    fn create_mock_database_with_transfers(
+        options: DatabaseOptions,
+    ) -> (ConcurrentNodeDatabase, Address, Address) {
+        let miner = miner_address();
+        let alice = alice_address();
+        let (database, genesis) = create_mock_database_with_genesis(options);
+
+        let mut transfers = vec![
+            Transaction::new(miner.clone(), alice.clone(), 10),
+            Transaction::new(miner.clone(), alice.clone(), 20),
+        ];
+        transfers.sort_by_key(|transaction| transaction.id());
+        let mut transactions = vec![Transaction::new_coinbase(alice.clone())];
+        transactions.extend(transfers);
+        let block = Block::new(1, 0, genesis.hash.clone(), transactions);
+        database.append_block(&block).unwrap();
+
+        (database, miner, alice)
+    }
+
+    fn get_history_amounts(history: &[HistoryEntry]) -> Vec<Coin> {
+        history
+            .iter()
+            .map(|entry| entry.transaction.amount)
+            .collect()
+    }
+
+    // The blocks have a weight limit, so their fullness is reported
+    fn create_mock_network() -> Network {
+        Network {
+            max_block_weight: 10000,
+            ..Network::test_default()
+        }
+    }
+
+    // Also used by the tests of the submodules, so the mock addresses are the same everywhere
+    pub(super) fn create_mock_address(byte: u8) -> Address {
+        ConsensusHash::digest(&[byte])
+    }
+
+    fn miner_address() -> Address {
+        "fe8aa8cc6011898d49bdacd0ab52075e92e1dfb2915bb9223528a5737583731d"
+            .parse()
+            .unwrap()
+    }
+
+    fn alice_address() -> Address {
+        "f780b958227ff0bf5795ede8f9f7eaac67e7e06666b043a400026cbd421ce28e"
+            .parse()
+            .unwrap()
+    }
+
+    fn get_indexes(blocks: &[Block]) -> Vec<u64> {
+        blocks.iter().map(|block| block.index).collect()
+    }
 }
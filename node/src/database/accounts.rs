@@ -12,15 +12,33 @@ pub enum AccountDatabaseError {
 
     #[error("Insufficient funds")]
     InsufficientFunds,
+
+    #[error("Balance overflow")]
+    BalanceOverflow,
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct AccountDatabase(HashMap<Address, Coin>);
 
 impl AccountDatabase {
-    pub fn add_funds(&mut self, address: &Address, new_funds: Coin) {
-        let current_balance = self.get_account_balance(address).unwrap_or(0);
-        self.update_funds(address, current_balance + new_funds);
+    #[cfg(test)]
+    pub fn from_balances(balances: Vec<(Address, Coin)>) -> Self {
+        AccountDatabase(balances.into_iter().collect())
+    }
+
+    pub fn add_funds(
+        &mut self,
+        address: &Address,
+        new_funds: Coin,
+    ) -> Result<(), AccountDatabaseError> {
+        let current_balance = self.get_recipient_balance(address);
+        let new_balance = current_balance
+            .checked_add(new_funds)
+            .ok_or(AccountDatabaseError::BalanceOverflow)?;
+
+        self.update_funds(address, new_balance);
+
+        Ok(())
     }
 
     pub fn transfer(
@@ -29,17 +47,22 @@ impl AccountDatabase {
         recipient: &Address,
         amount: Coin,
     ) -> Result<(), AccountDatabaseError> {
+        // balances can never go negative
         let sender_balance = self.get_sender_balance(sender)?;
-        let recipient_balance = self.get_recipient_balance(recipient);
+        let new_sender_balance = sender_balance
+            .checked_sub(amount)
+            .ok_or(AccountDatabaseError::InsufficientFunds)?;
 
-        if sender_balance < amount {
-            return Err(AccountDatabaseError::InsufficientFunds);
+        // the credit is checked before debiting the sender, so a failed transfer does not change any balance
+        // (a transfer to the same account can not overflow, as the amount was just debited)
+        if sender != recipient {
+            self.get_recipient_balance(recipient)
+                .checked_add(amount)
+                .ok_or(AccountDatabaseError::BalanceOverflow)?;
         }
 
-        self.update_funds(sender, sender_balance - amount);
-        self.update_funds(recipient, recipient_balance + amount);
-
-        Ok(())
+        self.update_funds(sender, new_sender_balance);
+        self.add_funds(recipient, amount)
     }
 
     pub fn get_account_balance(&self, address: &Address) -> Option<Coin> {
@@ -65,3 +88,39 @@ impl AccountDatabase {
         *balance = new_balance;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::create_mock_address;
+
+    #[test]
+    fn should_reject_overflowing_credits() {
+        let alice = create_mock_address(1);
+        let mut account_db = AccountDatabase::from_balances(vec![(alice.clone(), Coin::MAX)]);
+
+        let err = account_db.add_funds(&alice, 1).unwrap_err();
+
+        assert_eq!(err, AccountDatabaseError::BalanceOverflow);
+        assert_eq!(account_db.get_account_balance(&alice), Some(Coin::MAX));
+    }
+
+    #[test]
+    fn should_reject_failed_transfers_without_changing_balances() {
+        let alice = create_mock_address(1);
+        let bob = create_mock_address(2);
+        let balances = vec![(alice.clone(), 10), (bob.clone(), Coin::MAX)];
+        let mut account_db = AccountDatabase::from_balances(balances);
+        let original_account_db = account_db.clone();
+
+        // alice can not spend more than the account balance
+        let err = account_db.transfer(&alice, &bob, 11).unwrap_err();
+        assert_eq!(err, AccountDatabaseError::InsufficientFunds);
+
+        // and bob can not receive beyond the maximum balance
+        let err = account_db.transfer(&alice, &bob, 10).unwrap_err();
+        assert_eq!(err, AccountDatabaseError::BalanceOverflow);
+
+        assert_eq!(account_db, original_account_db);
+    }
+}
@@ -1,20 +1,206 @@
-use spec::types::Block;
+use std::collections::{HashMap, HashSet};
+
+use spec::{
+    types::{hash::ConsensusHash, Address, Block, Transaction},
+    validators::calculate_work,
+};
+
+use super::{HistoryDirection, HistoryEntry};
+
+// Number of latest blocks in a locator before spacing out the hashes, as most forks are recent
+const DENSE_LOCATOR_HASHES: usize = 10;
 
 #[derive(Debug, Clone, Default)]
 pub struct BlockDatabase {
     blocks: Vec<Block>,
+    // positions (block index and transaction index) of the transactions sent or received by each address
+    address_index: HashMap<Address, Vec<(usize, usize)>>,
+    // position of the block that includes each transaction
+    transaction_index: HashMap<ConsensusHash, usize>,
+    // position of each block by its hash
+    hash_index: HashMap<ConsensusHash, usize>,
+    // difficulty of each block, and the total work of the chain up to each block
+    difficulties: Vec<u32>,
+    chain_work: Vec<u128>,
 }
 
 impl BlockDatabase {
+    // Blocks are always validated before being added, this skips it to build invalid chains in tests
+    #[cfg(test)]
+    pub fn from_blocks(blocks: Vec<Block>, difficulty: u32) -> Self {
+        let mut block_db = BlockDatabase::default();
+        for block in blocks {
+            block_db.append_block(block, difficulty);
+        }
+
+        block_db
+    }
+
     pub fn get_all_blocks(&self) -> Vec<Block> {
         self.blocks.clone()
     }
 
+    pub fn get_block_by_index(&self, index: u64) -> Option<Block> {
+        let index = usize::try_from(index).ok()?;
+        self.blocks.get(index).cloned()
+    }
+
+    // Return up to `limit` consecutive blocks, starting with the one with the `from` index
+    pub fn get_blocks_range(&self, from: u64, limit: u64) -> Vec<Block> {
+        let start = usize::try_from(from).unwrap_or(usize::MAX);
+        let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+
+        match self.blocks.get(start..) {
+            Some(blocks) => blocks.iter().take(limit).cloned().collect(),
+            None => vec![],
+        }
+    }
+
     pub fn get_tip_block(&self) -> Option<Block> {
         self.blocks.last().cloned()
     }
 
-    pub fn append_block(&mut self, block: Block) {
+    // Check if the exact same block is already present in the chain
+    pub fn contains_block(&self, block: &Block) -> bool {
+        match self.blocks.get(block.index as usize) {
+            Some(existing_block) => existing_block == block,
+            None => false,
+        }
+    }
+
+    pub fn get_block_count(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+
+    // The difficulty of the block is kept, so neither it nor the chain work need to be computed again
+    pub fn append_block(&mut self, block: Block, difficulty: u32) {
+        let block_position = self.blocks.len();
+        let previous_work = self.chain_work.last().copied().unwrap_or_default();
+        self.chain_work
+            .push(previous_work.saturating_add(calculate_work(difficulty)));
+        self.difficulties.push(difficulty);
+
+        for (position, transaction) in block.transactions.iter().enumerate() {
+            // identical transactions (e.g. coinbases to the same address) point to the latest block
+            self.transaction_index
+                .insert(transaction.id(), block_position);
+
+            let mut addresses = vec![&transaction.sender, &transaction.recipient];
+            addresses.dedup();
+            for address in addresses {
+                self.address_index
+                    .entry(address.clone())
+                    .or_default()
+                    .push((block_position, position));
+            }
+        }
+
+        self.hash_index.insert(block.hash.clone(), block_position);
         self.blocks.push(block);
     }
+
+    // Hashes of the chain from the tip backwards, consecutive for the latest blocks and then exponentially
+    // spaced until the genesis, so a peer can find the last block in common with a few hashes
+    pub fn get_locator(&self) -> Vec<ConsensusHash> {
+        let mut locator = vec![];
+        let mut position = match self.blocks.len().checked_sub(1) {
+            Some(value) => value,
+            None => return locator,
+        };
+
+        let mut step = 1;
+        while position > 0 {
+            locator.push(self.blocks[position].hash.clone());
+            if locator.len() >= DENSE_LOCATOR_HASHES {
+                step *= 2;
+            }
+            position = position.saturating_sub(step);
+        }
+        locator.push(self.blocks[0].hash.clone());
+
+        locator
+    }
+
+    // Return up to `limit` blocks after the first block of the locator that is in the chain,
+    // or from the genesis if there is none in common
+    pub fn get_blocks_after_locator(&self, locator: &[ConsensusHash], limit: u64) -> Vec<Block> {
+        let from = locator
+            .iter()
+            .find_map(|hash| self.hash_index.get(hash))
+            .map_or(0, |position| position + 1);
+
+        self.get_blocks_range(from as u64, limit)
+    }
+
+    pub fn get_difficulty(&self, index: u64) -> Option<u32> {
+        let index = usize::try_from(index).ok()?;
+        self.difficulties.get(index).copied()
+    }
+
+    // Total expected work to mine all the blocks up to the indicated index
+    pub fn get_chain_work(&self, index: u64) -> Option<u128> {
+        let index = usize::try_from(index).ok()?;
+        self.chain_work.get(index).copied()
+    }
+
+    // Ids of the transactions included in the latest `depth` blocks
+    pub fn get_recent_transaction_ids(&self, depth: u64) -> HashSet<ConsensusHash> {
+        let depth = usize::try_from(depth).unwrap_or(usize::MAX);
+        self.blocks
+            .iter()
+            .rev()
+            .take(depth)
+            .flat_map(|block| block.transactions.iter().map(Transaction::id))
+            .collect()
+    }
+
+    // Index of the latest block that includes a transaction, if it is one of the latest `depth` blocks
+    pub fn get_recent_transaction_block(&self, id: &ConsensusHash, depth: u64) -> Option<u64> {
+        let block_index = self.get_transaction_block(id)?.index;
+        let height = self.blocks.len() as u64;
+        (block_index >= height.saturating_sub(depth)).then_some(block_index)
+    }
+
+    // Return the latest block that includes a transaction, if any
+    pub fn get_transaction_block(&self, id: &ConsensusHash) -> Option<&Block> {
+        let block_position = self.transaction_index.get(id)?;
+        self.blocks.get(*block_position)
+    }
+
+    // Return up to `limit` transactions of an address in the direction indicated, oldest first,
+    // skipping the first `from` ones, so the history can be read in pages
+    pub fn get_address_history(
+        &self,
+        address: &Address,
+        direction: HistoryDirection,
+        from: u64,
+        limit: u64,
+    ) -> Vec<HistoryEntry> {
+        let positions = match self.address_index.get(address) {
+            Some(value) => value,
+            None => return vec![],
+        };
+        let from = usize::try_from(from).unwrap_or(usize::MAX);
+        let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+
+        positions
+            .iter()
+            .map(|&(block_position, position)| {
+                let block = &self.blocks[block_position];
+                (block.index, &block.transactions[position])
+            })
+            .filter(|(_, transaction)| match direction {
+                HistoryDirection::Sent => transaction.sender == *address,
+                HistoryDirection::Received => transaction.recipient == *address,
+                HistoryDirection::All => true,
+            })
+            .skip(from)
+            .take(limit)
+            .map(|(block_index, transaction)| HistoryEntry {
+                block_index,
+                transaction_id: transaction.id(),
+                transaction: transaction.clone(),
+            })
+            .collect()
+    }
 }
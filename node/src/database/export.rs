@@ -0,0 +1,78 @@
+use std::io::Read;
+
+use anyhow::Result;
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use spec::types::Block;
+use thiserror::Error;
+
+// Exports bigger than this are rejected while decoding them, so a crafted file cannot exhaust the memory
+const MAX_EXPORT_BYTES: u64 = 1024 * 1024 * 1024;
+
+#[derive(Error, PartialEq, Eq, Debug)]
+pub enum ChainExportError {
+    #[error("A chain can only be imported into an empty database")]
+    DatabaseNotEmpty,
+}
+
+// Binary export of the whole chain, so a new node can import it from a file instead of syncing it from peers
+// It is not a fast sync: the file may come from an untrusted source, so every block is validated
+// and its transactions processed again when imported, as if the blocks were received from a peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainExport {
+    pub blocks: Vec<Block>,
+}
+
+impl ChainExport {
+    pub fn new(blocks: Vec<Block>) -> Self {
+        ChainExport { blocks }
+    }
+
+    // The file is decoded while being read, so an oversized one is never fully loaded
+    pub fn from_reader(reader: impl Read) -> Result<Self> {
+        decode(reader, MAX_EXPORT_BYTES)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+}
+
+// Same encoding as `bincode::serialize`, but failing as soon as more than `limit` bytes are read
+// bincode ignores the limit when decoding a slice, so the input is always decoded as a reader
+fn decode(reader: impl Read, limit: u64) -> Result<ChainExport> {
+    let options = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit);
+
+    Ok(options.deserialize_from(reader)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use spec::types::hash::ConsensusHash;
+
+    use super::*;
+
+    #[test]
+    fn should_encode_and_decode_an_export() {
+        let block = Block::new(0, 0, ConsensusHash::default(), vec![]);
+        let export = ChainExport::new(vec![block]);
+
+        let bytes = export.to_bytes().unwrap();
+
+        assert_eq!(ChainExport::from_reader(bytes.as_slice()).unwrap(), export);
+    }
+
+    #[test]
+    fn should_reject_exports_over_the_size_limit() {
+        let blocks = (0..10)
+            .map(|index| Block::new(index, 0, ConsensusHash::default(), vec![]))
+            .collect();
+        let bytes = ChainExport::new(blocks).to_bytes().unwrap();
+
+        decode(bytes.as_slice(), bytes.len() as u64).unwrap();
+        assert!(decode(bytes.as_slice(), bytes.len() as u64 - 1).is_err());
+    }
+}
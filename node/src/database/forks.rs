@@ -0,0 +1,83 @@
+use spec::types::{hash::ConsensusHash, Block};
+
+// Maximum amount of blocks tracked outside of the main chain, to avoid unbounded memory usage
+const MAX_TRACKED_BLOCKS: usize = 100;
+
+// Keeps track of the valid blocks that could not be appended to the main chain.
+// Side blocks belong to competing branches, orphan blocks are waiting for an unknown parent.
+// They are only kept for inspection, as the node does not switch branches.
+#[derive(Debug, Clone, Default)]
+pub struct ForkDatabase {
+    side_blocks: Vec<Block>,
+    orphans: Vec<Block>,
+}
+
+impl ForkDatabase {
+    pub fn get_side_blocks(&self) -> Vec<Block> {
+        self.side_blocks.clone()
+    }
+
+    pub fn get_orphans(&self) -> Vec<Block> {
+        self.orphans.clone()
+    }
+
+    pub fn get_side_block(&self, hash: &ConsensusHash) -> Option<Block> {
+        self.side_blocks
+            .iter()
+            .find(|block| block.hash == *hash)
+            .cloned()
+    }
+
+    pub fn add_side_block(&mut self, block: Block) {
+        if self.get_side_block(&block.hash).is_none() {
+            push_bounded(&mut self.side_blocks, block);
+        }
+    }
+
+    pub fn add_orphan(&mut self, block: Block) {
+        if !self.orphans.iter().any(|orphan| orphan.hash == block.hash) {
+            push_bounded(&mut self.orphans, block);
+        }
+    }
+}
+
+// The oldest blocks are discarded first
+fn push_bounded(blocks: &mut Vec<Block>, block: Block) {
+    if blocks.len() >= MAX_TRACKED_BLOCKS {
+        blocks.remove(0);
+    }
+    blocks.push(block);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_ignore_duplicated_blocks() {
+        let mut fork_db = ForkDatabase::default();
+        let block = Block::new(1, 0, ConsensusHash::default(), vec![]);
+
+        fork_db.add_side_block(block.clone());
+        fork_db.add_side_block(block.clone());
+        fork_db.add_orphan(block.clone());
+        fork_db.add_orphan(block.clone());
+
+        assert_eq!(fork_db.get_side_blocks(), vec![block.clone()]);
+        assert_eq!(fork_db.get_orphans(), vec![block]);
+    }
+
+    #[test]
+    fn should_discard_oldest_blocks_when_full() {
+        let mut fork_db = ForkDatabase::default();
+
+        for nonce in 0..=MAX_TRACKED_BLOCKS as u64 {
+            let block = Block::new(1, nonce, ConsensusHash::default(), vec![]);
+            fork_db.add_orphan(block);
+        }
+
+        let orphans = fork_db.get_orphans();
+        assert_eq!(orphans.len(), MAX_TRACKED_BLOCKS);
+        assert_eq!(orphans.first().unwrap().nonce, 1);
+    }
+}
@@ -1,34 +1,219 @@
-use spec::types::Transaction;
+use std::collections::HashSet;
+
+use spec::types::{hash::ConsensusHash, Address, Coin, Transaction};
+use thiserror::Error;
+
+use crate::util::config::MempoolConflictPolicy;
+
+#[derive(Error, PartialEq, Eq, Debug)]
+pub enum MempoolError {
+    #[error("Transaction overspends the balance together with pending transactions of the sender")]
+    ConflictingTransaction,
+
+    #[error("Mempool is full of transactions smaller than this one")]
+    MempoolFull,
+}
+
+// Default budget of the serialized transactions kept in the pool
+pub const DEFAULT_MAX_MEMPOOL_BYTES: u64 = 32 * 1024 * 1024;
 
 // Represents a pool of unrealized transactions
-#[derive(Debug, Clone, Default)]
+// The pool is bounded by the serialized size of its transactions, as their data fields make the
+// transaction count a poor measure of the memory it takes
+#[derive(Debug, Clone)]
 pub struct Mempool {
     transactions: Vec<Transaction>,
+    conflict_policy: MempoolConflictPolicy,
+    max_bytes: u64,
+    total_bytes: u64,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::new(MempoolConflictPolicy::default(), DEFAULT_MAX_MEMPOOL_BYTES)
+    }
 }
 
 impl Mempool {
+    pub fn new(conflict_policy: MempoolConflictPolicy, max_bytes: u64) -> Self {
+        Self {
+            transactions: vec![],
+            conflict_policy,
+            max_bytes,
+            total_bytes: 0,
+        }
+    }
+
     pub fn get_transactions(&self) -> Vec<Transaction> {
         self.transactions.clone()
     }
 
-    // Add a new transaction to the pool
-    pub fn add_transaction(&mut self, transaction: Transaction) {
+    pub fn get_transaction_count(&self) -> usize {
+        self.transactions.len()
+    }
+
+    // Serialized size of all the transactions in the pool
+    pub fn get_total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    pub fn get_max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    pub fn contains_transaction(&self, id: &ConsensusHash) -> bool {
+        self.transactions.iter().any(|t| t.id() == *id)
+    }
+
+    // Add a new transaction to the pool, given the spendable balance of its sender
+    // Transactions of the same sender that together spend more than the balance are conflicting,
+    // and they are resolved according to the conflict policy
+    pub fn add_transaction(
+        &mut self,
+        transaction: Transaction,
+        sender_balance: Coin,
+    ) -> Result<(), MempoolError> {
+        self.check_transaction(&transaction, sender_balance)?;
+
+        if self.conflict_policy == MempoolConflictPolicy::Replace {
+            self.evict_conflicting_transactions(&transaction, sender_balance);
+        }
+
+        self.evict_largest_transactions(transaction.weight());
+        self.total_bytes += transaction.weight();
         self.transactions.push(transaction);
         info!("transaction added");
+
+        Ok(())
+    }
+
+    // Check if a transaction would be added to the pool, without modifying it
+    pub fn check_transaction(
+        &self,
+        transaction: &Transaction,
+        sender_balance: Coin,
+    ) -> Result<(), MempoolError> {
+        let is_rejected = self.conflict_policy == MempoolConflictPolicy::RejectSecond
+            && !self.fits_in_balance(transaction, sender_balance);
+        if is_rejected {
+            return Err(MempoolError::ConflictingTransaction);
+        }
+
+        if !self.fits_in_budget(transaction.weight()) {
+            return Err(MempoolError::MempoolFull);
+        }
+
+        Ok(())
+    }
+
+    // Check if enough transactions bigger than the new one can be evicted to make room for it
+    // Transactions carry no fee, so the fee per byte is lowest for the biggest transactions
+    fn fits_in_budget(&self, weight: u64) -> bool {
+        let Some(overflow) = (self.total_bytes + weight).checked_sub(self.max_bytes) else {
+            return true;
+        };
+
+        let evictable_bytes: u64 = self
+            .transactions
+            .iter()
+            .map(Transaction::weight)
+            .filter(|pending_weight| *pending_weight > weight)
+            .sum();
+        evictable_bytes >= overflow
+    }
+
+    // Remove the biggest transactions until there is room for a new one of the given weight
+    // (the most recent one is removed among transactions of the same size)
+    fn evict_largest_transactions(&mut self, weight: u64) {
+        while self.total_bytes + weight > self.max_bytes {
+            let position = self
+                .transactions
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, t)| t.weight())
+                .map(|(position, _)| position);
+            match position {
+                Some(position) => self.remove_at(position),
+                None => break,
+            };
+        }
+    }
+
+    fn remove_at(&mut self, position: usize) -> Transaction {
+        let transaction = self.transactions.remove(position);
+        self.total_bytes -= transaction.weight();
+        transaction
+    }
+
+    // Check if the sender can pay for the transaction along with all its pending ones
+    fn fits_in_balance(&self, transaction: &Transaction, sender_balance: Coin) -> bool {
+        let pending_amount = self.get_pending_amount(&transaction.sender);
+        match pending_amount.checked_add(transaction.amount) {
+            Some(total_amount) => total_amount <= sender_balance,
+            None => false,
+        }
+    }
+
+    fn get_pending_amount(&self, sender: &Address) -> Coin {
+        self.transactions
+            .iter()
+            .filter(|t| t.sender == *sender)
+            .fold(0, |total, t| total.saturating_add(t.amount))
+    }
+
+    // Remove the most recent transactions of the sender until the new one can be paid
+    fn evict_conflicting_transactions(&mut self, transaction: &Transaction, sender_balance: Coin) {
+        while !self.fits_in_balance(transaction, sender_balance) {
+            let position = self
+                .transactions
+                .iter()
+                .rposition(|t| t.sender == transaction.sender);
+            match position {
+                Some(position) => self.remove_at(position),
+                None => break,
+            };
+        }
+    }
+
+    // Remove the transaction with the indicated id, returning whether it was present in the pool
+    pub fn remove_transaction_by_id(&mut self, id: &ConsensusHash) -> bool {
+        let previous_len = self.transactions.len();
+        self.retain_transactions(|t| t.id() != *id);
+        self.transactions.len() != previous_len
+    }
+
+    // Remove the transactions with any of the indicated ids, returning how many were removed
+    pub fn remove_transactions_by_id(&mut self, ids: &HashSet<ConsensusHash>) -> usize {
+        let previous_len = self.transactions.len();
+        self.retain_transactions(|t| !ids.contains(&t.id()));
+        previous_len - self.transactions.len()
     }
 
     pub fn remove_transactions(&mut self, transactions: &[Transaction]) {
         // TODO: transactions should have a nonce to avoid duplicates
-        self.transactions.retain(|t| !transactions.contains(t));
+        self.retain_transactions(|t| !transactions.contains(t));
+    }
+
+    fn retain_transactions(&mut self, keep: impl Fn(&Transaction) -> bool) {
+        let total_bytes = &mut self.total_bytes;
+        self.transactions.retain(|t| {
+            let is_kept = keep(t);
+            if !is_kept {
+                *total_bytes -= t.weight();
+            }
+            is_kept
+        });
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use spec::types::Address;
+    use spec::types::{Address, FORMAT_VERSION};
 
     use super::*;
 
+    const BALANCE: Coin = 100;
+
     #[test]
     fn should_be_empty_after_creation() {
         let mempool = Mempool::default();
@@ -42,11 +227,13 @@ mod tests {
 
         // add a single transaction to the pool...
         let transaction = create_mock_transaction(1);
-        mempool.add_transaction(transaction.clone());
+        mempool
+            .add_transaction(transaction.clone(), BALANCE)
+            .unwrap();
         assert_eq!(mempool.get_transactions().len(), 1);
 
         // ...and then remove it
-        mempool.remove_transactions(&vec![transaction]);
+        mempool.remove_transactions(&[transaction]);
         assert!(mempool.get_transactions().is_empty());
     }
 
@@ -58,13 +245,13 @@ mod tests {
         let tx_1 = create_mock_transaction(1);
         let tx_2 = create_mock_transaction(2);
         let tx_3 = create_mock_transaction(3);
-        mempool.add_transaction(tx_1.clone());
-        mempool.add_transaction(tx_2.clone());
-        mempool.add_transaction(tx_3.clone());
+        mempool.add_transaction(tx_1.clone(), BALANCE).unwrap();
+        mempool.add_transaction(tx_2.clone(), BALANCE).unwrap();
+        mempool.add_transaction(tx_3.clone(), BALANCE).unwrap();
         assert_eq!(mempool.get_transactions().len(), 3);
 
         // and then remove some all but one
-        mempool.remove_transactions(&vec![tx_1, tx_3]);
+        mempool.remove_transactions(&[tx_1, tx_3]);
         assert_eq!(mempool.get_transactions().len(), 1);
 
         // the remaining transaction should be the non-removed one
@@ -72,11 +259,158 @@ mod tests {
         assert_eq!(remaining_tx, tx_2);
     }
 
+    #[test]
+    fn should_remove_transaction_by_id() {
+        let mut mempool = Mempool::default();
+
+        let tx_1 = create_mock_transaction(1);
+        let tx_2 = create_mock_transaction(2);
+        mempool.add_transaction(tx_1.clone(), BALANCE).unwrap();
+        mempool.add_transaction(tx_2.clone(), BALANCE).unwrap();
+
+        // removing an existing transaction
+        assert!(mempool.remove_transaction_by_id(&tx_1.id()));
+        assert_eq!(mempool.get_transactions(), vec![tx_2]);
+
+        // removing an unknown transaction
+        assert!(!mempool.remove_transaction_by_id(&tx_1.id()));
+        assert_eq!(mempool.get_transactions().len(), 1);
+    }
+
+    #[test]
+    fn should_remove_multiple_transactions_by_id() {
+        let mut mempool = Mempool::default();
+
+        let tx_1 = create_mock_transaction(1);
+        let tx_2 = create_mock_transaction(2);
+        let tx_3 = create_mock_transaction(3);
+        mempool.add_transaction(tx_1.clone(), BALANCE).unwrap();
+        mempool.add_transaction(tx_2.clone(), BALANCE).unwrap();
+        mempool.add_transaction(tx_3.clone(), BALANCE).unwrap();
+
+        // unknown ids are ignored
+        let ids = HashSet::from([tx_1.id(), tx_3.id(), create_mock_transaction(4).id()]);
+        assert_eq!(mempool.remove_transactions_by_id(&ids), 2);
+        assert_eq!(mempool.get_transactions(), vec![tx_2.clone()]);
+        assert_eq!(mempool.get_total_bytes(), tx_2.weight());
+    }
+
+    #[test]
+    fn should_reject_second_conflicting_transaction() {
+        let mut mempool = Mempool::new(
+            MempoolConflictPolicy::RejectSecond,
+            DEFAULT_MAX_MEMPOOL_BYTES,
+        );
+
+        // each transaction fits in the balance, but not both of them
+        let tx_1 = create_mock_transaction(6);
+        let tx_2 = create_mock_transaction(5);
+        mempool.add_transaction(tx_1.clone(), 10).unwrap();
+
+        let err = mempool.add_transaction(tx_2, 10).unwrap_err();
+        assert_eq!(err, MempoolError::ConflictingTransaction);
+        assert_eq!(mempool.get_transactions(), vec![tx_1]);
+    }
+
+    #[test]
+    fn should_replace_conflicting_transactions() {
+        let mut mempool = Mempool::new(MempoolConflictPolicy::Replace, DEFAULT_MAX_MEMPOOL_BYTES);
+
+        // the most recent pending transactions are evicted until the new one fits
+        let tx_1 = create_mock_transaction(4);
+        let tx_2 = create_mock_transaction(5);
+        let tx_3 = create_mock_transaction(6);
+        mempool.add_transaction(tx_1.clone(), 10).unwrap();
+        mempool.add_transaction(tx_2, 10).unwrap();
+        mempool.add_transaction(tx_3.clone(), 10).unwrap();
+
+        assert_eq!(mempool.get_transactions(), vec![tx_1, tx_3]);
+    }
+
+    #[test]
+    fn should_keep_both_conflicting_transactions() {
+        let mut mempool = Mempool::new(MempoolConflictPolicy::KeepBoth, DEFAULT_MAX_MEMPOOL_BYTES);
+
+        let tx_1 = create_mock_transaction(6);
+        let tx_2 = create_mock_transaction(5);
+        mempool.add_transaction(tx_1.clone(), 10).unwrap();
+        mempool.add_transaction(tx_2.clone(), 10).unwrap();
+
+        assert_eq!(mempool.get_transactions(), vec![tx_1, tx_2]);
+    }
+
+    #[test]
+    fn should_accept_non_conflicting_transactions_with_any_policy() {
+        for policy in [
+            MempoolConflictPolicy::RejectSecond,
+            MempoolConflictPolicy::Replace,
+            MempoolConflictPolicy::KeepBoth,
+        ] {
+            let mut mempool = Mempool::new(policy, DEFAULT_MAX_MEMPOOL_BYTES);
+
+            let tx_1 = create_mock_transaction(6);
+            let tx_2 = create_mock_transaction(4);
+            mempool.add_transaction(tx_1.clone(), 10).unwrap();
+            mempool.add_transaction(tx_2.clone(), 10).unwrap();
+
+            assert_eq!(mempool.get_transactions(), vec![tx_1, tx_2]);
+        }
+    }
+
+    #[test]
+    fn should_evict_the_biggest_transactions_past_the_byte_budget() {
+        let small_tx = create_mock_transaction_with_data(1, 10);
+        let big_tx = create_mock_transaction_with_data(2, 50);
+        let medium_tx = create_mock_transaction_with_data(3, 30);
+        let new_tx = create_mock_transaction_with_data(4, 20);
+
+        // the budget fits the three first transactions, but not the new one as well
+        let max_bytes = small_tx.weight() + big_tx.weight() + medium_tx.weight();
+        let mut mempool = Mempool::new(MempoolConflictPolicy::KeepBoth, max_bytes);
+        mempool.add_transaction(small_tx.clone(), BALANCE).unwrap();
+        mempool.add_transaction(big_tx, BALANCE).unwrap();
+        mempool.add_transaction(medium_tx.clone(), BALANCE).unwrap();
+        assert_eq!(mempool.total_bytes, max_bytes);
+
+        // only the biggest one is evicted, as it leaves enough room
+        mempool.add_transaction(new_tx.clone(), BALANCE).unwrap();
+        assert_eq!(
+            mempool.get_transactions(),
+            vec![small_tx.clone(), medium_tx.clone(), new_tx.clone()]
+        );
+        assert_eq!(
+            mempool.total_bytes,
+            small_tx.weight() + medium_tx.weight() + new_tx.weight()
+        );
+    }
+
+    #[test]
+    fn should_reject_the_biggest_transaction_when_full() {
+        let tx_1 = create_mock_transaction_with_data(1, 10);
+        let tx_2 = create_mock_transaction_with_data(2, 10);
+        let mut mempool = Mempool::new(MempoolConflictPolicy::KeepBoth, tx_1.weight());
+        mempool.add_transaction(tx_1.clone(), BALANCE).unwrap();
+
+        // a transaction of the same size does not evict the pending one
+        let err = mempool.add_transaction(tx_2, BALANCE).unwrap_err();
+        assert_eq!(err, MempoolError::MempoolFull);
+        assert_eq!(mempool.get_transactions(), vec![tx_1]);
+    }
+
     fn create_mock_transaction(amount: u64) -> Transaction {
         Transaction {
+            version: FORMAT_VERSION,
             sender: Address::default(),
             recipient: Address::default(),
-            amount: amount,
+            amount,
+            data: vec![],
+        }
+    }
+
+    fn create_mock_transaction_with_data(amount: u64, data_size: usize) -> Transaction {
+        Transaction {
+            data: vec![0; data_size],
+            ..create_mock_transaction(amount)
         }
     }
 }
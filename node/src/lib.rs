@@ -4,5 +4,8 @@ extern crate log;
 pub mod api;
 pub mod database;
 pub mod peer;
+pub mod reconciler;
 pub mod server;
+pub mod stats;
 pub mod util;
+pub mod webhook;
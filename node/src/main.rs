@@ -4,30 +4,58 @@ extern crate log;
 mod api;
 mod database;
 mod peer;
+mod reconciler;
 mod server;
+mod stats;
 mod util;
+mod webhook;
+
+use std::sync::Arc;
 
 use env_logger::{Builder, Target};
 use log::LevelFilter;
 
-use crate::util::config::parse_from_cli;
+use crate::util::{
+    config::{parse_from_cli, NodeCommand},
+    shutdown::ShutdownCoordinator,
+};
 
 use crate::server::Server;
 
 fn main() {
+    // read the configuration from the command line
+    let config = parse_from_cli();
+
+    // commands talk to a running node and exit, without starting a new one
+    if let Some(NodeCommand::Stats { node_url }) = &config.command {
+        print_chain_stats(node_url);
+        return;
+    }
+
     // set up the logging system
     initialize_logger();
     info!("starting up");
 
-    // read the configuration from the command line
-    let config = parse_from_cli();
+    let server = Server::new(config);
+
+    // when user inputs Ctrl-C (or the process is terminated), shut down the subsystems and exit
+    set_ctrlc_handler(server.shutdown.clone());
 
     // run the server
-    let server = Server::new(config);
     server.start();
+}
 
-    // when user inputs Ctrl-C, terminate the program
-    set_ctrlc_handler();
+fn print_chain_stats(node_url: &str) {
+    match stats::fetch_chain_stats(node_url) {
+        Ok(chain_stats) => print!("{}", stats::format_chain_stats(&chain_stats)),
+        Err(error) => {
+            eprintln!(
+                "Could not get the stats of the node at {}: {}",
+                node_url, error
+            );
+            std::process::exit(1);
+        }
+    }
 }
 
 fn initialize_logger() {
@@ -37,8 +65,9 @@ fn initialize_logger() {
     builder.init();
 }
 
-pub fn set_ctrlc_handler() {
+pub fn set_ctrlc_handler(shutdown: Arc<ShutdownCoordinator>) {
     ctrlc::set_handler(move || {
+        shutdown.shutdown();
         std::process::exit(0);
     })
     .expect("Error setting Ctrl-C handler");
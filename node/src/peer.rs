@@ -1,33 +1,95 @@
+pub mod clock;
+pub mod stats;
+pub mod tcp;
+
+use self::{
+    clock::{estimate_clock_skew, NodeTime},
+    stats::ConcurrentPeerStats,
+};
 use crate::{
-    database::ConcurrentNodeDatabase,
-    util::{
-        config::Config,
-        execution::{sleep_millis, Runnable},
+    database::{
+        is_already_known_transaction, BlockLocator, ChainTip, ConcurrentNodeDatabase,
+        NodeDatabaseError, TipHeader,
     },
+    util::{config::Config, execution::Runnable},
 };
 use anyhow::Result;
-use isahc::{ReadResponseExt, Request};
-use spec::{types::Block, Database as SpecDatabase};
-use std::panic;
+use chrono::Utc;
+use isahc::{config::Configurable, http::header::CONTENT_LENGTH, Body, Request};
+use rand::seq::SliceRandom;
+use serde::de::DeserializeOwned;
+use spec::{
+    types::{hash::ConsensusHash, Block, Transaction},
+    Database as SpecDatabase,
+};
+use std::{
+    collections::HashSet,
+    io::Read,
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc::Receiver,
+    time::Duration,
+};
+use thiserror::Error;
+
+#[derive(Error, PartialEq, Eq, Debug)]
+pub enum PeerRequestError {
+    #[error("Peer did not respond: {0}")]
+    Unreachable(String),
+
+    #[error("Peer responded with status {0}")]
+    UnexpectedStatus(u16),
+
+    #[error("Malformed response: {0}")]
+    MalformedResponse(String),
+
+    #[error("Response exceeds the limit of {0} bytes")]
+    ResponseTooLarge(u64),
+}
+
+// Number of times that an invalid window of blocks is requested again before giving up on a peer
+const MAX_WINDOW_RETRIES: u32 = 3;
+
+// Size past which the small responses of a peer (i.e. its time or its tip) are discarded
+const MAX_SMALL_RESPONSE_BYTES: u64 = 16 * 1024;
 
 pub struct Peer {
     peer_addresses: Vec<String>,
     peer_sync_ms: u64,
+    sync_window_size: u64,
+    max_response_bytes: u64,
+    max_clock_skew_ms: u64,
+    max_peer_backoff_ms: u64,
+    block_fanout: usize,
     database: ConcurrentNodeDatabase,
+    stats: ConcurrentPeerStats,
 }
 
 impl Runnable for Peer {
+    fn name(&self) -> &str {
+        "peer sync"
+    }
+
     fn run(&self) -> Result<()> {
         self.start()
     }
 }
 
 impl Peer {
-    pub fn new(config: &Config, database: &ConcurrentNodeDatabase) -> Peer {
+    pub fn new(
+        config: &Config,
+        database: &ConcurrentNodeDatabase,
+        stats: &ConcurrentPeerStats,
+    ) -> Peer {
         Peer {
             peer_addresses: config.peers.clone(),
             peer_sync_ms: config.peer_sync_ms,
+            sync_window_size: config.sync_window_size,
+            max_response_bytes: config.max_peer_response_bytes,
+            max_clock_skew_ms: config.max_clock_skew_ms,
+            max_peer_backoff_ms: config.max_peer_backoff_ms,
+            block_fanout: config.block_fanout,
             database: database.clone(),
+            stats: stats.clone(),
         }
     }
 
@@ -42,129 +104,516 @@ impl Peer {
             self.peer_addresses.join(", ")
         );
 
-        // At regular intervals of time, we try to sync new blocks from our peers
+        // We try to sync with our peers at regular intervals of time,
+        // or as soon as a new block is added so it gets propagated without delay
+        let tip_changes = self.database.subscribe_tip_changes();
         let mut last_sent_block_index = None;
+        let mut relayed_transaction_ids = HashSet::new();
+        let mut handshaken_peers = HashSet::new();
         loop {
+            self.try_handshake_peers(&mut handshaken_peers, Peer::request_time_from_peer);
             self.try_receive_new_blocks();
-            last_sent_block_index = self.try_send_new_blocks_since(last_sent_block_index);
-            sleep_millis(self.peer_sync_ms);
+            last_sent_block_index =
+                self.try_send_new_blocks_since(last_sent_block_index, Peer::send_block_to_peer);
+            self.try_relay_transactions(&mut relayed_transaction_ids);
+            self.wait_for_next_sync(&tip_changes);
         }
     }
 
-    // Retrieve new blocks from all peers and add them to the blockchain
-    fn try_receive_new_blocks(&self) {
+    // Block until the tip changes or the sync interval elapses, returning whether the tip changed
+    fn wait_for_next_sync(&self, tip_changes: &Receiver<TipHeader>) -> bool {
+        let timeout = Duration::from_millis(self.peer_sync_ms);
+        let has_new_blocks = tip_changes.recv_timeout(timeout).is_ok();
+
+        // many blocks may have been added at once, all of them are handled in the same sync
+        while tip_changes.try_recv().is_ok() {}
+
+        has_new_blocks
+    }
+
+    // Exchange the current time with the peers that did not complete the handshake yet,
+    // so peers with a misconfigured clock are detected before wasting effort on their blocks
+    // `request_time` returns the current time of a peer, or None if the peer did not respond
+    fn try_handshake_peers<F>(&self, handshaken_peers: &mut HashSet<String>, request_time: F)
+    where
+        F: Fn(&str) -> Option<NodeTime>,
+    {
         for address in self.peer_addresses.iter() {
-            let new_blocks = self.get_new_blocks_from_peer(address);
+            if handshaken_peers.contains(address) {
+                continue;
+            }
+
+            // unresponsive peers are handled by the sync, the handshake is retried on the next one
+            let sent_at = NodeTime::now();
+            let peer_time = match request_time(address) {
+                Some(value) => value,
+                None => continue,
+            };
+            let received_at = NodeTime::now();
+
+            let clock_skew_ms = estimate_clock_skew(sent_at, peer_time, received_at);
+            self.check_clock_skew(address, clock_skew_ms);
+            handshaken_peers.insert(address.clone());
+        }
+    }
+
+    // Flag the peer if its clock is too far from ours, returning whether it was flagged
+    fn check_clock_skew(&self, address: &str, clock_skew_ms: i64) -> bool {
+        let is_excessive = clock_skew_ms.unsigned_abs() > self.max_clock_skew_ms;
+        if is_excessive {
+            warn!(
+                "Clock of peer {} is off by {} ms, it will be synced last",
+                address, clock_skew_ms
+            );
+        }
+        self.stats
+            .set_clock_skew(address, clock_skew_ms, is_excessive);
+
+        is_excessive
+    }
+
+    // Peers that keep failing are contacted less often, doubling the wait after each consecutive failure,
+    // and they are contacted on every sync again as soon as they respond
+    fn is_peer_due(&self, address: &str, now: i64) -> bool {
+        let stats = self.stats.get(address);
+        let last_failed_request = match stats.last_failed_request {
+            Some(value) if stats.consecutive_failures > 0 => value,
+            _ => return true,
+        };
+
+        let backoff_ms = get_backoff_ms(
+            self.peer_sync_ms,
+            self.max_peer_backoff_ms,
+            stats.consecutive_failures,
+        );
+        now.saturating_sub(last_failed_request) >= backoff_ms as i64
+    }
+
+    // Peers that are not backing off after failures, in their configured order
+    fn get_reachable_peers(&self) -> Vec<&String> {
+        let now = Utc::now().timestamp_millis();
+        self.peer_addresses
+            .iter()
+            .filter(|address| self.is_peer_due(address, now))
+            .collect()
+    }
+
+    // New blocks are only announced to a random subset of the reachable peers when there is a fan-out,
+    // as each peer relays them to its own peers in turn
+    fn get_block_announcement_peers(&self) -> Vec<&String> {
+        let mut addresses = self.get_reachable_peers();
+        if self.block_fanout > 0 && self.block_fanout < addresses.len() {
+            addresses.shuffle(&mut rand::thread_rng());
+            addresses.truncate(self.block_fanout);
+        }
+
+        addresses
+    }
+
+    // Peers with an excessive clock skew go last, the rest keep their configured order
+    fn get_prioritized_peers(&self) -> Vec<&String> {
+        let mut addresses = self.get_reachable_peers();
+        addresses.sort_by_key(|address| self.stats.has_excessive_clock_skew(address));
+
+        addresses
+    }
+
+    // Retrieve new blocks from the peers ahead of us and add them to the blockchain
+    fn try_receive_new_blocks(&self) {
+        for address in self.select_peers_to_sync(Peer::request_tip_from_peer) {
+            let request_window = |from, limit| {
+                Peer::request_blocks_from_peer(address, from, limit, self.max_response_bytes)
+            };
+            match self.receive_blocks_in_windows(address, request_window) {
+                Ok(_) => {}
+                // the peer is on a competing branch, so only the blocks after the last one in common are requested
+                Err(NodeDatabaseError::ChainDiverged) => {
+                    let request_blocks_after = |locator: &[ConsensusHash]| {
+                        Peer::request_blocks_after_locator(
+                            address,
+                            locator,
+                            self.sync_window_size,
+                            self.max_response_bytes,
+                        )
+                    };
+                    self.receive_diverged_blocks(address, request_blocks_after);
+                }
+                Err(error) => error!("Could not sync with peer {}: {}", address, error),
+            }
+        }
+    }
+
+    // The node does not switch branches, so the blocks of a competing branch are not added to our chain,
+    // but tracked as a fork (see GET /forks), returning how many blocks of the branch were not tracked yet
+    // `request_blocks_after` returns the blocks of the peer after the last one in common with the locator
+    fn receive_diverged_blocks<F>(&self, address: &str, request_blocks_after: F) -> usize
+    where
+        F: Fn(&[ConsensusHash]) -> Result<Vec<Block>, PeerRequestError>,
+    {
+        let locator = self.database.get_block_locator();
+        let peer_blocks = match request_blocks_after(&locator) {
+            Ok(blocks) => blocks,
+            Err(error) => {
+                self.record_request_error(address, &error);
+                return 0;
+            }
+        };
+
+        // a branch that we already track is skipped, as the peer keeps diverging on every sync
+        let new_blocks: Vec<_> = peer_blocks
+            .into_iter()
+            .filter(|block| !self.database.is_side_block(&block.hash))
+            .collect();
+        if new_blocks.is_empty() {
+            info!("Skipped peer {} as its branch is already tracked", address);
+            return 0;
+        }
+
+        // the blocks that do not follow our tip are tracked by the database when they fail to be appended
+        for block in new_blocks.iter() {
+            let _ = self.database.append_block(block);
+        }
+        info!(
+            "Peer {} diverged from our chain, received {} blocks of its branch",
+            address,
+            new_blocks.len()
+        );
+
+        new_blocks.len()
+    }
+
+    // Peers whose tip is not ahead of ours are skipped, instead of downloading blocks only to discard them
+    // Peers that do not tell their tip (e.g. an empty chain or an older version) are always synced
+    // `request_tip` returns the tip of a peer, or None if the peer did not respond
+    fn select_peers_to_sync<F>(&self, request_tip: F) -> Vec<&String>
+    where
+        F: Fn(&str) -> Option<ChainTip>,
+    {
+        let num_blocks = self.database.get_block_count();
+        self.get_prioritized_peers()
+            .into_iter()
+            .filter(|address| match request_tip(address) {
+                Some(peer_tip) if peer_tip.index >= num_blocks => true,
+                // we already have all the blocks of the peer, so it counts as synced
+                Some(_) => {
+                    self.stats.set_successful_sync(address);
+                    false
+                }
+                None => true,
+            })
+            .collect()
+    }
+
+    // Download the new blocks of a peer in windows of limited size, adding each window before requesting
+    // the next one, so memory usage is bounded and the progress is kept if a later window fails
+    // `request_window` returns up to `limit` blocks starting at `from`, or why they could not be retrieved
+    fn receive_blocks_in_windows<F>(
+        &self,
+        address: &str,
+        mut request_window: F,
+    ) -> Result<(), NodeDatabaseError>
+    where
+        F: FnMut(u64, u64) -> Result<Vec<Block>, PeerRequestError>,
+    {
+        let mut num_retries = 0;
+        loop {
+            let from = self.database.get_block_count();
+            let peer_blocks = match request_window(from, self.sync_window_size) {
+                Ok(blocks) => {
+                    self.stats.set_successful_sync(address);
+                    blocks
+                }
+                Err(error) => {
+                    self.record_request_error(address, &error);
+                    return Ok(());
+                }
+            };
+
+            let new_blocks = self.filter_new_blocks(peer_blocks)?;
+            if new_blocks.is_empty() {
+                return Ok(());
+            }
+
+            let num_added_blocks = self.add_new_blocks(&new_blocks);
+            self.stats.add_blocks_received(address, num_added_blocks);
+
+            // an invalid window is requested again from the first block that could not be added
+            if num_added_blocks < new_blocks.len() as u64 {
+                num_retries += 1;
+                if num_retries > MAX_WINDOW_RETRIES {
+                    error!("Giving up on invalid blocks from peer {}", address);
+                    return Ok(());
+                }
+            } else {
+                num_retries = 0;
+            }
+        }
+    }
 
-            if !new_blocks.is_empty() {
-                self.add_new_blocks(&new_blocks);
+    // Malformed responses are not just a peer being down, but most likely an incompatible version,
+    // so they are reported louder and counted apart in the peer stats, as well as oversized ones
+    fn record_request_error(&self, address: &str, error: &PeerRequestError) {
+        match error {
+            PeerRequestError::MalformedResponse(_) | PeerRequestError::ResponseTooLarge(_) => {
+                warn!("Peer {} sent a malformed response: {}", address, error);
+                self.stats.add_malformed_response(address);
             }
+            _ => self.stats.add_failed_request(address),
         }
     }
 
-    // Try to add a bunch of new blocks to our blockchain
-    fn add_new_blocks(&self, new_blocks: &[Block]) {
+    // Try to add a bunch of new blocks to our blockchain, returning how many were added
+    fn add_new_blocks(&self, new_blocks: &[Block]) -> u64 {
+        let mut num_added_blocks = 0;
         for block in new_blocks.iter() {
             let result = self.database.append_block(block);
 
-            // if a block is invalid, no point in trying to add the next ones
-            if result.is_err() {
-                error!("Could not add peer block {} to the blockchain", block.index);
-                return;
+            if let Err(error) = result {
+                // a block that we already have is not an error, so we just skip it
+                if let Some(NodeDatabaseError::BlockAlreadyExists) = error.downcast_ref() {
+                    info!("Skipped peer block {} as we already have it", block.index);
+                    continue;
+                }
+
+                // if a block is invalid, no point in trying to add the next ones
+                error!(
+                    "Could not add peer block {} to the blockchain: {}",
+                    block.index, error
+                );
+                break;
             }
 
             info!("Added new peer block {} to the blockchain", block.index);
+            num_added_blocks += 1;
         }
+
+        num_added_blocks
     }
 
-    // Retrieve only the new blocks from a peer
-    fn get_new_blocks_from_peer(&self, address: &str) -> Vec<Block> {
+    // Keep only the blocks from the peer that we do not have yet
+    fn filter_new_blocks(&self, peer_blocks: Vec<Block>) -> Result<Vec<Block>, NodeDatabaseError> {
+        // a peer with a different genesis block belongs to an incompatible network
+        if let Some(peer_genesis) = peer_blocks.iter().find(|block| block.index == 0) {
+            self.database.check_genesis(peer_genesis)?;
+        }
+
         // we need to know the next block index to ask
-        // FIXME: we should use a u64 range to avoid overflows
-        let next_index = match self.database.get_tip_block() {
-            Some(block) => block.index + 1,
-            None => 0,
-        };
+        let next_index = self.database.get_block_count();
+        let new_blocks = select_new_blocks(peer_blocks, next_index);
+
+        // the next block of a peer on a competing branch does not follow our tip
+        let tip_hash = self.database.get_tip_block().map(|block| block.hash);
+        if let (Some(first_block), Some(tip_hash)) = (new_blocks.first(), tip_hash) {
+            if first_block.index == next_index && first_block.previous_hash != tip_hash {
+                return Err(NodeDatabaseError::ChainDiverged);
+            }
+        }
 
-        // we retrieve all the blocks from the peer
-        let peer_blocks = self.get_blocks_from_peer(address);
-        let peer_last_index = match peer_blocks.last() {
-            Some(block) => block.index,
-            None => return vec![],
+        Ok(new_blocks)
+    }
+
+    // Request the blocks of a peer after the last one in common with our locator
+    fn request_blocks_after_locator(
+        address: &str,
+        locator: &[ConsensusHash],
+        limit: u64,
+        max_bytes: u64,
+    ) -> Result<Vec<Block>, PeerRequestError> {
+        let uri = format!("{}/blocks/locate", address);
+        let block_locator = BlockLocator {
+            hashes: locator.to_vec(),
+            limit: Some(limit),
         };
+        let body = serde_json::to_string(&block_locator).unwrap();
+        let request = Request::post(uri)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .map_err(|error| PeerRequestError::Unreachable(error.to_string()))?;
 
-        // exit if the peer do not have blocks that we don't have
-        if peer_last_index < next_index {
-            return vec![];
-        }
+        request_json(request, max_bytes)
+    }
 
-        // The peer do have new blocks, and we return ONLY the new ones
-        let first_new = next_index as usize;
-        let last_new = peer_last_index as usize;
-        let new_blocks_range = first_new..=last_new;
-        peer_blocks
-            .get(new_blocks_range)
-            .unwrap_or_default()
-            .to_vec()
+    // Request the current time of a peer, returning None if the peer did not respond properly
+    fn request_time_from_peer(address: &str) -> Option<NodeTime> {
+        let uri = format!("{}/time", address);
+        request_json(Request::get(uri).body(()).ok()?, MAX_SMALL_RESPONSE_BYTES).ok()
     }
 
-    // Retrieve ALL blocks from a peer
-    // if the peer is not responsive, we ignore it and return and empty vector
-    fn get_blocks_from_peer(&self, address: &str) -> Vec<Block> {
-        let uri = format!("{}/blocks", address);
-        let default_value = vec![];
+    // Request the header of the tip of a peer, returning None if the peer did not respond properly
+    fn request_tip_from_peer(address: &str) -> Option<ChainTip> {
+        let uri = format!("{}/tip", address);
+        request_json(Request::get(uri).body(()).ok()?, MAX_SMALL_RESPONSE_BYTES).ok()
+    }
 
-        let mut response = match isahc::get(uri) {
-            Ok(value) => value,
-            Err(_) => return default_value,
-        };
+    // Request a range of blocks from a peer
+    fn request_blocks_from_peer(
+        address: &str,
+        from: u64,
+        limit: u64,
+        max_bytes: u64,
+    ) -> Result<Vec<Block>, PeerRequestError> {
+        let uri = format!("{}/blocks?from={}&limit={}", address, from, limit);
 
-        // check that the response is sucessful
-        if response.status().as_u16() != 200 {
-            return default_value;
-        }
+        // the range of blocks can be big, so we ask the peer to compress it
+        // the response body is transparently decompressed
+        let request = Request::get(uri)
+            .automatic_decompression(true)
+            .body(())
+            .map_err(|error| PeerRequestError::Unreachable(error.to_string()))?;
 
-        // parse and return the list of blocks from the response body
-        let raw_body = response.text().unwrap_or_default();
-        serde_json::from_str(&raw_body).unwrap_or_default()
+        request_json(request, max_bytes)
     }
 
     // Try to broadcast all new blocks to peers since last time we broadcasted
-    fn try_send_new_blocks_since(&self, last_send_block_index: Option<u64>) -> Option<u64> {
+    // `send_block` sends a block to a peer, see `send_block_to_peer`
+    fn try_send_new_blocks_since<F>(
+        &self,
+        last_send_block_index: Option<u64>,
+        send_block: F,
+    ) -> Option<u64>
+    where
+        F: Fn(&str, &Block) -> Option<bool>,
+    {
         let new_blocks = self.get_new_blocks_since(last_send_block_index);
 
         for block in new_blocks.iter() {
-            for address in self.peer_addresses.iter() {
+            for address in self.get_block_announcement_peers() {
                 // we don't want to panic if one peer is down or not working properly
-                let result = panic::catch_unwind(|| {
-                    Peer::send_block_to_peer(address, block);
-                });
+                let result = panic::catch_unwind(AssertUnwindSafe(|| send_block(address, block)));
 
-                if result.is_err() {
-                    error!("Could not send block {} to peer {}", block.index, address);
-                    break;
+                match result {
+                    Ok(Some(true)) => {
+                        self.stats.add_block_sent(address);
+                        info!("Sent new block {} to peer {}", block.index, address);
+                    }
+                    Ok(Some(false)) => {
+                        info!("Peer {} did not accept block {}", address, block.index);
+                    }
+                    _ => {
+                        self.stats.add_failed_request(address);
+                        error!("Could not send block {} to peer {}", block.index, address);
+                    }
                 }
-
-                info!("Sended new block {} to peer {}", block.index, address);
             }
         }
 
         // return the index of the last new block
-        new_blocks.last().map(|block| block.index)
+        match new_blocks.last() {
+            Some(block) => Some(block.index),
+            None => last_send_block_index,
+        }
+    }
+
+    // Exchange mempool transactions with all peers, so they can be mined by the miners of any node
+    fn try_relay_transactions(&self, relayed_ids: &mut HashSet<ConsensusHash>) {
+        self.try_receive_new_transactions();
+        self.try_send_new_transactions(relayed_ids, Peer::send_transaction_to_peer);
+    }
+
+    // Add the mempool transactions of all peers that we do not have yet
+    fn try_receive_new_transactions(&self) {
+        for address in self.get_reachable_peers() {
+            let peer_transactions =
+                match Peer::request_transactions_from_peer(address, self.max_response_bytes) {
+                    Ok(value) => value,
+                    Err(error) => {
+                        self.record_request_error(address, &error);
+                        continue;
+                    }
+                };
+
+            for transaction in peer_transactions {
+                let id = transaction.id();
+                match self.database.add_mempool_transaction(transaction) {
+                    Ok(_) => info!("Added transaction {} from peer {}", id, address),
+                    Err(error) => {
+                        // most of the time we already know the transaction, so it's not worth logging
+                        if is_already_known_transaction(&error) {
+                            continue;
+                        }
+                        info!(
+                            "Skipped transaction {} from peer {}: {}",
+                            id, address, error
+                        );
+                    }
+                }
+            }
+        }
     }
 
-    // Return all new blocks added to the blockchain since the one with the indicated index
+    // Send to all peers the mempool transactions that were not relayed yet
+    // Each transaction is only sent once, so relayed transactions do not bounce between peers forever
+    // `send_transaction` sends a transaction to a peer, see `send_transaction_to_peer`
+    fn try_send_new_transactions<F>(
+        &self,
+        relayed_ids: &mut HashSet<ConsensusHash>,
+        send_transaction: F,
+    ) where
+        F: Fn(&str, &Transaction) -> Option<bool>,
+    {
+        let transactions = self.database.get_mempool_transactions();
+
+        for transaction in transactions.iter() {
+            let id = transaction.id();
+            if relayed_ids.contains(&id) {
+                continue;
+            }
+
+            for address in self.get_reachable_peers() {
+                if send_transaction(address, transaction).is_none() {
+                    self.stats.add_failed_request(address);
+                    error!("Could not send transaction {} to peer {}", id, address);
+                }
+            }
+            relayed_ids.insert(id);
+        }
+
+        // transactions that left the mempool (e.g. included in a block) do not need to be tracked anymore
+        let mempool_ids: HashSet<ConsensusHash> = transactions.iter().map(|t| t.id()).collect();
+        relayed_ids.retain(|id| mempool_ids.contains(id));
+    }
+
+    // Request the mempool transactions of a peer
+    fn request_transactions_from_peer(
+        address: &str,
+        max_bytes: u64,
+    ) -> Result<Vec<Transaction>, PeerRequestError> {
+        let uri = format!("{}/transactions", address);
+        let request = Request::get(uri)
+            .body(())
+            .map_err(|error| PeerRequestError::Unreachable(error.to_string()))?;
+
+        request_json(request, max_bytes)
+    }
+
+    // Send a transaction to a peer using the REST API of the peer
+    // Returns None if the peer is unresponsive, otherwise whether the peer accepted the transaction
+    fn send_transaction_to_peer(address: &str, transaction: &Transaction) -> Option<bool> {
+        let uri = format!("{}/transactions", address);
+        let body = serde_json::to_string(&transaction).unwrap();
+
+        let request = Request::post(uri)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .ok()?;
+
+        let response = isahc::send(request).ok()?;
+        Some(response.status().is_success())
+    }
+
+    // Return all new blocks added to the blockchain after the one with the indicated index
     fn get_new_blocks_since(&self, start_index: Option<u64>) -> Vec<Block> {
         let iter = self.database.get_all_blocks().into_iter();
 
         match start_index {
-            Some(index) => iter.skip(index as usize).collect(),
+            Some(index) => iter.skip(index as usize + 1).collect(),
             None => iter.collect(),
         }
     }
 
     // Send a block to a peer using the REST API of the peer
-    fn send_block_to_peer(address: &str, block: &Block) {
+    // Returns None if the peer is unresponsive, otherwise whether the peer accepted the block
+    fn send_block_to_peer(address: &str, block: &Block) -> Option<bool> {
         let uri = format!("{}/blocks", address);
         let body = serde_json::to_string(&block).unwrap();
 
@@ -173,7 +622,593 @@ impl Peer {
             .body(body)
             .unwrap();
 
-        // Ignore unresponsive peers
-        let _response = isahc::send(request);
+        let response = isahc::send(request).ok()?;
+        Some(response.status().is_success())
+    }
+}
+
+// Send a request to the REST API of a peer, parsing the JSON body of a successful response
+// The response is dropped as soon as its body exceeds `max_bytes`, so a peer can not exhaust the memory
+fn request_json<T: DeserializeOwned, B: Into<Body>>(
+    request: Request<B>,
+    max_bytes: u64,
+) -> Result<T, PeerRequestError> {
+    let response =
+        isahc::send(request).map_err(|error| PeerRequestError::Unreachable(error.to_string()))?;
+
+    if response.status().as_u16() != 200 {
+        return Err(PeerRequestError::UnexpectedStatus(
+            response.status().as_u16(),
+        ));
+    }
+
+    // the declared length is checked first, so the body is not even downloaded
+    let content_length = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > max_bytes) {
+        return Err(PeerRequestError::ResponseTooLarge(max_bytes));
+    }
+
+    let body = read_limited_body(response.into_body(), max_bytes)?;
+    parse_json_body(&body)
+}
+
+// Read a body up to `max_bytes`, failing as soon as there are more (e.g. compressed or chunked bodies)
+fn read_limited_body(body: impl Read, max_bytes: u64) -> Result<Vec<u8>, PeerRequestError> {
+    let mut buffer = vec![];
+    body.take(max_bytes.saturating_add(1))
+        .read_to_end(&mut buffer)
+        .map_err(|error| PeerRequestError::Unreachable(error.to_string()))?;
+
+    if buffer.len() as u64 > max_bytes {
+        return Err(PeerRequestError::ResponseTooLarge(max_bytes));
+    }
+
+    Ok(buffer)
+}
+
+// A body that is not UTF-8 or does not match the expected type is an error, never an empty value
+fn parse_json_body<T: DeserializeOwned>(body: &[u8]) -> Result<T, PeerRequestError> {
+    let raw_body = std::str::from_utf8(body)
+        .map_err(|error| PeerRequestError::MalformedResponse(error.to_string()))?;
+
+    serde_json::from_str(raw_body)
+        .map_err(|error| PeerRequestError::MalformedResponse(error.to_string()))
+}
+
+// Time to wait before contacting a peer again, doubling the sync interval after each consecutive failure
+fn get_backoff_ms(peer_sync_ms: u64, max_backoff_ms: u64, consecutive_failures: u32) -> u64 {
+    let multiplier = 2_u64.saturating_pow(consecutive_failures);
+    peer_sync_ms.saturating_mul(multiplier).min(max_backoff_ms)
+}
+
+// Return ONLY the blocks that we do not have yet
+// The blocks are selected by their index instead of their position,
+// so it works with any range of blocks returned by the peer
+fn select_new_blocks(peer_blocks: Vec<Block>, next_index: u64) -> Vec<Block> {
+    peer_blocks
+        .into_iter()
+        .filter(|block| block.index >= next_index)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io, ops::RangeInclusive, thread, time::Instant};
+
+    use spec::{
+        types::{
+            hash::{ConsensusHash, ConsensusHashable},
+            Address, Network, PowAlgorithm, Transaction,
+        },
+        validators::BLOCK_SUBSIDY,
+    };
+
+    use super::*;
+    use crate::{
+        database::DatabaseOptions,
+        util::config::{MempoolConflictPolicy, PeerTransport},
+    };
+
+    #[test]
+    fn should_skip_duplicated_blocks_and_keep_syncing() {
+        let network = Network::test_default();
+        let database = ConcurrentNodeDatabase::new(network.clone(), DatabaseOptions::default());
+        let peer = create_mock_peer(&database);
+
+        // we already have the genesis block
+        let genesis = Block::new(0, 0, network.consensus_hash(), vec![create_coinbase()]);
+        database.append_block(&genesis).unwrap();
+
+        // the peer sends us the genesis block again, followed by a new one
+        let new_block = Block::new(1, 0, genesis.hash.clone(), vec![create_coinbase()]);
+        peer.add_new_blocks(&[genesis, new_block.clone()]);
+
+        // the duplicated block is skipped, but the new one is still added
+        let blocks = database.get_all_blocks();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(database.get_tip_block().unwrap(), new_block);
+    }
+
+    #[test]
+    fn should_sync_in_windows() {
+        let network = Network::test_default();
+        let database = ConcurrentNodeDatabase::new(network.clone(), DatabaseOptions::default());
+        let peer = create_mock_peer(&database);
+        let peer_chain = create_mock_chain(&network, 1000);
+
+        let mut requested_windows = vec![];
+        peer.receive_blocks_in_windows("peer", |from, limit| {
+            requested_windows.push((from, limit));
+            Ok(get_window(&peer_chain, from, limit))
+        })
+        .unwrap();
+
+        // the whole chain is downloaded in windows of 100 blocks, until the peer has no more blocks
+        assert_eq!(database.get_all_blocks(), peer_chain);
+        let expected_windows: Vec<(u64, u64)> = (0..=10).map(|i| (i * 100, 100)).collect();
+        assert_eq!(requested_windows, expected_windows);
+    }
+
+    #[test]
+    fn should_retry_a_corrupted_window() {
+        let network = Network::test_default();
+        let database = ConcurrentNodeDatabase::new(network.clone(), DatabaseOptions::default());
+        let peer = create_mock_peer(&database);
+        let peer_chain = create_mock_chain(&network, 1000);
+
+        // the first time, a block in the middle of a window is corrupted
+        let mut requested_froms = vec![];
+        peer.receive_blocks_in_windows("peer", |from, limit| {
+            let mut window = get_window(&peer_chain, from, limit);
+            if from == 300 && !requested_froms.contains(&from) {
+                window[50].nonce += 1;
+            }
+            requested_froms.push(from);
+            Ok(window)
+        })
+        .unwrap();
+
+        // the valid blocks of the window are kept, and the rest are requested again
+        assert!(requested_froms.contains(&350));
+        assert_eq!(database.get_all_blocks(), peer_chain);
+    }
+
+    #[test]
+    fn should_give_up_on_a_peer_with_invalid_blocks() {
+        let network = Network::test_default();
+        let database = ConcurrentNodeDatabase::new(network.clone(), DatabaseOptions::default());
+        let peer = create_mock_peer(&database);
+        let peer_chain = create_mock_chain(&network, 200);
+
+        // the peer always sends an invalid block at the same position
+        let mut num_requests = 0;
+        peer.receive_blocks_in_windows("peer", |from, limit| {
+            num_requests += 1;
+            let mut window = get_window(&peer_chain, from, limit);
+            if let Some(block) = window.iter_mut().find(|block| block.index == 150) {
+                block.nonce += 1;
+            }
+            Ok(window)
+        })
+        .unwrap();
+
+        // the previous blocks are added, and the invalid window is only retried a few times
+        assert_eq!(database.get_block_count(), 150);
+        assert_eq!(num_requests, 2 + MAX_WINDOW_RETRIES);
+    }
+
+    #[test]
+    fn should_only_receive_the_blocks_after_the_common_ancestor_of_a_diverged_peer() {
+        let network = Network::test_default();
+        let database = ConcurrentNodeDatabase::new(network.clone(), DatabaseOptions::default());
+        let peer = create_mock_peer(&database);
+
+        // both chains share the first two blocks, and then each one has its own blocks
+        let peer_chain = create_mock_chain(&network, 6);
+        let peer_database = ConcurrentNodeDatabase::new(network, DatabaseOptions::default());
+        for block in peer_chain.iter() {
+            peer_database.append_block(block).unwrap();
+        }
+        let mut previous_hash = peer_chain[1].hash.clone();
+        for block in peer_chain[..2].iter() {
+            database.append_block(block).unwrap();
+        }
+        for index in 2..4 {
+            let coinbase = create_coinbase().with_data(vec![1]);
+            let block = Block::new(index, 0, previous_hash, vec![coinbase]);
+            database.append_block(&block).unwrap();
+            previous_hash = block.hash;
+        }
+
+        // the next blocks of the peer do not follow our tip
+        let err = peer
+            .receive_blocks_in_windows("peer", |from, limit| {
+                Ok(peer_database.get_blocks_range(from, limit))
+            })
+            .unwrap_err();
+        assert_eq!(err, NodeDatabaseError::ChainDiverged);
+
+        // so only the blocks after the common ancestor are requested
+        let received_blocks = RefCell::new(vec![]);
+        let num_blocks = peer.receive_diverged_blocks("peer", |locator| {
+            let blocks = peer_database.get_blocks_after_locator(locator, 100);
+            received_blocks.replace(blocks.clone());
+            Ok(blocks)
+        });
+        assert_eq!(num_blocks, 4);
+        assert_eq!(received_blocks.into_inner(), peer_chain[2..]);
+
+        // and tracked as a competing branch, while our chain stays the same
+        assert_eq!(database.get_block_count(), 4);
+        let peer_tip = database
+            .get_fork_tips()
+            .into_iter()
+            .find(|fork_tip| !fork_tip.main)
+            .unwrap();
+        assert_eq!(peer_tip.hash, peer_chain[5].hash);
+
+        // a branch already tracked is skipped on the next syncs
+        let num_blocks = peer.receive_diverged_blocks("peer", |locator| {
+            Ok(peer_database.get_blocks_after_locator(locator, 100))
+        });
+        assert_eq!(num_blocks, 0);
+    }
+
+    #[test]
+    fn should_report_peers_with_different_genesis() {
+        let network = Network::test_default();
+        let database = ConcurrentNodeDatabase::new(network.clone(), DatabaseOptions::default());
+        let peer = create_mock_peer(&database);
+
+        // we already have a genesis block
+        let genesis = Block::new(0, 0, network.consensus_hash(), vec![create_coinbase()]);
+        database.append_block(&genesis).unwrap();
+
+        // but the peer has a different one
+        let other_coinbase = create_coinbase().with_data(vec![1]);
+        let other_genesis = Block::new(0, 0, network.consensus_hash(), vec![other_coinbase]);
+        let other_block = Block::new(1, 0, other_genesis.hash.clone(), vec![create_coinbase()]);
+
+        let err = peer
+            .filter_new_blocks(vec![other_genesis, other_block])
+            .unwrap_err();
+        assert_eq!(err, NodeDatabaseError::GenesisMismatch);
+    }
+
+    #[test]
+    fn should_flag_peers_with_excessive_clock_skew_on_handshake() {
+        let database =
+            ConcurrentNodeDatabase::new(Network::test_default(), DatabaseOptions::default());
+        let mut peer = create_mock_peer(&database);
+        peer.max_clock_skew_ms = 60_000;
+        peer.peer_addresses = vec!["skewed".to_string(), "synced".to_string()];
+
+        // the first peer is two hours ahead, the second one is in sync with us
+        let mut handshaken_peers = HashSet::new();
+        peer.try_handshake_peers(&mut handshaken_peers, |address| {
+            let now = NodeTime::now();
+            match address {
+                "skewed" => Some(NodeTime {
+                    timestamp: now.timestamp + 2 * 60 * 60 * 1000,
+                }),
+                _ => Some(now),
+            }
+        });
+        assert_eq!(handshaken_peers.len(), 2);
+
+        // only the skewed peer is flagged, and it is synced after the other one
+        let all_stats = peer.stats.get_all();
+        assert!(all_stats["skewed"].excessive_clock_skew);
+        assert!(all_stats["skewed"].clock_skew_ms.unwrap() > 60_000);
+        assert!(!all_stats["synced"].excessive_clock_skew);
+        assert_eq!(peer.get_prioritized_peers(), vec!["synced", "skewed"]);
+
+        // the handshake is not repeated with peers that already completed it
+        peer.try_handshake_peers(&mut handshaken_peers, |_| unreachable!());
+    }
+
+    #[test]
+    fn should_penalize_peers_with_malformed_responses() {
+        let network = Network::test_default();
+        let database = ConcurrentNodeDatabase::new(network, DatabaseOptions::default());
+        let peer = create_mock_peer(&database);
+
+        // a body with a different schema is not mistaken for an empty list of blocks
+        peer.receive_blocks_in_windows("peer", |_, _| parse_json_body(br#"{"blocks": []}"#))
+            .unwrap();
+
+        let stats = peer.stats.get("peer");
+        assert_eq!(stats.malformed_responses, 1);
+        assert_eq!(stats.failed_requests, 1);
+        assert_eq!(stats.last_successful_sync, None);
+
+        // and neither is a body that is not UTF-8
+        let result = parse_json_body::<Vec<Block>>(&[0xff, 0xfe]);
+        assert!(matches!(
+            result,
+            Err(PeerRequestError::MalformedResponse(_))
+        ));
+    }
+
+    #[test]
+    fn should_reject_responses_beyond_the_size_limit() {
+        // a body within the limit is fully read
+        let body = read_limited_body(&b"[]"[..], 2).unwrap();
+        assert_eq!(body, b"[]");
+
+        // an endless body is only read up to the limit
+        let result = read_limited_body(io::repeat(b' '), 1024);
+        assert_eq!(result, Err(PeerRequestError::ResponseTooLarge(1024)));
+
+        // and the peer is penalized as for a malformed response
+        let network = Network::test_default();
+        let database = ConcurrentNodeDatabase::new(network, DatabaseOptions::default());
+        let peer = create_mock_peer(&database);
+        peer.receive_blocks_in_windows("peer", |_, _| {
+            read_limited_body(io::repeat(b' '), 1024).and_then(|body| parse_json_body(&body))
+        })
+        .unwrap();
+        assert_eq!(peer.stats.get("peer").malformed_responses, 1);
+    }
+
+    #[test]
+    fn should_only_sync_from_peers_ahead_of_us() {
+        let network = Network::test_default();
+        let database = ConcurrentNodeDatabase::new(network.clone(), DatabaseOptions::default());
+        let mut peer = create_mock_peer(&database);
+        peer.peer_addresses = vec![
+            "ahead".to_string(),
+            "synced".to_string(),
+            "unknown".to_string(),
+        ];
+
+        let genesis = Block::new(0, 0, network.consensus_hash(), vec![create_coinbase()]);
+        database.append_block(&genesis).unwrap();
+        let our_tip = database.get_chain_tip().unwrap();
+
+        // the first peer has more blocks, the second one has the same tip, the third one did not tell
+        let selected_peers = peer.select_peers_to_sync(|address| match address {
+            "ahead" => Some(ChainTip {
+                index: 5,
+                hash: ConsensusHash::default(),
+                total_work: our_tip.total_work * 6,
+            }),
+            "synced" => Some(our_tip.clone()),
+            _ => None,
+        });
+
+        assert_eq!(selected_peers, vec!["ahead", "unknown"]);
+        assert!(peer.stats.get_all()["synced"]
+            .last_successful_sync
+            .is_some());
+    }
+
+    #[test]
+    fn should_back_off_from_failing_peers() {
+        let database =
+            ConcurrentNodeDatabase::new(Network::test_default(), DatabaseOptions::default());
+        let mut peer = create_mock_peer(&database);
+        peer.peer_sync_ms = 10;
+        peer.max_peer_backoff_ms = 1000;
+
+        // the wait doubles after each consecutive failure, up to the maximum
+        let mut backoffs = vec![];
+        for _ in 0..8 {
+            peer.stats.add_failed_request("peer");
+            let last_failure = peer.stats.get("peer").last_failed_request.unwrap();
+            let backoff = (1..=2000)
+                .find(|elapsed| peer.is_peer_due("peer", last_failure + elapsed))
+                .unwrap();
+            assert!(!peer.is_peer_due("peer", last_failure + backoff - 1));
+            backoffs.push(backoff);
+        }
+        assert_eq!(backoffs, vec![20, 40, 80, 160, 320, 640, 1000, 1000]);
+
+        // a successful contact brings the peer back to the normal sync interval
+        peer.stats.set_successful_sync("peer");
+        assert!(peer.is_peer_due("peer", 0));
+        assert_eq!(peer.stats.get("peer").consecutive_failures, 0);
+    }
+
+    #[test]
+    fn should_announce_new_blocks_to_a_limited_fanout() {
+        let network = Network::test_default();
+        let database = ConcurrentNodeDatabase::new(network.clone(), DatabaseOptions::default());
+        let mut peer = create_mock_peer(&database);
+        peer.peer_addresses = (0..5).map(|index| format!("peer-{}", index)).collect();
+        peer.block_fanout = 2;
+
+        for block in create_mock_chain(&network, 3) {
+            database.append_block(&block).unwrap();
+        }
+
+        // each block is announced to only as many peers as the fan-out, all of them different
+        let announcements = RefCell::new(vec![]);
+        let last_sent_index = peer.try_send_new_blocks_since(None, |address, block| {
+            announcements
+                .borrow_mut()
+                .push((block.index, address.to_string()));
+            Some(true)
+        });
+        assert_eq!(last_sent_index, Some(2));
+
+        let announcements = announcements.into_inner();
+        assert_eq!(announcements.len(), 3 * 2);
+        for index in 0..3 {
+            let addresses: HashSet<_> = announcements
+                .iter()
+                .filter(|(block_index, _)| *block_index == index)
+                .map(|(_, address)| address)
+                .collect();
+            assert_eq!(addresses.len(), 2);
+            assert!(addresses
+                .iter()
+                .all(|address| peer.peer_addresses.contains(address)));
+        }
+
+        // without a fan-out, the blocks are announced to every peer
+        peer.block_fanout = 0;
+        assert_eq!(peer.get_block_announcement_peers().len(), 5);
+    }
+
+    #[test]
+    fn should_wake_up_when_a_new_block_is_added() {
+        let network = Network::test_default();
+        let database = ConcurrentNodeDatabase::new(network.clone(), DatabaseOptions::default());
+        let mut peer = create_mock_peer(&database);
+        peer.peer_sync_ms = 10_000;
+
+        let tip_changes = database.subscribe_tip_changes();
+
+        // a block is added locally while the peer is waiting
+        let local_database = database.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let genesis = Block::new(0, 0, network.consensus_hash(), vec![create_coinbase()]);
+            local_database.append_block(&genesis).unwrap();
+        });
+
+        // the peer should wake up way before the sync interval elapses
+        let start = Instant::now();
+        assert!(peer.wait_for_next_sync(&tip_changes));
+        assert!(start.elapsed() < Duration::from_millis(peer.peer_sync_ms));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn should_wake_up_when_the_sync_interval_elapses() {
+        let database =
+            ConcurrentNodeDatabase::new(Network::test_default(), DatabaseOptions::default());
+        let peer = create_mock_peer(&database);
+
+        let tip_changes = database.subscribe_tip_changes();
+
+        assert!(!peer.wait_for_next_sync(&tip_changes));
+    }
+
+    #[test]
+    fn should_select_new_blocks_from_peer_one_block_ahead() {
+        let peer_blocks = create_mock_blocks(0..=2);
+
+        let new_blocks = select_new_blocks(peer_blocks, 2);
+
+        assert_eq!(get_indexes(&new_blocks), vec![2]);
+    }
+
+    #[test]
+    fn should_select_no_blocks_from_peer_with_same_height() {
+        let peer_blocks = create_mock_blocks(0..=2);
+
+        let new_blocks = select_new_blocks(peer_blocks, 3);
+
+        assert!(new_blocks.is_empty());
+    }
+
+    #[test]
+    fn should_select_new_blocks_from_non_zero_based_range() {
+        // the peer only returns a partial range of its chain
+        let peer_blocks = create_mock_blocks(5..=9);
+
+        let new_blocks = select_new_blocks(peer_blocks, 7);
+
+        assert_eq!(get_indexes(&new_blocks), vec![7, 8, 9]);
+    }
+
+    fn create_mock_blocks(indexes: RangeInclusive<u64>) -> Vec<Block> {
+        indexes
+            .map(|index| Block::new(index, 0, ConsensusHash::default(), vec![]))
+            .collect()
+    }
+
+    fn get_indexes(blocks: &[Block]) -> Vec<u64> {
+        blocks.iter().map(|block| block.index).collect()
+    }
+
+    fn create_mock_peer(database: &ConcurrentNodeDatabase) -> Peer {
+        let config = Config {
+            command: None,
+            port: 8000,
+            peer_sync_ms: 10,
+            sync_window_size: 100,
+            max_peer_response_bytes: 67108864,
+            difficulty: 0,
+            max_transaction_data_size: 256,
+            target_block_time_ms: 10000,
+            retarget_window: 0,
+            min_difficulty: 0,
+            max_difficulty: 256,
+            coinbase_maturity: 0,
+            pow_algorithm: PowAlgorithm::Sha3,
+            premine: vec![],
+            max_block_weight: 0,
+            genesis_difficulty: None,
+            max_txs_per_sender_per_block: 0,
+            warmup_blocks: 0,
+            mempool_conflict_policy: MempoolConflictPolicy::RejectSecond,
+            max_mempool_bytes: 33554432,
+            max_future_blocks: 100,
+            mempool_reconcile_ms: 60000,
+            mempool_reconcile_depth: 100,
+            dedup_depth: 100,
+            import_chain: None,
+            peers: vec![],
+            max_clock_skew_ms: 60000,
+            max_peer_backoff_ms: 300000,
+            block_fanout: 0,
+            peer_transport: PeerTransport::Http,
+            tcp_port: 9000,
+            coins_as_strings: false,
+            pretty_json: false,
+            read_only: false,
+            admin_endpoints: false,
+            api_workers: 0,
+            api_backlog: 2048,
+            write_queue_capacity: 1024,
+            rate_limit_burst: 100,
+            rate_limit_per_second: 10,
+            cors_allowed_origins: vec![],
+            cors_allowed_methods: vec!["GET".to_string()],
+            webhooks: vec![],
+            webhook_poll_ms: 10,
+            webhook_timeout_ms: 1000,
+            webhook_retries: 3,
+            webhook_retry_backoff_ms: 200,
+            max_subsystem_restarts: 5,
+            subsystem_restart_backoff_ms: 1000,
+            subsystem_stable_ms: 60000,
+            shutdown_timeout_ms: 5000,
+        };
+
+        Peer::new(&config, database, &ConcurrentPeerStats::default())
+    }
+
+    // Create a chain of valid blocks
+    fn create_mock_chain(network: &Network, num_blocks: u64) -> Vec<Block> {
+        let mut blocks: Vec<Block> = vec![];
+        for index in 0..num_blocks {
+            let previous_hash = match blocks.last() {
+                Some(block) => block.hash.clone(),
+                None => network.consensus_hash(),
+            };
+            blocks.push(Block::new(index, 0, previous_hash, vec![create_coinbase()]));
+        }
+
+        blocks
+    }
+
+    fn get_window(blocks: &[Block], from: u64, limit: u64) -> Vec<Block> {
+        blocks
+            .iter()
+            .skip(from as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect()
+    }
+
+    fn create_coinbase() -> Transaction {
+        Transaction::new(Address::default(), Address::default(), BLOCK_SUBSIDY)
     }
 }
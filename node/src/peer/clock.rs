@@ -0,0 +1,44 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Current time of a node, exchanged with peers to detect misconfigured clocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct NodeTime {
+    // timestamp in milliseconds
+    pub timestamp: i64,
+}
+
+impl NodeTime {
+    pub fn now() -> Self {
+        NodeTime {
+            timestamp: Utc::now().timestamp_millis(),
+        }
+    }
+}
+
+// Difference between the clock of a peer and ours, in milliseconds (positive if the peer is ahead)
+// The peer time is compared to the middle of the request, as it was read at some point during it
+pub fn estimate_clock_skew(sent_at: NodeTime, peer_time: NodeTime, received_at: NodeTime) -> i64 {
+    let round_trip_ms = received_at.timestamp.saturating_sub(sent_at.timestamp);
+    let local_time = sent_at.timestamp.saturating_add(round_trip_ms / 2);
+
+    peer_time.timestamp.saturating_sub(local_time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_compare_peer_time_with_the_middle_of_the_request() {
+        let sent_at = NodeTime { timestamp: 1000 };
+        let received_at = NodeTime { timestamp: 1200 };
+
+        let skew = |timestamp| estimate_clock_skew(sent_at, NodeTime { timestamp }, received_at);
+
+        assert_eq!(skew(1100), 0);
+        assert_eq!(skew(5100), 4000);
+        assert_eq!(skew(100), -1000);
+    }
+}
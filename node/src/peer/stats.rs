@@ -0,0 +1,98 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Counters about the synchronization with a single peer
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct PeerStats {
+    pub blocks_received: u64,
+    pub blocks_sent: u64,
+    pub failed_requests: u64,
+    // responses that could not be parsed, usually from peers running an incompatible version
+    pub malformed_responses: u64,
+    // failed requests since the last successful contact, to back off from unreachable peers
+    pub consecutive_failures: u32,
+    // timestamp in milliseconds of the last failed request
+    pub last_failed_request: Option<i64>,
+    // timestamp in milliseconds of the last time we retrieved the blocks of the peer
+    pub last_successful_sync: Option<i64>,
+    // difference between the clock of the peer and ours in milliseconds, measured on the handshake
+    pub clock_skew_ms: Option<i64>,
+    // peers whose clock is too far from ours are synced last, as their blocks may be rejected
+    pub excessive_clock_skew: bool,
+}
+
+// Statistics of all peers, indexed by peer address
+// It can be cloned and shared between threads, as all clones point to the same statistics
+#[derive(Debug, Default, Clone)]
+pub struct ConcurrentPeerStats(Arc<RwLock<BTreeMap<String, PeerStats>>>);
+
+impl ConcurrentPeerStats {
+    pub fn get_all(&self) -> BTreeMap<String, PeerStats> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn add_blocks_received(&self, address: &str, num_blocks: u64) {
+        self.update(address, |stats| stats.blocks_received += num_blocks);
+    }
+
+    pub fn add_block_sent(&self, address: &str) {
+        self.update(address, |stats| {
+            stats.blocks_sent += 1;
+            stats.consecutive_failures = 0;
+        });
+    }
+
+    pub fn add_failed_request(&self, address: &str) {
+        let now = Utc::now().timestamp_millis();
+        self.update(address, |stats| {
+            stats.failed_requests += 1;
+            stats.consecutive_failures += 1;
+            stats.last_failed_request = Some(now);
+        });
+    }
+
+    pub fn add_malformed_response(&self, address: &str) {
+        self.update(address, |stats| stats.malformed_responses += 1);
+        self.add_failed_request(address);
+    }
+
+    pub fn set_successful_sync(&self, address: &str) {
+        let now = Utc::now().timestamp_millis();
+        self.update(address, |stats| {
+            stats.last_successful_sync = Some(now);
+            stats.consecutive_failures = 0;
+        });
+    }
+
+    pub fn set_clock_skew(&self, address: &str, clock_skew_ms: i64, is_excessive: bool) {
+        self.update(address, |stats| {
+            stats.clock_skew_ms = Some(clock_skew_ms);
+            stats.excessive_clock_skew = is_excessive;
+        });
+    }
+
+    // Statistics of a single peer, empty if we did not contact it yet
+    pub fn get(&self, address: &str) -> PeerStats {
+        let all_stats = self.0.read().unwrap();
+        all_stats.get(address).cloned().unwrap_or_default()
+    }
+
+    pub fn has_excessive_clock_skew(&self, address: &str) -> bool {
+        let all_stats = self.0.read().unwrap();
+        all_stats
+            .get(address)
+            .is_some_and(|stats| stats.excessive_clock_skew)
+    }
+
+    fn update(&self, address: &str, update_fn: impl FnOnce(&mut PeerStats)) {
+        let mut all_stats = self.0.write().unwrap();
+        let stats = all_stats.entry(address.to_string()).or_default();
+        update_fn(stats);
+    }
+}
@@ -0,0 +1,436 @@
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use crossbeam_utils::thread;
+use serde::{Deserialize, Serialize};
+use spec::{
+    types::{Block, Transaction},
+    Database as SpecDatabase,
+};
+use thiserror::Error;
+
+use super::{clock::NodeTime, stats::ConcurrentPeerStats, Peer, PeerRequestError};
+use crate::{
+    api::rate_limiter::RateLimiter,
+    database::ConcurrentNodeDatabase,
+    util::{config::Config, execution::Runnable},
+};
+
+// Messages bigger than this are rejected, to avoid allocating huge buffers
+const MAX_MESSAGE_SIZE: u32 = 64 * 1024 * 1024;
+
+// Maximum number of blocks requested to a peer at once
+const MAX_BLOCKS_PER_REQUEST: u64 = 1000;
+
+const CONNECTION_TIMEOUT_MS: u64 = 5000;
+
+// Connections accepted beyond this are closed right away, as each one takes a thread
+const MAX_INCOMING_CONNECTIONS: usize = 64;
+
+#[derive(Error, PartialEq, Eq, Debug)]
+pub enum TcpTransportError {
+    #[error("Message too large")]
+    MessageTooLarge,
+
+    #[error("Unexpected message")]
+    UnexpectedMessage,
+
+    #[error("The node is in read-only mode")]
+    ReadOnly,
+
+    #[error("Too many messages, try again later")]
+    RateLimited,
+}
+
+// Messages exchanged between peers, encoded with bincode and prefixed by their length
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PeerMessage {
+    // Announce a new block, usually the new tip of the sender
+    NewBlock(Block),
+    // Ask for up to `limit` consecutive blocks, starting with the one with the `from` index
+    GetBlocks { from: u64, limit: u64 },
+    // Response to a `GetBlocks` message
+    Blocks(Vec<Block>),
+    // Relay a transaction to be added to the mempool
+    NewTransaction(Transaction),
+    // Ask for the current time of the peer, as part of the handshake
+    GetTime,
+    // Response to a `GetTime` message
+    Time(NodeTime),
+}
+
+// Peer system that uses raw TCP connections instead of the HTTP API of the peers
+// Peer addresses must be in the "host:port" format, pointing to the TCP port of each peer
+pub struct TcpPeer {
+    peer: Peer,
+    tcp_port: u16,
+    ingress: Arc<IngressPolicy>,
+}
+
+// Same restrictions as the write endpoints of the REST API, for the blocks and transactions pushed by peers
+struct IngressPolicy {
+    read_only: bool,
+    rate_limiter: RateLimiter,
+}
+
+impl IngressPolicy {
+    fn check_write(&self, stream: &TcpStream) -> Result<(), TcpTransportError> {
+        if self.read_only {
+            return Err(TcpTransportError::ReadOnly);
+        }
+
+        match stream.peer_addr() {
+            Ok(address) if !self.rate_limiter.try_acquire(address.ip()) => {
+                Err(TcpTransportError::RateLimited)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Releases the slot of an incoming connection when its thread finishes
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    fn try_acquire(open_connections: &Arc<AtomicUsize>) -> Option<ConnectionSlot> {
+        let previous = open_connections.fetch_add(1, Ordering::SeqCst);
+        // the slot is released when dropped, even if the connection is refused
+        let slot = ConnectionSlot(open_connections.clone());
+        (previous < MAX_INCOMING_CONNECTIONS).then_some(slot)
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Runnable for TcpPeer {
+    fn name(&self) -> &str {
+        "tcp peer sync"
+    }
+
+    fn run(&self) -> Result<()> {
+        self.start()
+    }
+}
+
+impl TcpPeer {
+    pub fn new(
+        config: &Config,
+        database: &ConcurrentNodeDatabase,
+        stats: &ConcurrentPeerStats,
+    ) -> TcpPeer {
+        TcpPeer {
+            peer: Peer::new(config, database, stats),
+            tcp_port: config.tcp_port,
+            ingress: Arc::new(IngressPolicy {
+                read_only: config.read_only,
+                rate_limiter: RateLimiter::new(
+                    config.rate_limit_burst,
+                    config.rate_limit_per_second,
+                ),
+            }),
+        }
+    }
+
+    pub fn start(&self) -> Result<()> {
+        let listener = TcpListener::bind(("localhost", self.tcp_port))?;
+        info!("start tcp peer system on port {}", self.tcp_port);
+
+        thread::scope(|s| {
+            s.spawn(|_| self.listen(listener));
+            self.sync();
+        })
+        .unwrap();
+
+        Ok(())
+    }
+
+    // Answer the messages of other peers, each connection is handled in its own thread
+    // Idle connections are closed after a timeout, so a limited number of threads is enough
+    fn listen(&self, listener: TcpListener) {
+        let open_connections = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            let stream = match stream.and_then(set_timeouts) {
+                Ok(value) => value,
+                Err(error) => {
+                    error!("Could not accept tcp peer connection: {}", error);
+                    continue;
+                }
+            };
+
+            let slot = match ConnectionSlot::try_acquire(&open_connections) {
+                Some(value) => value,
+                None => {
+                    info!("Too many tcp peer connections, closing the new one");
+                    continue;
+                }
+            };
+
+            let database = self.peer.database.clone();
+            let ingress = self.ingress.clone();
+            std::thread::spawn(move || {
+                handle_connection(stream, &database, &ingress);
+                drop(slot);
+            });
+        }
+    }
+
+    // Same as the HTTP peer system, we sync at regular intervals of time or when a new block is added
+    fn sync(&self) {
+        let tip_changes = self.peer.database.subscribe_tip_changes();
+        let mut last_sent_block_index = None;
+        let mut relayed_transaction_ids = HashSet::new();
+        let mut handshaken_peers = HashSet::new();
+        loop {
+            self.peer
+                .try_handshake_peers(&mut handshaken_peers, |address| request_time(address).ok());
+            self.try_receive_new_blocks();
+            last_sent_block_index = self.try_send_new_blocks_since(last_sent_block_index);
+            // peers only push their transactions, as there is no message to request a mempool
+            self.peer.try_send_new_transactions(
+                &mut relayed_transaction_ids,
+                |address, transaction| {
+                    let message = PeerMessage::NewTransaction(transaction.clone());
+                    send_message(address, &message).ok().map(|_| true)
+                },
+            );
+            self.peer.wait_for_next_sync(&tip_changes);
+        }
+    }
+
+    // Ask all peers for the blocks that we do not have yet, in windows of limited size
+    fn try_receive_new_blocks(&self) {
+        for address in self.peer.get_prioritized_peers() {
+            let request_window = |from, limit: u64| {
+                let limit = limit.min(MAX_BLOCKS_PER_REQUEST);
+                request_blocks(address, from, limit).map_err(|error| {
+                    error!(
+                        "Could not request blocks from tcp peer {}: {}",
+                        address, error
+                    );
+                    PeerRequestError::Unreachable(error.to_string())
+                })
+            };
+
+            if let Err(error) = self.peer.receive_blocks_in_windows(address, request_window) {
+                error!("Could not sync with tcp peer {}: {}", address, error);
+            }
+        }
+    }
+
+    // Announce to the peers (limited by the fan-out) the blocks added since last time we announced
+    fn try_send_new_blocks_since(&self, last_send_block_index: Option<u64>) -> Option<u64> {
+        let new_blocks = self.peer.get_new_blocks_since(last_send_block_index);
+
+        for block in new_blocks.iter() {
+            for address in self.peer.get_block_announcement_peers() {
+                let message = PeerMessage::NewBlock(block.clone());
+                match send_message(address, &message) {
+                    Ok(_) => {
+                        self.peer.stats.add_block_sent(address);
+                        info!("Sent new block {} to tcp peer {}", block.index, address);
+                    }
+                    Err(error) => {
+                        self.peer.stats.add_failed_request(address);
+                        error!(
+                            "Could not send block {} to tcp peer {}: {}",
+                            block.index, address, error
+                        );
+                    }
+                }
+            }
+        }
+
+        match new_blocks.last() {
+            Some(block) => Some(block.index),
+            None => last_send_block_index,
+        }
+    }
+}
+
+// Process all the messages received through a connection, until the other peer closes it or stays idle
+fn handle_connection(
+    mut stream: TcpStream,
+    database: &ConcurrentNodeDatabase,
+    ingress: &IngressPolicy,
+) {
+    while let Ok(message) = read_message(&mut stream) {
+        let is_write = matches!(
+            message,
+            PeerMessage::NewBlock(_) | PeerMessage::NewTransaction(_)
+        );
+        if is_write {
+            if let Err(error) = ingress.check_write(&stream) {
+                info!("Rejected tcp peer message: {}", error);
+                continue;
+            }
+        }
+
+        let result = match message {
+            PeerMessage::NewBlock(block) => database.append_block(&block),
+            PeerMessage::GetBlocks { from, limit } => {
+                let limit = limit.min(MAX_BLOCKS_PER_REQUEST);
+                let blocks = database.get_blocks_range(from, limit);
+                write_message(&mut stream, &PeerMessage::Blocks(blocks))
+            }
+            PeerMessage::NewTransaction(transaction) => {
+                database.add_mempool_transaction(transaction)
+            }
+            PeerMessage::GetTime => write_message(&mut stream, &PeerMessage::Time(NodeTime::now())),
+            PeerMessage::Blocks(_) | PeerMessage::Time(_) => {
+                Err(TcpTransportError::UnexpectedMessage.into())
+            }
+        };
+
+        if let Err(error) = result {
+            info!("Could not process tcp peer message: {}", error);
+        }
+    }
+}
+
+fn connect(address: &str) -> Result<TcpStream> {
+    let stream = TcpStream::connect(address)?;
+    Ok(set_timeouts(stream)?)
+}
+
+fn set_timeouts(stream: TcpStream) -> std::io::Result<TcpStream> {
+    let timeout = Some(Duration::from_millis(CONNECTION_TIMEOUT_MS));
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+
+    Ok(stream)
+}
+
+// Send a single message to a peer, without waiting for any response
+pub fn send_message(address: &str, message: &PeerMessage) -> Result<()> {
+    let mut stream = connect(address)?;
+    write_message(&mut stream, message)
+}
+
+// Request a range of blocks to a peer
+pub fn request_blocks(address: &str, from: u64, limit: u64) -> Result<Vec<Block>> {
+    let mut stream = connect(address)?;
+    write_message(&mut stream, &PeerMessage::GetBlocks { from, limit })?;
+
+    match read_message(&mut stream)? {
+        PeerMessage::Blocks(blocks) => Ok(blocks),
+        _ => Err(TcpTransportError::UnexpectedMessage.into()),
+    }
+}
+
+// Request the current time of a peer
+pub fn request_time(address: &str) -> Result<NodeTime> {
+    let mut stream = connect(address)?;
+    write_message(&mut stream, &PeerMessage::GetTime)?;
+
+    match read_message(&mut stream)? {
+        PeerMessage::Time(time) => Ok(time),
+        _ => Err(TcpTransportError::UnexpectedMessage.into()),
+    }
+}
+
+// Each message is prefixed with its length as a big endian u32
+pub fn write_message<W: Write>(writer: &mut W, message: &PeerMessage) -> Result<()> {
+    let payload = bincode::serialize(message)?;
+    let length = u32::try_from(payload.len())
+        .ok()
+        .filter(|length| *length <= MAX_MESSAGE_SIZE)
+        .ok_or(TcpTransportError::MessageTooLarge)?;
+
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(&payload)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+pub fn read_message<R: Read>(reader: &mut R) -> Result<PeerMessage> {
+    let mut length_bytes = [0; 4];
+    reader.read_exact(&mut length_bytes)?;
+
+    let length = u32::from_be_bytes(length_bytes);
+    if length > MAX_MESSAGE_SIZE {
+        return Err(TcpTransportError::MessageTooLarge.into());
+    }
+
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload)?;
+
+    Ok(bincode::deserialize(&payload)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use spec::types::{hash::ConsensusHash, Address};
+
+    use super::*;
+
+    #[test]
+    fn should_encode_and_decode_messages() {
+        let transaction =
+            Transaction::new(Address::default(), Address::default(), 10).with_data(vec![1, 2, 3]);
+        let block = Block::new(1, 0, ConsensusHash::default(), vec![transaction.clone()]);
+        let messages = [
+            PeerMessage::NewBlock(block.clone()),
+            PeerMessage::GetBlocks { from: 1, limit: 10 },
+            PeerMessage::Blocks(vec![block]),
+            PeerMessage::NewTransaction(transaction),
+            PeerMessage::GetTime,
+            PeerMessage::Time(NodeTime { timestamp: 1000 }),
+        ];
+
+        // all the messages are written one after another in the same stream
+        let mut buffer = vec![];
+        for message in messages.iter() {
+            write_message(&mut buffer, message).unwrap();
+        }
+
+        let mut reader = buffer.as_slice();
+        for message in messages.iter() {
+            assert_eq!(read_message(&mut reader).unwrap(), *message);
+        }
+        assert!(read_message(&mut reader).is_err());
+    }
+
+    #[test]
+    fn should_limit_the_incoming_connections() {
+        let open_connections = Arc::new(AtomicUsize::new(0));
+        let slots: Vec<_> = (0..MAX_INCOMING_CONNECTIONS)
+            .map(|_| ConnectionSlot::try_acquire(&open_connections).unwrap())
+            .collect();
+
+        // a refused connection does not take a slot
+        assert!(ConnectionSlot::try_acquire(&open_connections).is_none());
+        assert_eq!(
+            open_connections.load(Ordering::SeqCst),
+            MAX_INCOMING_CONNECTIONS
+        );
+
+        // and the slots are released when the connections finish
+        drop(slots);
+        assert_eq!(open_connections.load(Ordering::SeqCst), 0);
+        assert!(ConnectionSlot::try_acquire(&open_connections).is_some());
+    }
+
+    #[test]
+    fn should_reject_too_large_messages() {
+        let mut buffer = (MAX_MESSAGE_SIZE + 1).to_be_bytes().to_vec();
+        buffer.extend_from_slice(&[0; 16]);
+
+        let err = read_message(&mut buffer.as_slice()).unwrap_err();
+        let inner_err = err.downcast::<TcpTransportError>().unwrap();
+        assert_eq!(inner_err, TcpTransportError::MessageTooLarge);
+    }
+}
@@ -0,0 +1,48 @@
+use anyhow::Result;
+
+use crate::{
+    database::ConcurrentNodeDatabase,
+    util::{
+        config::Config,
+        execution::{sleep_millis, Runnable},
+    },
+};
+
+// Periodically drops the mempool transactions that are already confirmed in the chain
+pub struct MempoolReconciler {
+    reconcile_ms: u64,
+    depth: u64,
+    database: ConcurrentNodeDatabase,
+}
+
+impl Runnable for MempoolReconciler {
+    fn name(&self) -> &str {
+        "mempool reconciler"
+    }
+
+    fn run(&self) -> Result<()> {
+        self.start()
+    }
+}
+
+impl MempoolReconciler {
+    pub fn new(config: &Config, database: &ConcurrentNodeDatabase) -> MempoolReconciler {
+        MempoolReconciler {
+            reconcile_ms: config.mempool_reconcile_ms,
+            depth: config.mempool_reconcile_depth,
+            database: database.clone(),
+        }
+    }
+
+    pub fn start(&self) -> Result<()> {
+        if self.reconcile_ms == 0 {
+            info!("Mempool reconciliation disabled, exiting mempool reconciler");
+            return Ok(());
+        }
+
+        loop {
+            sleep_millis(self.reconcile_ms);
+            self.database.reconcile_mempool(self.depth);
+        }
+    }
+}
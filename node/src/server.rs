@@ -1,15 +1,25 @@
+use std::{fs::File, io::BufReader, sync::Arc};
+
 use spec::types::Network;
 
 use crate::{
     api::Api,
-    database::ConcurrentNodeDatabase,
-    peer::Peer,
-    util::{config::Config, execution},
+    database::{ChainExport, ConcurrentNodeDatabase, DatabaseOptions},
+    peer::{stats::ConcurrentPeerStats, tcp::TcpPeer, Peer},
+    reconciler::MempoolReconciler,
+    util::{
+        config::{Config, PeerTransport},
+        execution::{self, RestartPolicy, Runnable},
+        shutdown::ShutdownCoordinator,
+    },
+    webhook::Webhook,
 };
 
 pub struct Server {
     pub config: Config,
     pub database: ConcurrentNodeDatabase,
+    pub peer_stats: ConcurrentPeerStats,
+    pub shutdown: Arc<ShutdownCoordinator>,
 }
 
 impl Server {
@@ -17,18 +27,80 @@ impl Server {
         // TODO: read the network definition from a file
         let network = Network {
             description: "Test network".to_string(),
-            difficulty: 0,
+            difficulty: config.difficulty,
             timestamp: 0,
+            max_transaction_data_size: config.max_transaction_data_size,
+            target_block_time_ms: config.target_block_time_ms,
+            retarget_window: config.retarget_window,
+            min_difficulty: config.min_difficulty,
+            max_difficulty: config.max_difficulty,
+            coinbase_maturity: config.coinbase_maturity,
+            pow_algorithm: config.pow_algorithm,
+            premine: config.premine.clone(),
+            max_block_weight: config.max_block_weight,
+            genesis_difficulty: config.genesis_difficulty,
+            max_txs_per_sender_per_block: config.max_txs_per_sender_per_block,
+            warmup_blocks: config.warmup_blocks,
         };
 
-        let database = ConcurrentNodeDatabase::new(network);
+        let database = ConcurrentNodeDatabase::new(network, DatabaseOptions::from(&config));
+
+        if let Some(path) = &config.import_chain {
+            let file = File::open(path).expect("Error opening the chain export file");
+            let export = ChainExport::from_reader(BufReader::new(file))
+                .expect("Error decoding the chain export");
+            database
+                .import_chain(export)
+                .expect("Error importing the chain");
+            info!("Imported the chain from {}", path);
+        }
+
+        let peer_stats = ConcurrentPeerStats::default();
 
-        Self { config, database }
+        // subsystems are shut down in the order they are registered
+        let shutdown = Arc::new(ShutdownCoordinator::new(config.shutdown_timeout_ms));
+        shutdown.register(Arc::new(database.clone()));
+
+        Self {
+            config,
+            database,
+            peer_stats,
+            shutdown,
+        }
     }
 
     pub fn start(&self) {
-        let api = Api::new(self.config.port, &self.database.clone());
-        let peer = Peer::new(&self.config, &self.database.clone());
-        execution::run_in_parallel(vec![&api, &peer]);
+        let api = Api::new(&self.config, &self.database, &self.peer_stats);
+        let webhook = Webhook::new(&self.config, &self.database.clone());
+        let reconciler = MempoolReconciler::new(&self.config, &self.database);
+
+        // the REST API is always available for clients, regardless of the transport used by peers
+        let peer: Box<dyn Runnable> = match self.config.peer_transport {
+            PeerTransport::Http => {
+                Box::new(Peer::new(&self.config, &self.database, &self.peer_stats))
+            }
+            PeerTransport::Tcp => {
+                Box::new(TcpPeer::new(&self.config, &self.database, &self.peer_stats))
+            }
+        };
+
+        let restart_policy = RestartPolicy {
+            max_restarts: self.config.max_subsystem_restarts,
+            backoff_ms: self.config.subsystem_restart_backoff_ms,
+            stable_ms: self.config.subsystem_stable_ms,
+        };
+
+        // a node missing one of its subsystems (e.g. the api keeps failing to bind its port, or panicking)
+        // is not worth keeping up
+        let on_give_up = |name: &str| {
+            error!("Stopping the node, as its {} can not be restarted", name);
+            self.shutdown.shutdown();
+            std::process::exit(1);
+        };
+        execution::run_supervised(
+            vec![&api, peer.as_ref(), &webhook, &reconciler],
+            restart_policy,
+            on_give_up,
+        );
     }
 }
@@ -0,0 +1,45 @@
+use anyhow::{anyhow, Result};
+use isahc::ReadResponseExt;
+
+use crate::database::ChainStats;
+
+// Request the stats of a running node through its REST API
+pub fn fetch_chain_stats(node_url: &str) -> Result<ChainStats> {
+    let uri = format!("{}/stats", node_url);
+    let mut response = isahc::get(uri)?;
+
+    // check that the response is sucessful
+    if !response.status().is_success() {
+        return Err(anyhow!("Unexpected status {}", response.status()));
+    }
+
+    let raw_body = response.text()?;
+    Ok(serde_json::from_str(&raw_body)?)
+}
+
+// One "name: value" line per stat, so the output is easy to read and to parse in scripts
+pub fn format_chain_stats(stats: &ChainStats) -> String {
+    let tip_hash = match &stats.tip_hash {
+        Some(hash) => hash.to_string(),
+        None => "none".to_string(),
+    };
+
+    let utilization = &stats.utilization;
+    let average_block_fullness = match utilization.average_block_fullness {
+        Some(fullness) => format!("{:.1}%", fullness * 100.0),
+        None => "none".to_string(),
+    };
+
+    format!(
+        "height: {}\ntip_hash: {}\ndifficulty: {}\nmempool_size: {}\ntotal_supply: {}\n\
+        average_block_weight: {:.1}\naverage_block_fullness: {}\nmempool_bytes: {}\n",
+        stats.height,
+        tip_hash,
+        stats.difficulty,
+        stats.mempool_size,
+        stats.total_supply,
+        utilization.average_block_weight,
+        average_block_fullness,
+        utilization.mempool_bytes
+    )
+}
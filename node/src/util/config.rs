@@ -1,19 +1,230 @@
-use clap::Parser;
+use clap::{ArgEnum, Parser, Subcommand};
+use spec::types::{GenesisAllocation, PowAlgorithm};
 
 #[derive(Parser, Debug, Clone)]
 #[clap(author, version, about, long_about = None)]
 pub struct Config {
+    // Without a command, the node is started
+    #[clap(subcommand)]
+    pub command: Option<NodeCommand>,
+
     #[clap(short = 'p', long, value_parser, default_value = "8000")]
     pub port: u16,
 
     #[clap(short = 's', long, value_parser, default_value = "10000")]
     pub peer_sync_ms: u64,
 
+    // Maximum number of blocks downloaded from a peer at once
+    #[clap(long, value_parser, default_value = "100")]
+    pub sync_window_size: u64,
+
+    // Size past which a response of a peer (e.g. a window of blocks or its mempool) is discarded, and the peer penalized
+    #[clap(long, value_parser, default_value = "67108864")]
+    pub max_peer_response_bytes: u64,
+
     #[clap(short = 'd', long, value_parser, default_value = "10")]
     pub difficulty: u32,
 
+    // Difficulty of the genesis block, if it must differ from the one of the following blocks
+    #[clap(long, value_parser)]
+    pub genesis_difficulty: Option<u32>,
+
+    #[clap(long, value_parser, default_value = "256")]
+    pub max_transaction_data_size: u64,
+
+    #[clap(long, value_parser, default_value = "10000")]
+    pub target_block_time_ms: u64,
+
+    #[clap(long, value_parser, default_value = "0")]
+    pub retarget_window: u64,
+
+    #[clap(long, value_parser, default_value = "0")]
+    pub min_difficulty: u32,
+
+    #[clap(long, value_parser, default_value = "256")]
+    pub max_difficulty: u32,
+
+    #[clap(long, value_parser, default_value = "0")]
+    pub coinbase_maturity: u64,
+
+    #[clap(long, value_parser, default_value = "sha3")]
+    pub pow_algorithm: PowAlgorithm,
+
+    // Funds given by the genesis block, as "address:amount" or "address:amount:unlock_height"
+    #[clap(long, value_parser, multiple = true)]
+    pub premine: Vec<GenesisAllocation>,
+
+    // Maximum sum of the encoded sizes of the transactions of a block (0 for no limit)
+    #[clap(long, value_parser, default_value = "0")]
+    pub max_block_weight: u64,
+
+    // Maximum number of transactions of the same sender in a block, not counting the coinbase (0 for no limit)
+    #[clap(long, value_parser, default_value = "0")]
+    pub max_txs_per_sender_per_block: u64,
+
+    // Number of blocks from the genesis that only hold the coinbase, transfers wait in the mempool until then
+    #[clap(long, value_parser, default_value = "0")]
+    pub warmup_blocks: u64,
+
+    #[clap(long, arg_enum, value_parser, default_value = "reject-second")]
+    pub mempool_conflict_policy: MempoolConflictPolicy,
+
+    // Serialized size of the pending transactions, past which the biggest ones are evicted
+    #[clap(long, value_parser, default_value = "33554432")]
+    pub max_mempool_bytes: u64,
+
+    // Blocks whose index exceeds the tip by more than this are rejected instead of kept as orphans
+    #[clap(long, value_parser, default_value = "100")]
+    pub max_future_blocks: u64,
+
+    // Interval to drop the mempool transactions already confirmed in the chain (0 to disable)
+    #[clap(long, value_parser, default_value = "60000")]
+    pub mempool_reconcile_ms: u64,
+
+    // Number of latest blocks checked for confirmed transactions on each reconciliation
+    #[clap(long, value_parser, default_value = "100")]
+    pub mempool_reconcile_depth: u64,
+
+    // Number of latest blocks whose transactions are rejected when submitted again
+    #[clap(long, value_parser, default_value = "100")]
+    pub dedup_depth: u64,
+
+    // File exported from another node (GET /chain/export) to import the chain from, instead of syncing it from peers
+    // All the blocks are validated again when importing them, so it can only be used on an empty database
+    #[clap(long, value_parser)]
+    pub import_chain: Option<String>,
+
     #[clap(long, value_parser, multiple = true)]
     pub peers: Vec<String>,
+
+    // Peers whose clock differs from ours by more than this are flagged and synced last
+    #[clap(long, value_parser, default_value = "60000")]
+    pub max_clock_skew_ms: u64,
+
+    // Longest wait before contacting again a peer that keeps failing (0 to contact it on every sync)
+    #[clap(long, value_parser, default_value = "300000")]
+    pub max_peer_backoff_ms: u64,
+
+    // Number of random peers that a new block is announced to (0 for all of them)
+    // The rest receive it relayed by those peers, bounding the immediate outbound traffic
+    #[clap(long, value_parser, default_value = "0")]
+    pub block_fanout: usize,
+
+    #[clap(long, arg_enum, value_parser, default_value = "http")]
+    pub peer_transport: PeerTransport,
+
+    #[clap(long, value_parser, default_value = "9000")]
+    pub tcp_port: u16,
+
+    // Write coin amounts as decimal strings in the API responses, to avoid precision loss in JavaScript
+    #[clap(long, action)]
+    pub coins_as_strings: bool,
+
+    // Indent the JSON responses of the read endpoints by default, instead of only with "?pretty=true"
+    #[clap(long, action)]
+    pub pretty_json: bool,
+
+    // Only serve reads and sync from peers, rejecting mining and submitted blocks or transactions
+    #[clap(long, action)]
+    pub read_only: bool,
+
+    // Serve the endpoints that alter the node for everyone (i.e. deleting mempool transactions)
+    // They are disabled by default, as anyone reaching the API could use them
+    #[clap(long, action)]
+    pub admin_endpoints: bool,
+
+    // Worker threads of the REST API server (0 for one per CPU)
+    #[clap(long, value_parser, default_value = "0")]
+    pub api_workers: usize,
+
+    // Maximum number of pending connections to the REST API server
+    #[clap(long, value_parser, default_value = "2048")]
+    pub api_backlog: u32,
+
+    // Maximum number of database writes waiting to be processed, the next ones are answered with 429
+    #[clap(long, value_parser, default_value = "1024")]
+    pub write_queue_capacity: usize,
+
+    #[clap(long, value_parser, default_value = "100")]
+    pub rate_limit_burst: u32,
+
+    #[clap(long, value_parser, default_value = "10")]
+    pub rate_limit_per_second: u32,
+
+    #[clap(long, value_parser, multiple = true)]
+    pub cors_allowed_origins: Vec<String>,
+
+    #[clap(long, value_parser, multiple = true, default_value = "GET")]
+    pub cors_allowed_methods: Vec<String>,
+
+    #[clap(long, value_parser, multiple = true)]
+    pub webhooks: Vec<String>,
+
+    #[clap(long, value_parser, default_value = "1000")]
+    pub webhook_poll_ms: u64,
+
+    #[clap(long, value_parser, default_value = "5000")]
+    pub webhook_timeout_ms: u64,
+
+    #[clap(long, value_parser, default_value = "3")]
+    pub webhook_retries: u32,
+
+    // Time to wait before sending a block to a webhook again, doubled after each failed attempt
+    #[clap(long, value_parser, default_value = "500")]
+    pub webhook_retry_backoff_ms: u64,
+
+    // Times that a failed subsystem (api, peers, webhooks, mempool reconciler) is restarted before giving up on it
+    #[clap(long, value_parser, default_value = "5")]
+    pub max_subsystem_restarts: u32,
+
+    // Time to wait before restarting a failed subsystem, doubled after each restart
+    #[clap(long, value_parser, default_value = "1000")]
+    pub subsystem_restart_backoff_ms: u64,
+
+    // Time that a restarted subsystem has to stay up for its restarts and backoff to start over
+    #[clap(long, value_parser, default_value = "60000")]
+    pub subsystem_stable_ms: u64,
+
+    // Maximum time given to each subsystem to shut down when the node exits
+    #[clap(long, value_parser, default_value = "5000")]
+    pub shutdown_timeout_ms: u64,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum NodeCommand {
+    // Print the height, tip, difficulty, mempool size, total supply and utilization of a running node
+    Stats {
+        #[clap(
+            short = 'n',
+            long,
+            value_parser,
+            default_value = "http://localhost:8000"
+        )]
+        node_url: String,
+    },
+}
+
+// How the node communicates with its peers
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerTransport {
+    // JSON over the REST API of the peers
+    Http,
+    // Length-prefixed bincode messages over raw TCP connections
+    Tcp,
+}
+
+// What to do when a new transaction and the pending ones of the same sender overspend its balance
+#[derive(ArgEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MempoolConflictPolicy {
+    // Keep the pending transactions and reject the new one
+    #[default]
+    RejectSecond,
+    // Evict the most recent pending transactions of the sender until the new one fits
+    // (transactions carry no fee, so the newest transaction always wins)
+    Replace,
+    // Accept the new transaction, as it is confirmable on its own,
+    // and leave to block templates to only include the ones that fit in the balance
+    KeepBoth,
 }
 
 pub fn parse_from_cli() -> Config {
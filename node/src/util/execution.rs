@@ -1,24 +1,230 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use crossbeam_utils::thread;
-use std::time;
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    time::{self, Instant},
+};
 
 pub trait Runnable: Sync {
+    fn name(&self) -> &str;
     fn run(&self) -> Result<()>;
 }
 
-pub fn run_in_parallel(runnables: Vec<&dyn Runnable>) {
+// How the runnables that fail are restarted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    pub max_restarts: u32,
+    // time to wait before the first restart, doubled after each one
+    pub backoff_ms: u64,
+    // a runnable that stayed up this long before failing starts over with no restarts and the first backoff
+    pub stable_ms: u64,
+}
+
+// Run all the runnables in parallel, restarting the ones that fail so a transient error
+// (e.g. in the peer loop) does not permanently stop a subsystem
+// `on_give_up` is called with the name of any runnable that keeps failing after all the restarts
+pub fn run_supervised<F>(runnables: Vec<&dyn Runnable>, policy: RestartPolicy, on_give_up: F)
+where
+    F: Fn(&str) + Sync,
+{
+    let on_give_up = &on_give_up;
     thread::scope(|s| {
         for runnable in runnables {
             s.spawn(move |_| {
-                runnable.run().unwrap();
+                if supervise(runnable, policy).is_err() {
+                    on_give_up(runnable.name());
+                }
             });
         }
     })
     .unwrap();
 }
 
+// Run until it succeeds, returning the last error if it keeps failing after all the restarts
+// A panic is a failure like any other, so it is restarted too instead of silently ending the thread
+fn supervise(runnable: &dyn Runnable, policy: RestartPolicy) -> Result<()> {
+    let mut backoff_ms = policy.backoff_ms;
+    let mut num_restarts = 0;
+    loop {
+        let start_time = Instant::now();
+        let error = match panic::catch_unwind(AssertUnwindSafe(|| runnable.run())) {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(error)) => error,
+            Err(payload) => anyhow!("panicked: {}", get_panic_message(payload.as_ref())),
+        };
+
+        // only the failures in a row count, so a long running subsystem is not eventually given up on
+        if start_time.elapsed().as_millis() >= u128::from(policy.stable_ms) {
+            backoff_ms = policy.backoff_ms;
+            num_restarts = 0;
+        }
+
+        if num_restarts >= policy.max_restarts {
+            error!(
+                "{} failed after {} restarts, giving up: {}",
+                runnable.name(),
+                num_restarts,
+                error
+            );
+            return Err(error);
+        }
+
+        error!(
+            "{} failed, restarting it in {} ms: {}",
+            runnable.name(),
+            backoff_ms,
+            error
+        );
+        sleep_millis(backoff_ms);
+        backoff_ms = backoff_ms.saturating_mul(2);
+        num_restarts += 1;
+    }
+}
+
+// Panics usually carry their message as a string, but any other value is allowed
+fn get_panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
 // Suspend the execution of the thread by a particular amount of milliseconds
 pub fn sleep_millis(millis: u64) {
     let wait_duration = time::Duration::from_millis(millis);
     std::thread::sleep(wait_duration);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use anyhow::anyhow;
+
+    use super::*;
+
+    // Runnable that fails (or panics) the first times it is run, after running for a while
+    struct FlakyRunnable {
+        num_failures: u32,
+        num_runs: AtomicU32,
+        run_ms: u64,
+        panics: bool,
+    }
+
+    impl Runnable for FlakyRunnable {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn run(&self) -> Result<()> {
+            let num_runs = self.num_runs.fetch_add(1, Ordering::SeqCst) + 1;
+            sleep_millis(self.run_ms);
+            if num_runs <= self.num_failures {
+                if self.panics {
+                    panic!("failure {}", num_runs);
+                }
+                return Err(anyhow!("failure {}", num_runs));
+            }
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_restart_a_failed_runnable() {
+        let runnable = create_flaky_runnable(2);
+
+        supervise(&runnable, create_policy(3)).unwrap();
+
+        assert_eq!(runnable.num_runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn should_give_up_after_the_max_restarts() {
+        let runnable = create_flaky_runnable(10);
+
+        let err = supervise(&runnable, create_policy(3)).unwrap_err();
+
+        assert_eq!(err.to_string(), "failure 4");
+        assert_eq!(runnable.num_runs.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn should_restart_a_panicked_runnable() {
+        let runnable = FlakyRunnable {
+            panics: true,
+            ..create_flaky_runnable(2)
+        };
+
+        supervise(&runnable, create_policy(3)).unwrap();
+
+        assert_eq!(runnable.num_runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn should_give_up_on_a_runnable_that_keeps_panicking() {
+        let runnable = FlakyRunnable {
+            panics: true,
+            ..create_flaky_runnable(10)
+        };
+        let given_up = std::sync::Mutex::new(vec![]);
+
+        run_supervised(vec![&runnable], create_policy(1), |name| {
+            given_up.lock().unwrap().push(name.to_string())
+        });
+
+        assert_eq!(given_up.into_inner().unwrap(), vec!["flaky"]);
+        assert_eq!(runnable.num_runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn should_reset_the_restarts_of_a_runnable_that_stayed_up() {
+        let runnable = FlakyRunnable {
+            run_ms: 20,
+            ..create_flaky_runnable(5)
+        };
+        let policy = RestartPolicy {
+            stable_ms: 10,
+            ..create_policy(1)
+        };
+
+        // each failure comes after being up long enough, so it is never given up on
+        supervise(&runnable, policy).unwrap();
+
+        assert_eq!(runnable.num_runs.load(Ordering::SeqCst), 6);
+    }
+
+    #[test]
+    fn should_notify_the_runnables_given_up_on() {
+        let flaky = create_flaky_runnable(10);
+        let healthy = create_flaky_runnable(0);
+        let given_up = std::sync::Mutex::new(vec![]);
+
+        run_supervised(vec![&flaky, &healthy], create_policy(1), |name| {
+            given_up.lock().unwrap().push(name.to_string())
+        });
+
+        assert_eq!(given_up.into_inner().unwrap(), vec!["flaky"]);
+    }
+
+    fn create_flaky_runnable(num_failures: u32) -> FlakyRunnable {
+        FlakyRunnable {
+            num_failures,
+            num_runs: AtomicU32::new(0),
+            run_ms: 0,
+            panics: false,
+        }
+    }
+
+    fn create_policy(max_restarts: u32) -> RestartPolicy {
+        RestartPolicy {
+            max_restarts,
+            backoff_ms: 1,
+            stable_ms: 60000,
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::Result;
+
+// Subsystem that needs to flush or release its state before the node exits
+pub trait Shutdownable: Send + Sync {
+    fn name(&self) -> &str;
+    fn shutdown(&self) -> Result<()>;
+}
+
+// Runs the shutdown hooks of the subsystems, in the order they were registered
+// A hook that takes longer than the timeout is abandoned, so a stuck subsystem cannot block the exit
+pub struct ShutdownCoordinator {
+    hooks: Mutex<Vec<Arc<dyn Shutdownable>>>,
+    timeout: Duration,
+}
+
+impl ShutdownCoordinator {
+    pub fn new(timeout_ms: u64) -> Self {
+        Self {
+            hooks: Mutex::new(vec![]),
+            timeout: Duration::from_millis(timeout_ms),
+        }
+    }
+
+    pub fn register(&self, hook: Arc<dyn Shutdownable>) {
+        self.hooks.lock().unwrap().push(hook);
+    }
+
+    // The hooks are removed when run, so each one is only called once even if shutdown is requested again
+    pub fn shutdown(&self) {
+        let hooks: Vec<_> = self.hooks.lock().unwrap().drain(..).collect();
+        for hook in hooks {
+            let (sender, receiver) = mpsc::channel();
+            let running_hook = hook.clone();
+            thread::spawn(move || {
+                let _ = sender.send(running_hook.shutdown());
+            });
+
+            match receiver.recv_timeout(self.timeout) {
+                Ok(Ok(())) => info!("Shut down {}", hook.name()),
+                Ok(Err(error)) => error!("Error shutting down {}: {}", hook.name(), error),
+                Err(_) => error!("Timeout shutting down {}", hook.name()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn should_run_each_hook_once() {
+        let coordinator = ShutdownCoordinator::new(1000);
+        let hooks = [Arc::new(MockHook::new(0)), Arc::new(MockHook::new(0))];
+        for hook in &hooks {
+            coordinator.register(hook.clone());
+        }
+
+        coordinator.shutdown();
+        coordinator.shutdown();
+
+        for hook in &hooks {
+            assert_eq!(hook.calls.load(Ordering::SeqCst), 1);
+        }
+    }
+
+    #[test]
+    fn should_not_wait_for_stuck_hooks() {
+        let coordinator = ShutdownCoordinator::new(10);
+        let stuck_hook = Arc::new(MockHook::new(60_000));
+        let next_hook = Arc::new(MockHook::new(0));
+        coordinator.register(stuck_hook);
+        coordinator.register(next_hook.clone());
+
+        coordinator.shutdown();
+
+        assert_eq!(next_hook.calls.load(Ordering::SeqCst), 1);
+    }
+
+    struct MockHook {
+        calls: AtomicUsize,
+        duration_ms: u64,
+    }
+
+    impl MockHook {
+        fn new(duration_ms: u64) -> Self {
+            Self {
+                calls: AtomicUsize::new(0),
+                duration_ms,
+            }
+        }
+    }
+
+    impl Shutdownable for MockHook {
+        fn name(&self) -> &str {
+            "mock"
+        }
+
+        fn shutdown(&self) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(self.duration_ms));
+            Ok(())
+        }
+    }
+}
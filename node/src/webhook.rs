@@ -0,0 +1,124 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use anyhow::Result;
+use isahc::{config::Configurable, Request};
+use spec::{types::Block, Database as SpecDatabase};
+
+use crate::{
+    database::ConcurrentNodeDatabase,
+    util::{
+        config::Config,
+        execution::{sleep_millis, Runnable},
+    },
+};
+
+pub struct Webhook {
+    webhook_urls: Vec<String>,
+    webhook_poll_ms: u64,
+    webhook_timeout_ms: u64,
+    webhook_retries: u32,
+    webhook_retry_backoff_ms: u64,
+    database: ConcurrentNodeDatabase,
+    // kept across restarts of the subsystem, so the blocks already notified are not sent again
+    next_index: AtomicU64,
+}
+
+impl Runnable for Webhook {
+    fn name(&self) -> &str {
+        "webhooks"
+    }
+
+    fn run(&self) -> Result<()> {
+        self.start()
+    }
+}
+
+impl Webhook {
+    pub fn new(config: &Config, database: &ConcurrentNodeDatabase) -> Webhook {
+        Webhook {
+            webhook_urls: config.webhooks.clone(),
+            webhook_poll_ms: config.webhook_poll_ms,
+            webhook_timeout_ms: config.webhook_timeout_ms,
+            webhook_retries: config.webhook_retries,
+            webhook_retry_backoff_ms: config.webhook_retry_backoff_ms,
+            database: database.clone(),
+            next_index: AtomicU64::new(0),
+        }
+    }
+
+    pub fn start(&self) -> Result<()> {
+        if self.webhook_urls.is_empty() {
+            info!("No webhooks configured, exiting webhook system");
+            return Ok(());
+        }
+
+        info!(
+            "start webhook system with urls: {}",
+            self.webhook_urls.join(", ")
+        );
+
+        // At regular intervals of time, we notify the new blocks to all webhooks
+        // so notifications never block the processing of new blocks
+        loop {
+            self.notify_new_blocks();
+            sleep_millis(self.webhook_poll_ms);
+        }
+    }
+
+    // Notify all the blocks added since the last notified one
+    fn notify_new_blocks(&self) {
+        let start_index = self.next_index.load(Ordering::SeqCst);
+        let num_blocks = self.database.get_block_count();
+        let new_blocks = self
+            .database
+            .get_blocks_range(start_index, num_blocks.saturating_sub(start_index));
+
+        for block in new_blocks.iter() {
+            for url in self.webhook_urls.iter() {
+                self.notify_block(url, block);
+            }
+            self.next_index.store(block.index + 1, Ordering::SeqCst);
+        }
+    }
+
+    // Send a block to a webhook, retrying a limited amount of times if it fails
+    fn notify_block(&self, url: &str, block: &Block) {
+        let mut backoff_ms = self.webhook_retry_backoff_ms;
+        for attempt in 0..=self.webhook_retries {
+            if attempt > 0 {
+                sleep_millis(backoff_ms);
+                backoff_ms = backoff_ms.saturating_mul(2);
+            }
+
+            if self.send_block(url, block) {
+                info!("Notified block {} to webhook {}", block.index, url);
+                return;
+            }
+        }
+
+        error!("Could not notify block {} to webhook {}", block.index, url);
+    }
+
+    // Returns whether the webhook received the block successfully
+    fn send_block(&self, url: &str, block: &Block) -> bool {
+        let body = serde_json::to_string(&block).unwrap();
+
+        let request = Request::post(url)
+            .header("Content-Type", "application/json")
+            .timeout(Duration::from_millis(self.webhook_timeout_ms))
+            .body(body);
+
+        let request = match request {
+            Ok(value) => value,
+            Err(_) => return false,
+        };
+
+        match isahc::send(request) {
+            Ok(response) => response.status().is_success(),
+            Err(_) => false,
+        }
+    }
+}
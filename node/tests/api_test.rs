@@ -1,10 +1,16 @@
 mod utils;
+use std::{env, fs, thread, time::Duration};
+
+use isahc::ReadResponseExt;
+use node::stats::{fetch_chain_stats, format_chain_stats};
 use rusty_fork::rusty_fork_test;
 use serial_test::serial;
 use spec::types::hash::ConsensusHash;
+use spec::types::Address;
 use spec::types::Block;
 use spec::types::Transaction;
-use spec::validators::BLOCK_SUBSIDY;
+use spec::types::FORMAT_VERSION;
+use spec::validators::{calculate_block_weight, BLOCK_SUBSIDY};
 
 use crate::utils::alice;
 use crate::utils::miner_address;
@@ -36,6 +42,193 @@ fn test_should_get_a_valid_genesis_block() {
     assert_eq!(genesis_block.index, 0);
 }
 
+#[test]
+#[serial]
+fn test_should_compress_blocks() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block and add another one
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+    node.add_valid_block();
+
+    // request the blocks supporting compression
+    let (content_encoding, compressed_blocks) = node.get_compressed_blocks();
+
+    // the response should be compressed...
+    assert!(content_encoding.is_some());
+
+    // ...and decompress to the exact same blocks
+    let blocks = node.get_blocks();
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(compressed_blocks, blocks);
+}
+
+#[test]
+#[serial]
+fn test_should_not_resend_unchanged_blocks() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+
+    // the first request returns the blocks along with an ETag
+    let res = node.get_blocks_if_none_match("\"unknown\"");
+    assert_eq!(res.status().as_u16(), 200);
+    let etag = res.headers().get("ETag").unwrap().to_str().unwrap().to_string();
+
+    // a new request with the same ETag should not return the blocks again
+    let res = node.get_blocks_if_none_match(&etag);
+    assert_eq!(res.status().as_u16(), 304);
+
+    // after appending a new block, the ETag is no longer valid
+    node.add_valid_block();
+    let res = node.get_blocks_if_none_match(&etag);
+    assert_eq!(res.status().as_u16(), 200);
+    let new_etag = res.headers().get("ETag").unwrap().to_str().unwrap();
+    assert_ne!(new_etag, etag);
+}
+
+#[test]
+#[serial]
+fn test_should_get_the_latest_block() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // there is no tip before the genesis block is mined
+    assert_eq!(node.get_latest_block(), None);
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+    assert_eq!(node.get_latest_block(), node.get_blocks().pop());
+
+    // the latest block follows the appended blocks
+    node.add_valid_block();
+    let blocks = node.get_blocks();
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(node.get_latest_block().unwrap(), blocks[1]);
+}
+
+#[test]
+#[serial]
+fn test_should_mine_exactly_one_block() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+
+    // a block is mined even without pending transactions, and the tip advances by one
+    let block = miner.mine_one_block();
+    assert_eq!(block.index, 1);
+    assert_eq!(block.transactions.len(), 1);
+    assert_eq!(node.get_blocks().len(), 2);
+    assert_eq!(node.get_last_block(), block);
+}
+
+#[test]
+#[serial]
+fn test_should_get_the_tip_header() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // there is no tip before the genesis block is mined
+    assert_eq!(node.get_tip(), None);
+
+    // mine the genesis block, and add another one
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+    node.add_valid_block();
+
+    let tip = node.get_tip().unwrap();
+    let tip_block = node.get_last_block();
+    assert_eq!(tip["index"], 1);
+    assert_eq!(tip["hash"], tip_block.hash.to_string());
+    assert!(tip["total_work"].as_u64().unwrap() > 0);
+}
+
+#[test]
+#[serial]
+fn test_should_include_coinbase_in_block_template() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+    let genesis_block = node.get_last_block();
+
+    // request a template for a specific miner address
+    let template = node.get_block_template(&alice());
+
+    // the template is for the next block...
+    assert_eq!(template.index, 1);
+    assert_eq!(template.previous_hash, genesis_block.hash);
+
+    // ...and already rewards the miner address
+    let coinbase = template.transactions.first().unwrap();
+    assert_eq!(*coinbase, Transaction::new_coinbase(alice()));
+    assert_eq!(template.hash, template.calculate_hash());
+}
+
+#[test]
+#[serial]
+fn test_should_stream_new_templates_to_miners() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+
+    // the current template is sent right after subscribing
+    let mut templates = node.subscribe_block_templates(&miner_address());
+    let template = templates.next_template();
+    assert_eq!(template.index, 1);
+    assert_eq!(template.transactions.len(), 1);
+
+    // and a new one, including the transaction, after it is submitted
+    let transaction = Transaction::new(miner_address(), alice(), 10);
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 200);
+
+    let template = templates.next_template();
+    assert_eq!(template.index, 1);
+    assert_eq!(template.transactions[0].recipient, miner_address());
+    assert_eq!(template.transactions[1], transaction);
+}
+
+#[test]
+#[serial]
+fn test_should_estimate_mining_time() {
+    // start the node
+    let node = TestServerBuilder::new().difficulty(10).build();
+    node.start();
+
+    // at difficulty 10, a block needs 1024 hashes on average
+    let mut res = node.estimate_mining_time(1024.0);
+    assert_eq!(res.status().as_u16(), 200);
+    let estimate: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(estimate["difficulty"], 10);
+    assert_eq!(estimate["expected_seconds"], 1.0);
+
+    // the hashrate must be a positive number
+    let res = node.estimate_mining_time(0.0);
+    assert_eq!(res.status().as_u16(), 400);
+}
+
 #[test]
 #[serial]
 fn test_should_let_add_transactions() {
@@ -52,9 +245,11 @@ fn test_should_let_add_transactions() {
     // the sender must the mining address,
     // as it should have funds from the coinbase reward of the genesis block
     let transaction = Transaction {
+        version: FORMAT_VERSION,
         sender: miner_address(),
         recipient: alice(),
-        amount: 10 as u64,
+        amount: 10,
+        data: vec![],
     };
     let res = node.add_transaction(&transaction);
     assert_eq!(res.status().as_u16(), 200);
@@ -78,6 +273,259 @@ fn test_should_let_add_transactions() {
     assert_eq!(*mined_transaction, transaction);
 }
 
+#[test]
+#[serial]
+fn test_should_reject_known_transactions_submitted_again() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block, so the miner has funds
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+
+    // a pending transaction is not added twice
+    let transaction = Transaction::new(miner_address(), alice(), 10);
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 200);
+    let mut res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 409);
+    assert_eq!(res.text().unwrap(), "Transaction already exists");
+    assert_eq!(node.get_transactions().len(), 1);
+
+    // nor once it is confirmed
+    miner.mine_blocks(1);
+    let mut res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 409);
+    assert_eq!(
+        res.text().unwrap(),
+        "Transaction already confirmed in block 1"
+    );
+    assert!(node.get_transactions().is_empty());
+}
+
+#[test]
+#[serial]
+fn test_should_add_hex_encoded_transactions() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block, so the miner has funds
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+
+    // the transaction is sent encoded as an offline signer would do
+    let transaction = Transaction::new(miner_address(), alice(), 10).with_data(vec![1, 2]);
+    let raw_transaction = hex::encode(bincode::serialize(&transaction).unwrap());
+    let res = node.add_hex_transaction(&raw_transaction);
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(node.get_transactions(), vec![transaction.clone()]);
+
+    // it goes through the same validations as the JSON transactions
+    let mut res = node.add_hex_transaction(&raw_transaction);
+    assert_eq!(res.status().as_u16(), 409);
+    assert_eq!(res.text().unwrap(), "Transaction already exists");
+
+    // a body that is not hex is rejected with a clear error
+    let mut res = node.add_hex_transaction("not hex");
+    assert_eq!(res.status().as_u16(), 400);
+    let error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(error["code"], "malformed_transaction");
+    assert!(error["message"].as_str().unwrap().starts_with("Invalid hex"));
+
+    // as well as hex that does not encode a transaction
+    let mut res = node.add_hex_transaction("0102");
+    assert_eq!(res.status().as_u16(), 400);
+    let error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(error["code"], "malformed_transaction");
+}
+
+#[test]
+#[serial]
+fn test_should_let_delete_transactions() {
+    // start the node with the admin endpoints
+    let node = TestServerBuilder::new().admin_endpoints().build();
+    node.start();
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+
+    // add a new transaction to the pool
+    let transaction = Transaction {
+        version: FORMAT_VERSION,
+        sender: miner_address(),
+        recipient: alice(),
+        amount: 10,
+        data: vec![],
+    };
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(node.get_transactions().len(), 1);
+
+    // deleting the transaction should remove it from the pool
+    let res = node.delete_transaction(&transaction.id());
+    assert_eq!(res.status().as_u16(), 200);
+    assert!(node.get_transactions().is_empty());
+
+    // deleting an unknown transaction should return not found
+    let res = node.delete_transaction(&transaction.id());
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[test]
+#[serial]
+fn test_should_not_delete_transactions_by_default() {
+    // start the node without the admin endpoints
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+
+    // add a new transaction to the pool
+    let transaction = Transaction::new(miner_address(), alice(), 10);
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 200);
+
+    // anyone reaching the node cannot delete it
+    let res = node.delete_transaction(&transaction.id());
+    assert_eq!(res.status().as_u16(), 403);
+    assert_eq!(node.get_transactions().len(), 1);
+}
+
+#[test]
+#[serial]
+fn test_should_count_pending_transactions() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+    assert_eq!(node.get_pending_transaction_count(), 0);
+
+    // the count follows the transactions added to the pool
+    for amount in [10, 20] {
+        let transaction = Transaction::new(miner_address(), alice(), amount);
+        let res = node.add_transaction(&transaction);
+        assert_eq!(res.status().as_u16(), 200);
+    }
+    assert_eq!(node.get_pending_transaction_count(), 2);
+
+    // and the mined transactions are no longer pending
+    miner.mine_blocks(1);
+    assert_eq!(node.get_pending_transaction_count(), 0);
+}
+
+#[test]
+#[serial]
+fn test_should_rate_limit_transactions() {
+    // start the node allowing a burst of only two write requests
+    let node = TestServerBuilder::new().rate_limit(2, 1).build();
+    node.start();
+
+    let transaction = Transaction {
+        version: FORMAT_VERSION,
+        sender: miner_address(),
+        recipient: alice(),
+        amount: 10,
+        data: vec![],
+    };
+
+    // the requests within the burst are processed (and rejected as the sender has no funds)
+    assert_eq!(node.add_transaction(&transaction).status().as_u16(), 400);
+    assert_eq!(node.add_transaction(&transaction).status().as_u16(), 400);
+
+    // but the requests beyond the burst are not processed at all
+    assert_eq!(node.add_transaction(&transaction).status().as_u16(), 429);
+
+    // eventually the node allows new requests
+    thread::sleep(Duration::from_millis(1100));
+    assert_eq!(node.add_transaction(&transaction).status().as_u16(), 400);
+}
+
+#[test]
+#[serial]
+fn test_should_verify_the_chain_beyond_the_write_rate_limit() {
+    // start the node allowing a burst of only one write request
+    let node = TestServerBuilder::new().rate_limit(1, 1).build();
+    node.start();
+
+    // the writes are over the limit
+    let transaction = Transaction {
+        version: FORMAT_VERSION,
+        sender: miner_address(),
+        recipient: alice(),
+        amount: 10,
+        data: vec![],
+    };
+    assert_eq!(node.add_transaction(&transaction).status().as_u16(), 400);
+    assert_eq!(node.add_transaction(&transaction).status().as_u16(), 429);
+
+    // but the verification of the chain has its own limit
+    let report: serde_json::Value =
+        serde_json::from_str(&node.get_raw_body("/chain/verify")).unwrap();
+    assert_eq!(report["ok"], true);
+    assert!(report["first_error"].is_null());
+}
+
+#[test]
+#[serial]
+fn test_should_only_allow_configured_cors_origins() {
+    // start the node allowing a single origin
+    let allowed_origin = "http://explorer.example.com";
+    let node = TestServerBuilder::new()
+        .cors_allowed_origin(allowed_origin)
+        .build();
+    node.start();
+
+    // the allowed origin can read the blocks
+    let res = node.preflight_request("/blocks", allowed_origin, "GET");
+    assert_eq!(res.status().as_u16(), 200);
+    let allow_origin = res.headers().get("Access-Control-Allow-Origin").unwrap();
+    assert_eq!(allow_origin, allowed_origin);
+
+    // but it cannot write new blocks
+    let res = node.preflight_request("/blocks", allowed_origin, "POST");
+    assert!(res.headers().get("Access-Control-Allow-Origin").is_none());
+
+    // other origins are refused
+    let res = node.preflight_request("/blocks", "http://other.example.com", "GET");
+    assert_eq!(res.status().as_u16(), 400);
+    assert!(res.headers().get("Access-Control-Allow-Origin").is_none());
+}
+
+#[test]
+#[serial]
+fn test_should_never_allow_cors_writes() {
+    // start the node allowing POST requests from a single origin, e.g. for the simulations
+    let allowed_origin = "http://explorer.example.com";
+    let node = TestServerBuilder::new()
+        .cors_allowed_origin(allowed_origin)
+        .cors_allowed_method("POST")
+        .build();
+    node.start();
+
+    // the origin can simulate transactions
+    let res = node.preflight_request("/simulate", allowed_origin, "POST");
+    assert_eq!(res.status().as_u16(), 200);
+    let allow_origin = res.headers().get("Access-Control-Allow-Origin").unwrap();
+    assert_eq!(allow_origin, allowed_origin);
+
+    // but it cannot send them, even if the method is allowed
+    let res = node.preflight_request("/transactions", allowed_origin, "POST");
+    assert!(res.headers().get("Access-Control-Allow-Origin").is_none());
+
+    // while the reads on the same path are still allowed
+    let res = node.preflight_request("/transactions", allowed_origin, "GET");
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(node.get_transactions().len(), 0);
+}
+
 #[test]
 #[serial]
 fn test_should_let_add_valid_block() {
@@ -92,9 +540,11 @@ fn test_should_let_add_valid_block() {
 
     // build a valid block
     let coinbase = Transaction {
-        sender: miner_address(),
+        version: FORMAT_VERSION,
+        sender: Address::default(),
         recipient: alice(),
         amount: BLOCK_SUBSIDY,
+        data: vec![],
     };
     let valid_block = Block::new(1, 0, genesis_block.hash, vec![coinbase]);
 
@@ -121,4 +571,539 @@ fn test_should_not_let_add_invalid_block() {
     let res = node.add_block(&invalid_block);
     assert_eq!(res.status().as_u16(), 400);
 }
+
+#[test]
+#[serial]
+fn test_should_tell_stale_blocks_apart() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block, and a block extending it
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+    let genesis_block = node.get_last_block();
+    node.add_valid_block();
+    let tip_block = node.get_last_block();
+
+    // a block mined on the previous tip is stale
+    let coinbase = Transaction::new_coinbase(alice());
+    let stale_block = Block::new(1, 0, genesis_block.hash, vec![coinbase.clone()]);
+    let mut res = node.add_block(&stale_block);
+    assert_eq!(res.status().as_u16(), 400);
+    let api_error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(api_error["code"], "stale_block");
+
+    // while a block on the current tip can still be invalid
+    let mut invalid_block = Block::new(2, 0, tip_block.hash.clone(), vec![coinbase.clone()]);
+    invalid_block.nonce += 1;
+    let mut res = node.add_block(&invalid_block);
+    assert_eq!(res.status().as_u16(), 400);
+    let api_error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(api_error["code"], "invalid_block");
+
+    // as well as a block beyond the tip, which no template could have been for
+    let future_block = Block::new(5, 0, tip_block.hash.clone(), vec![coinbase]);
+    let mut res = node.add_block(&future_block);
+    assert_eq!(res.status().as_u16(), 400);
+    let api_error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(api_error["code"], "invalid_block");
+
+    // and a block that we already have is neither of them
+    let mut res = node.add_block(&tip_block);
+    assert_eq!(res.status().as_u16(), 409);
+    let api_error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(api_error["code"], "duplicate_block");
+}
+
+#[test]
+#[serial]
+fn test_should_validate_blocks_without_adding_them() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+    let genesis_block = node.get_last_block();
+
+    // a valid block is reported as valid...
+    let coinbase = Transaction::new_coinbase(alice());
+    let valid_block = Block::new(1, 0, genesis_block.hash.clone(), vec![coinbase]);
+    let result = node.validate_block(&valid_block);
+    assert_eq!(result["valid"], true);
+    assert!(result["error"].is_null());
+
+    // ...and an invalid one along with the reason
+    let invalid_block = Block::new(1, 0, ConsensusHash::default(), vec![]);
+    let result = node.validate_block(&invalid_block);
+    assert_eq!(result["valid"], false);
+    assert!(!result["error"].as_str().unwrap().is_empty());
+
+    // none of them is added to the blockchain
+    assert_eq!(node.get_blocks(), vec![genesis_block]);
+}
+
+#[test]
+#[serial]
+fn test_should_validate_transactions_without_adding_them() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+
+    // the miner has funds from the genesis block, but alice does not
+    let valid_transaction = Transaction::new(miner_address(), alice(), 10);
+    let result = node.validate_transaction(&valid_transaction);
+    assert_eq!(result["valid"], true);
+
+    let invalid_transaction = Transaction::new(alice(), miner_address(), 10);
+    let result = node.validate_transaction(&invalid_transaction);
+    assert_eq!(result["valid"], false);
+    assert_eq!(result["error"], "Sender account does not exist");
+
+    // none of them is added to the pool
+    assert!(node.get_transactions().is_empty());
+}
+
+#[test]
+#[serial]
+fn test_should_simulate_transactions_without_applying_them() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+
+    // the transfer is reflected in the resulting balances of both accounts
+    let transaction = Transaction::new(miner_address(), alice(), 10);
+    let balances = node.simulate_transactions(&[transaction]);
+    assert_eq!(balances[0]["address"], miner_address().to_string());
+    assert_eq!(balances[0]["balance"], BLOCK_SUBSIDY - 10);
+    assert_eq!(balances[1]["address"], alice().to_string());
+    assert_eq!(balances[1]["balance"], 10);
+
+    // but the real balances are unchanged, and the transaction is not added to the pool
+    assert_eq!(node.get_balance(&miner_address())["total"], BLOCK_SUBSIDY);
+    assert!(node.get_transactions().is_empty());
+}
+
+#[test]
+#[serial]
+fn test_should_limit_the_simulations() {
+    // start the node allowing a burst of only three requests: the mined block and two simulations
+    let node = TestServerBuilder::new().rate_limit(3, 1).build();
+    node.start();
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+
+    // too many transactions are rejected before simulating any of them
+    let transaction = Transaction::new(miner_address(), alice(), 1);
+    let transactions = vec![transaction.clone(); 101];
+    assert_eq!(node.simulate_raw_transactions(&transactions).status().as_u16(), 400);
+
+    // and the requests beyond the burst are not processed at all
+    let transactions = vec![transaction; 100];
+    assert_eq!(node.simulate_raw_transactions(&transactions).status().as_u16(), 200);
+    assert_eq!(node.simulate_raw_transactions(&transactions).status().as_u16(), 429);
+}
+
+#[test]
+#[serial]
+fn test_should_get_the_history_of_an_account() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block, and a block with a transfer to alice
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+    let transaction = Transaction::new(miner_address(), alice(), 10);
+    node.add_transaction(&transaction);
+    miner.mine_blocks(1);
+
+    // the miner received both coinbase rewards and sent the transfer
+    let history = node.get_history(&miner_address(), "");
+    assert_eq!(history.as_array().unwrap().len(), 3);
+
+    let history = node.get_history(&miner_address(), "direction=sent");
+    assert_eq!(history[0]["block_index"], 1);
+    assert_eq!(history[0]["transaction_id"], transaction.id().to_string());
+    assert_eq!(history[0]["transaction"]["amount"], 10);
+
+    // and the history can be read in pages
+    let history = node.get_history(&miner_address(), "direction=received&from=1&limit=5");
+    assert_eq!(history.as_array().unwrap().len(), 1);
+    assert_eq!(history[0]["block_index"], 1);
+    let history = node.get_history(&alice(), "direction=sent");
+    assert!(history.as_array().unwrap().is_empty());
+}
+
+#[test]
+#[serial]
+fn test_should_get_the_status_of_transactions() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+
+    // the node does not know about a transaction before it is submitted
+    let transaction = Transaction::new(miner_address(), alice(), 10);
+    let status = node.get_transaction_status(&transaction.id());
+    assert_eq!(status["status"], "unknown");
+
+    // then it is pending in the pool
+    node.add_transaction(&transaction);
+    let status = node.get_transaction_status(&transaction.id());
+    assert_eq!(status["status"], "pending");
+
+    // and confirmed once mined
+    miner.mine_blocks(1);
+    let tip_block = node.get_latest_block().unwrap();
+    let status = node.get_transaction_status(&transaction.id());
+    assert_eq!(status["status"], "confirmed");
+    assert_eq!(status["block_index"], 1);
+    assert_eq!(status["block_hash"], tip_block.hash.to_string());
+}
+
+#[test]
+#[serial]
+fn test_should_print_the_stats_of_a_running_node() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block, and leave a transaction in the pool
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+    let transaction = Transaction::new(miner_address(), alice(), 10);
+    node.add_transaction(&transaction);
+
+    let chain_stats = fetch_chain_stats(&node.get_base_url()).unwrap();
+    let tip_block = node.get_latest_block().unwrap();
+    // test nodes mine without difficulty nor weight limit
+    let expected_output = format!(
+        "height: {}\ntip_hash: {}\ndifficulty: 0\nmempool_size: 1\ntotal_supply: {}\n\
+        average_block_weight: {:.1}\naverage_block_fullness: none\nmempool_bytes: {}\n",
+        tip_block.index,
+        tip_block.hash,
+        BLOCK_SUBSIDY,
+        calculate_block_weight(&tip_block.transactions) as f64,
+        transaction.weight()
+    );
+    assert_eq!(format_chain_stats(&chain_stats), expected_output);
+}
+
+#[test]
+#[serial]
+fn test_should_write_all_the_coin_amounts_as_strings() {
+    // start a node with a premine, writing the coin amounts as strings
+    let node = TestServerBuilder::new()
+        .coins_as_strings()
+        .premine(alice(), 1000)
+        .build();
+    node.start();
+
+    // the miner reads the premine of the network to mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+
+    let network: serde_json::Value =
+        serde_json::from_str(&node.get_raw_body("/network")).unwrap();
+    assert_eq!(network["premine"][0]["amount"], "1000");
+
+    let expected_supply = BLOCK_SUBSIDY + 1000;
+    let stats: serde_json::Value = serde_json::from_str(&node.get_raw_body("/stats")).unwrap();
+    assert_eq!(stats["total_supply"], expected_supply.to_string());
+    // other numbers are left as they are
+    assert_eq!(stats["height"], 0);
+
+    // and the stats command still reads them
+    let chain_stats = fetch_chain_stats(&node.get_base_url()).unwrap();
+    assert_eq!(chain_stats.total_supply, expected_supply);
+}
+
+#[test]
+#[serial]
+fn test_should_get_the_settings_of_the_node() {
+    // start the node, with a webhook whose URL must not be exposed
+    let node = TestServerBuilder::new()
+        .peer(8001)
+        .webhook("http://localhost:9999/hook?token=secret")
+        .build();
+    node.start();
+
+    let settings = node.get_config();
+    assert_eq!(settings["port"], node.config.port);
+    assert_eq!(settings["peers"], serde_json::json!(["http://localhost:8001"]));
+    assert_eq!(settings["difficulty"], node.config.difficulty);
+    assert_eq!(
+        settings["target_block_time_ms"],
+        node.config.target_block_time_ms
+    );
+    assert_eq!(
+        settings["max_mempool_bytes"],
+        node.config.max_mempool_bytes
+    );
+
+    // the network id is the same hash committed by the genesis block
+    let genesis = node.get_genesis();
+    assert_eq!(settings["network_id"], genesis["network_hash"]);
+
+    // secrets are never exposed
+    assert!(settings.get("webhooks").is_none());
+    assert!(!settings.to_string().contains("secret"));
+}
+
+#[test]
+#[serial]
+fn test_should_pretty_print_json_on_request() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // the default responses are compact
+    let compact_body = node.get_raw_body("/network");
+    assert!(!compact_body.contains('\n'));
+
+    // the pretty responses are indented, with the same content
+    let pretty_body = node.get_raw_body("/network?pretty=true");
+    assert!(pretty_body.contains("\n  \"difficulty\": "));
+
+    let compact_value: serde_json::Value = serde_json::from_str(&compact_body).unwrap();
+    let pretty_value: serde_json::Value = serde_json::from_str(&pretty_body).unwrap();
+    assert_eq!(compact_value, pretty_value);
+}
+
+#[test]
+#[serial]
+fn test_should_get_total_and_spendable_balance() {
+    // start the node, the coinbase rewards can only be spent after two more blocks
+    let node = TestServerBuilder::new().coinbase_maturity(2).build();
+    node.start();
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+
+    // the coinbase reward is not mature yet
+    let balance = node.get_balance(&miner_address());
+    assert_eq!(balance["total"], BLOCK_SUBSIDY);
+    assert_eq!(balance["spendable"], 0);
+
+    // but it is after adding two more blocks
+    node.add_valid_block();
+    node.add_valid_block();
+    let balance = node.get_balance(&miner_address());
+    assert_eq!(balance["total"], BLOCK_SUBSIDY);
+    assert_eq!(balance["spendable"], BLOCK_SUBSIDY);
+}
+
+#[test]
+#[serial]
+fn test_should_list_forks_and_orphans() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // mine the genesis block and add another one
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+    let genesis_block = node.get_last_block();
+    node.add_valid_block();
+    let tip_block = node.get_last_block();
+
+    // feed a competing branch of two blocks starting after the genesis block
+    let coinbase = Transaction::new_coinbase(alice());
+    let side_block_1 = Block::new(1, 0, genesis_block.hash, vec![coinbase.clone()]);
+    let side_block_2 = Block::new(2, 0, side_block_1.hash.clone(), vec![coinbase.clone()]);
+    assert_eq!(node.add_block(&side_block_1).status().as_u16(), 400);
+    assert_eq!(node.add_block(&side_block_2).status().as_u16(), 400);
+
+    // and a block whose parent is unknown
+    let orphan_block = Block::new(5, 0, ConsensusHash::default(), vec![coinbase]);
+    assert_eq!(node.add_block(&orphan_block).status().as_u16(), 400);
+
+    // both the main chain and the competing branch are listed, along with their work
+    let forks = node.get_forks();
+    assert_eq!(forks.as_array().unwrap().len(), 2);
+    assert_eq!(forks[0]["hash"], tip_block.hash.to_string());
+    assert_eq!(forks[0]["height"], tip_block.index);
+    assert_eq!(forks[0]["total_work"], 2);
+    assert_eq!(forks[0]["main"], true);
+    assert_eq!(forks[1]["hash"], side_block_2.hash.to_string());
+    assert_eq!(forks[1]["height"], side_block_2.index);
+    assert_eq!(forks[1]["total_work"], 3);
+    assert_eq!(forks[1]["main"], false);
+
+    // the orphan is waiting for its parent
+    assert_eq!(node.get_orphans(), vec![orphan_block]);
+
+    // the main chain is not modified
+    assert_eq!(node.get_last_block(), tip_block);
+}
+
+#[test]
+#[serial]
+fn test_should_describe_all_routes_in_openapi() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // the document is valid JSON following the OpenAPI 3 specification
+    let document = node.get_openapi();
+    assert!(document["openapi"].as_str().unwrap().starts_with("3."));
+
+    // each registered route is described, along with its methods
+    let routes = [
+        ("/openapi.json", "get"),
+        ("/network", "get"),
+        ("/genesis", "get"),
+        ("/block_template", "get"),
+        ("/events/block_template", "get"),
+        ("/estimate_mining_time", "get"),
+        ("/blocks", "get"),
+        ("/blocks", "post"),
+        ("/blocks/latest", "get"),
+        ("/blocks/locate", "post"),
+        ("/tip", "get"),
+        ("/transactions", "get"),
+        ("/transactions", "post"),
+        ("/transactions/raw", "post"),
+        ("/transactions/pending/count", "get"),
+        ("/transactions/{id}", "delete"),
+        ("/transactions/{id}/status", "get"),
+        ("/validate/block", "post"),
+        ("/validate/transaction", "post"),
+        ("/simulate", "post"),
+        ("/accounts/{address}/balance", "get"),
+        ("/accounts/{address}/history", "get"),
+        ("/peers/stats", "get"),
+        ("/stats", "get"),
+        ("/time", "get"),
+        ("/config", "get"),
+        ("/forks", "get"),
+        ("/orphans", "get"),
+        ("/chain/export", "get"),
+        ("/chain/verify", "get"),
+    ];
+    for (path, method) in routes {
+        assert!(document["paths"][path][method].is_object(), "{} {}", method, path);
+    }
+
+    // and the schemas are derived from the blockchain types
+    let schemas = &document["components"]["schemas"];
+    assert!(schemas["Block"]["properties"]["transactions"].is_object());
+    assert!(schemas["Transaction"]["properties"]["amount"].is_object());
+}
+
+#[test]
+#[serial]
+fn test_should_return_structured_error_for_malformed_body() {
+    // start the node
+    let node = TestServerBuilder::new().build();
+    node.start();
+
+    // send a transaction that is not valid JSON
+    let mut res = node.add_raw_transaction("{\"sender\": ");
+    assert_eq!(res.status().as_u16(), 400);
+
+    // the error is returned as JSON, along with the parse detail
+    let content_type = res.headers().get("Content-Type").unwrap().to_str().unwrap();
+    assert_eq!(content_type, "application/json");
+    let error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(error["code"], "malformed_request_body");
+    assert!(!error["message"].as_str().unwrap().is_empty());
+}
+
+#[test]
+#[serial]
+fn test_should_start_a_node_from_an_exported_chain() {
+    // start a node with some blocks and transactions
+    let node = TestServerBuilder::new().port(8000).build();
+    node.start();
+    let miner = Miner::new_with_node(&node);
+    miner.mine_blocks(1);
+    let transaction = Transaction::new(miner_address(), alice(), 10);
+    assert_eq!(node.add_transaction(&transaction).status().as_u16(), 200);
+    miner.mine_blocks(1);
+
+    // store its chain into a file
+    let export_path = env::temp_dir().join("blockchain_export_test.bin");
+    fs::write(&export_path, node.export_chain()).unwrap();
+
+    // a fresh node importing the chain has the same blocks and balances, without peers
+    let new_node = TestServerBuilder::new()
+        .port(8001)
+        .import_chain(export_path.to_str().unwrap())
+        .build();
+    new_node.start();
+
+    assert_eq!(new_node.get_blocks(), node.get_blocks());
+    assert_eq!(new_node.get_balance(&alice()), node.get_balance(&alice()));
+    assert_eq!(
+        new_node.get_balance(&miner_address()),
+        node.get_balance(&miner_address())
+    );
+}
+
+#[test]
+#[serial]
+fn test_should_rate_limit_the_chain_exports() {
+    // start the node allowing a burst of only one request
+    let node = TestServerBuilder::new().rate_limit(1, 1).build();
+    node.start();
+
+    // the first export is served
+    let uri = format!("{}/chain/export", node.get_base_url());
+    assert_eq!(isahc::get(&uri).unwrap().status().as_u16(), 200);
+
+    // but the exports beyond the burst are not
+    assert_eq!(isahc::get(&uri).unwrap().status().as_u16(), 429);
+}
+
+#[test]
+#[serial]
+fn test_should_only_serve_reads_in_read_only_mode() {
+    // start a regular node with the genesis block
+    let node = TestServerBuilder::new().port(8000).build();
+    node.start();
+    let miner = Miner::new_with_node(&node);
+    miner.mine_blocks(1);
+
+    // and a read-only node that syncs from it
+    let read_only_node = TestServerBuilder::new()
+        .port(8001)
+        .peer(8000)
+        .read_only()
+        .build();
+    read_only_node.start();
+    read_only_node.wait_for_peer_sync();
+
+    // blocks can be queried
+    assert_eq!(read_only_node.get_blocks(), node.get_blocks());
+
+    // but transactions, blocks and mining are rejected
+    let transaction = Transaction::new(miner_address(), alice(), 10);
+    let res = read_only_node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = read_only_node.add_valid_block();
+    assert_eq!(res.status().as_u16(), 403);
+
+    let uri = format!("{}/block_template", read_only_node.get_base_url());
+    let res = isahc::get(uri).unwrap();
+    assert_eq!(res.status().as_u16(), 403);
+}
 }
@@ -2,6 +2,10 @@ mod utils;
 use rusty_fork::rusty_fork_test;
 use serial_test::serial;
 
+use spec::types::Transaction;
+
+use crate::utils::alice;
+use crate::utils::miner_address;
 use crate::utils::Miner;
 use crate::utils::TestServerBuilder;
 
@@ -44,6 +48,34 @@ fn test_should_receive_new_valid_blocks() {
     assert_eq!(last_follower_block, last_leader_block);
 }
 
+#[test]
+#[serial]
+fn test_should_share_the_genesis_with_peers_of_the_same_network() {
+    let leader_node = TestServerBuilder::new().port(8000).build();
+    leader_node.start();
+
+    // mine the genesis block in the leader node
+    let miner = Miner::new_with_node(&leader_node);
+    miner.mine_blocks(1);
+
+    // a node with the same network receives the same genesis block
+    let follower_node = TestServerBuilder::new().port(8001).peer(8000).build();
+    follower_node.start();
+    follower_node.wait_for_peer_sync();
+
+    let genesis = leader_node.get_genesis();
+    assert_eq!(genesis["block"], serde_json::to_value(leader_node.get_blocks()[0].clone()).unwrap());
+    assert_eq!(follower_node.get_genesis(), genesis);
+
+    // while a node with a different network definition does not
+    let other_node = TestServerBuilder::new().difficulty(20).port(8002).build();
+    other_node.start();
+
+    let other_genesis = other_node.get_genesis();
+    assert_ne!(other_genesis["network_hash"], genesis["network_hash"]);
+    assert_ne!(other_genesis["network"], genesis["network"]);
+}
+
 #[test]
 #[serial]
 fn test_should_not_receive_new_invalid_blocks() {
@@ -68,8 +100,8 @@ fn test_should_not_receive_new_invalid_blocks() {
     // the follower node should eventually ask and receive the new block
     follower_node.wait_for_peer_sync();
 
-    // but the block should not be added as the difficulty will not match
-    assert_eq!(follower_node.get_blocks().len(), 1);
+    // but the blocks should not be added as the difficulty will not match
+    assert!(follower_node.get_blocks().is_empty());
 }
 
 #[test]
@@ -102,6 +134,40 @@ fn test_should_ignore_unavailable_peers() {
     assert_eq!(follower_node.get_blocks().len(), 2);
 }
 
+#[test]
+#[serial]
+fn test_should_track_peer_stats() {
+    // We will use this node to be the most updated one
+    let leader_node = TestServerBuilder::new().port(8000).build();
+    leader_node.start();
+
+    // mine the genesis block in the leader node
+    let miner = Miner::new_with_node(&leader_node);
+    miner.mine_blocks(1);
+
+    // This new node will sync from the leader node and from a node that does not exist
+    let follower_node = TestServerBuilder::new()
+        .port(8001)
+        .peer(9000)
+        .peer(8000)
+        .build();
+    follower_node.start();
+    follower_node.wait_for_peer_sync();
+
+    let stats = follower_node.get_peer_stats();
+
+    // the leader node synced successfully
+    let leader_stats = stats.get("http://localhost:8000").unwrap();
+    assert_eq!(leader_stats.blocks_received, 1);
+    assert!(leader_stats.last_successful_sync.is_some());
+
+    // but the unavailable node never did
+    let unavailable_stats = stats.get("http://localhost:9000").unwrap();
+    assert_eq!(unavailable_stats.blocks_received, 0);
+    assert!(unavailable_stats.failed_requests > 0);
+    assert!(unavailable_stats.last_successful_sync.is_none());
+}
+
 #[test]
 #[serial]
 fn test_should_send_new_blocks() {
@@ -137,4 +203,93 @@ fn test_should_send_new_blocks() {
     let last_follower_block = leader_node.get_last_block();
     assert_eq!(last_follower_block, last_leader_block);
 }
+
+#[test]
+#[serial]
+fn test_should_propagate_blocks_using_tcp_transport() {
+    // both nodes communicate using the tcp transport, while keeping the REST API for clients
+    let leader_node = TestServerBuilder::new()
+        .port(8000)
+        .tcp_transport(9000)
+        .build();
+    leader_node.start();
+    let follower_node = TestServerBuilder::new()
+        .port(8001)
+        .tcp_transport(9001)
+        .tcp_peer(9000)
+        .build();
+    follower_node.start();
+
+    // mine the genesis block in the leader node
+    let miner = Miner::new_with_node(&leader_node);
+    miner.mine_blocks(1);
+
+    // the follower should receive the genesis block
+    follower_node.wait_for_peer_sync();
+    assert_eq!(follower_node.get_blocks(), leader_node.get_blocks());
+
+    // a new block added to the follower is pushed to the leader, even if it does not have the follower as peer
+    follower_node.add_valid_block();
+    leader_node.wait_for_peer_sync();
+    assert_eq!(leader_node.get_blocks().len(), 2);
+    assert_eq!(leader_node.get_last_block(), follower_node.get_last_block());
+}
+
+#[test]
+#[serial]
+fn test_should_relay_transactions_using_tcp_transport() {
+    // the follower node is the only one that knows about the other
+    let leader_node = TestServerBuilder::new()
+        .port(8000)
+        .tcp_transport(9000)
+        .build();
+    leader_node.start();
+    let follower_node = TestServerBuilder::new()
+        .port(8001)
+        .tcp_transport(9001)
+        .tcp_peer(9000)
+        .build();
+    follower_node.start();
+
+    // mine the genesis block in the leader node, so the miner has funds
+    let miner = Miner::new_with_node(&leader_node);
+    miner.mine_blocks(1);
+    follower_node.wait_for_peer_sync();
+
+    // a transaction submitted to the follower is pushed to the leader
+    let transaction = Transaction::new(miner_address(), alice(), 10);
+    assert_eq!(follower_node.add_transaction(&transaction).status().as_u16(), 200);
+    leader_node.wait_for_peer_sync();
+    assert_eq!(leader_node.get_transactions(), vec![transaction]);
+}
+
+#[test]
+#[serial]
+fn test_should_relay_mempool_transactions() {
+    // the follower node is the only one that knows about the other
+    let leader_node = TestServerBuilder::new().port(8000).build();
+    leader_node.start();
+    let follower_node = TestServerBuilder::new().port(8001).peer(8000).build();
+    follower_node.start();
+
+    // mine the genesis block in the leader node, so the miner has funds
+    let miner = Miner::new_with_node(&leader_node);
+    miner.mine_blocks(1);
+    follower_node.wait_for_peer_sync();
+
+    // a transaction submitted to the leader appears in the follower mempool
+    let leader_transaction = Transaction::new(miner_address(), alice(), 10);
+    assert_eq!(leader_node.add_transaction(&leader_transaction).status().as_u16(), 200);
+    follower_node.wait_for_peer_sync();
+    assert_eq!(follower_node.get_transactions(), vec![leader_transaction.clone()]);
+
+    // and the other way around
+    let follower_transaction = Transaction::new(miner_address(), alice(), 20);
+    assert_eq!(follower_node.add_transaction(&follower_transaction).status().as_u16(), 200);
+    follower_node.wait_for_peer_sync();
+    assert_eq!(
+        leader_node.get_transactions(),
+        vec![leader_transaction, follower_transaction]
+    );
+}
 }
@@ -1,11 +1,30 @@
-use std::{thread, time::Duration};
+#![allow(dead_code)]
 
-use isahc::{Body, ReadResponseExt, Request, Response};
-use node::{server::Server, util::config::Config};
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader},
+    thread,
+    time::Duration,
+};
+
+use isahc::{config::Configurable, Body, ReadResponseExt, Request, Response};
+use node::{
+    peer::stats::PeerStats,
+    server::Server,
+    util::config::{Config, MempoolConflictPolicy, PeerTransport},
+};
 
-use miner::{cli::MinerArgs, mining_loop::run_mining_loop, node_client::NetworkNodeClient};
+use miner::{
+    block_miner::CancellationToken,
+    cli::MinerArgs,
+    mining_loop::{mine_one_block, run_mining_loop},
+    node_client::NetworkNodeClient,
+};
 use spec::{
-    types::{Address, Block, Transaction},
+    types::{
+        hash::ConsensusHash, Address, Block, Coin, GenesisAllocation, PowAlgorithm, Transaction,
+        FORMAT_VERSION,
+    },
     validators::BLOCK_SUBSIDY,
 };
 
@@ -16,7 +35,6 @@ pub struct TestServer {
     pub config: Config,
 }
 
-#[allow(dead_code)]
 impl TestServer {
     pub fn start(&self) {
         // thread::sleep(Duration::from_millis(1100));
@@ -47,17 +65,63 @@ pub struct TestServerBuilder {
     config: Config,
 }
 
-#[allow(dead_code)]
 impl TestServerBuilder {
     pub fn new() -> TestServerBuilder {
         // set the default values
         let config = Config {
+            command: None,
             port: DEFAULT_PORT,
             // not to high to avoid waiting too much, not too shot to spam it
             peer_sync_ms: 10,
+            sync_window_size: 100,
+            max_peer_response_bytes: 67108864,
             // no difficulty to minimize the mining time
             difficulty: DEFAULT_DIFFICULTY,
+            max_transaction_data_size: 256,
+            target_block_time_ms: 10000,
+            retarget_window: 0,
+            min_difficulty: 0,
+            max_difficulty: 256,
+            coinbase_maturity: 0,
+            pow_algorithm: PowAlgorithm::Sha3,
+            premine: vec![],
+            max_block_weight: 0,
+            genesis_difficulty: None,
+            max_txs_per_sender_per_block: 0,
+            warmup_blocks: 0,
+            mempool_conflict_policy: MempoolConflictPolicy::RejectSecond,
+            max_mempool_bytes: 33554432,
+            max_future_blocks: 100,
+            mempool_reconcile_ms: 60000,
+            mempool_reconcile_depth: 100,
+            dedup_depth: 100,
+            import_chain: None,
             peers: Vec::<String>::new(),
+            max_clock_skew_ms: 60000,
+            max_peer_backoff_ms: 300000,
+            block_fanout: 0,
+            peer_transport: PeerTransport::Http,
+            tcp_port: 9000,
+            coins_as_strings: false,
+            pretty_json: false,
+            read_only: false,
+            admin_endpoints: false,
+            api_workers: 0,
+            api_backlog: 2048,
+            write_queue_capacity: 1024,
+            rate_limit_burst: 100,
+            rate_limit_per_second: 10,
+            cors_allowed_origins: vec![],
+            cors_allowed_methods: vec!["GET".to_string()],
+            webhooks: vec![],
+            webhook_poll_ms: 10,
+            webhook_timeout_ms: 1000,
+            webhook_retries: 3,
+            webhook_retry_backoff_ms: 200,
+            max_subsystem_restarts: 5,
+            subsystem_restart_backoff_ms: 1000,
+            subsystem_stable_ms: 60000,
+            shutdown_timeout_ms: 5000,
         };
 
         TestServerBuilder { config }
@@ -79,6 +143,74 @@ impl TestServerBuilder {
         self
     }
 
+    // Use the TCP transport to communicate with peers, listening on the indicated port
+    pub fn tcp_transport(mut self, tcp_port: u16) -> TestServerBuilder {
+        self.config.peer_transport = PeerTransport::Tcp;
+        self.config.tcp_port = tcp_port;
+        self
+    }
+
+    pub fn tcp_peer(mut self, tcp_port: u16) -> TestServerBuilder {
+        let address = format!("localhost:{}", tcp_port);
+        self.config.peers.push(address);
+        self
+    }
+
+    pub fn read_only(mut self) -> TestServerBuilder {
+        self.config.read_only = true;
+        self
+    }
+
+    pub fn admin_endpoints(mut self) -> TestServerBuilder {
+        self.config.admin_endpoints = true;
+        self
+    }
+
+    pub fn rate_limit(mut self, burst: u32, per_second: u32) -> TestServerBuilder {
+        self.config.rate_limit_burst = burst;
+        self.config.rate_limit_per_second = per_second;
+        self
+    }
+
+    pub fn cors_allowed_origin(mut self, origin: &str) -> TestServerBuilder {
+        self.config.cors_allowed_origins.push(origin.to_string());
+        self
+    }
+
+    pub fn cors_allowed_method(mut self, method: &str) -> TestServerBuilder {
+        self.config.cors_allowed_methods.push(method.to_string());
+        self
+    }
+
+    pub fn coinbase_maturity(mut self, coinbase_maturity: u64) -> TestServerBuilder {
+        self.config.coinbase_maturity = coinbase_maturity;
+        self
+    }
+
+    pub fn import_chain(mut self, path: &str) -> TestServerBuilder {
+        self.config.import_chain = Some(path.to_string());
+        self
+    }
+
+    pub fn coins_as_strings(mut self) -> TestServerBuilder {
+        self.config.coins_as_strings = true;
+        self
+    }
+
+    pub fn premine(mut self, address: Address, amount: Coin) -> TestServerBuilder {
+        self.config.premine.push(GenesisAllocation {
+            address,
+            amount,
+            unlock_height: 0,
+        });
+        self
+    }
+
+    pub fn webhook(mut self, url: &str) -> TestServerBuilder {
+        self.config.webhooks.push(url.to_string());
+        self
+    }
+
     pub fn build(self) -> TestServer {
         TestServer {
             config: self.config,
@@ -86,6 +218,12 @@ impl TestServerBuilder {
     }
 }
 
+impl Default for TestServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub fn miner_address() -> Address {
     Address::try_from(
         "fe8aa8cc6011898d49bdacd0ab52075e92e1dfb2915bb9223528a5737583731d".to_string(),
@@ -110,10 +248,38 @@ pub fn bob() -> Address {
 pub trait RestApi {
     fn get_base_url(&self) -> String;
     fn get_blocks(&self) -> Vec<Block>;
+    fn get_block_template(&self, miner_address: &Address) -> Block;
+    fn subscribe_block_templates(&self, miner_address: &Address) -> TemplateStream;
+    fn get_blocks_if_none_match(&self, etag: &str) -> Response<Body>;
+    fn estimate_mining_time(&self, hashrate: f64) -> Response<Body>;
+    fn get_compressed_blocks(&self) -> (Option<String>, Vec<Block>);
     fn get_last_block(&self) -> Block;
     fn add_block(&self, block: &Block) -> Response<Body>;
     fn add_valid_block(&self) -> Response<Body>;
     fn add_transaction(&self, transaction: &Transaction) -> Response<Body>;
+    fn add_raw_transaction(&self, body: &str) -> Response<Body>;
+    fn add_hex_transaction(&self, hex: &str) -> Response<Body>;
+    fn validate_block(&self, block: &Block) -> serde_json::Value;
+    fn validate_transaction(&self, transaction: &Transaction) -> serde_json::Value;
+    fn simulate_transactions(&self, transactions: &[Transaction]) -> serde_json::Value;
+    fn simulate_raw_transactions(&self, transactions: &[Transaction]) -> Response<Body>;
+    fn preflight_request(&self, path: &str, origin: &str, method: &str) -> Response<Body>;
+    fn get_transactions(&self) -> Vec<Transaction>;
+    fn get_pending_transaction_count(&self) -> u64;
+    fn get_latest_block(&self) -> Option<Block>;
+    fn get_tip(&self) -> Option<serde_json::Value>;
+    fn get_peer_stats(&self) -> BTreeMap<String, PeerStats>;
+    fn get_balance(&self, address: &Address) -> serde_json::Value;
+    fn get_transaction_status(&self, id: &ConsensusHash) -> serde_json::Value;
+    fn get_history(&self, address: &Address, query: &str) -> serde_json::Value;
+    fn get_forks(&self) -> serde_json::Value;
+    fn get_orphans(&self) -> Vec<Block>;
+    fn delete_transaction(&self, id: &ConsensusHash) -> Response<Body>;
+    fn get_openapi(&self) -> serde_json::Value;
+    fn get_genesis(&self) -> serde_json::Value;
+    fn get_config(&self) -> serde_json::Value;
+    fn get_raw_body(&self, path: &str) -> String;
+    fn export_chain(&self) -> Vec<u8>;
 }
 
 impl RestApi for TestServer {
@@ -136,6 +302,83 @@ impl RestApi for TestServer {
         blocks
     }
 
+    fn get_block_template(&self, miner_address: &Address) -> Block {
+        let uri = format!(
+            "{}/block_template?address={}",
+            self.get_base_url(),
+            miner_address
+        );
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn subscribe_block_templates(&self, miner_address: &Address) -> TemplateStream {
+        let uri = format!(
+            "{}/events/block_template?address={}",
+            self.get_base_url(),
+            miner_address
+        );
+        let request = Request::get(uri)
+            .timeout(Duration::from_secs(10))
+            .body(())
+            .unwrap();
+        let response = isahc::send(request).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        TemplateStream(BufReader::new(response.into_body()))
+    }
+
+    fn get_blocks_if_none_match(&self, etag: &str) -> Response<Body> {
+        let uri = format!("{}/blocks", self.get_base_url());
+        let request = Request::get(uri)
+            .header("If-None-Match", etag)
+            .body(())
+            .unwrap();
+
+        isahc::send(request).unwrap()
+    }
+
+    fn estimate_mining_time(&self, hashrate: f64) -> Response<Body> {
+        let uri = format!(
+            "{}/estimate_mining_time?hashrate={}",
+            self.get_base_url(),
+            hashrate
+        );
+
+        isahc::get(uri).unwrap()
+    }
+
+    // Returns the content encoding used by the node, along with the decompressed list of blocks
+    fn get_compressed_blocks(&self) -> (Option<String>, Vec<Block>) {
+        let uri = format!("{}/blocks", self.get_base_url());
+        let request = Request::get(uri)
+            .automatic_decompression(true)
+            .body(())
+            .unwrap();
+        let mut response = isahc::send(request).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let content_encoding = response
+            .headers()
+            .get("Content-Encoding")
+            .map(|value| value.to_str().unwrap().to_string());
+
+        // parse the list of blocks from the response body
+        let raw_body = response.text().unwrap();
+        let blocks: Vec<Block> = serde_json::from_str(&raw_body).unwrap();
+
+        (content_encoding, blocks)
+    }
+
     fn get_last_block(&self) -> Block {
         self.get_blocks().last().unwrap().to_owned()
     }
@@ -143,9 +386,11 @@ impl RestApi for TestServer {
     fn add_valid_block(&self) -> Response<Body> {
         let last_block = self.get_last_block();
         let coinbase = Transaction {
-            sender: alice(),
+            version: FORMAT_VERSION,
+            sender: Address::default(),
             recipient: bob(),
             amount: BLOCK_SUBSIDY,
+            data: vec![],
         };
 
         let index = last_block.index + 1;
@@ -171,6 +416,277 @@ impl RestApi for TestServer {
 
         post_request(uri, body)
     }
+
+    fn add_raw_transaction(&self, body: &str) -> Response<Body> {
+        // send the request to the REST API
+        let uri = format!("{}/transactions", self.get_base_url());
+
+        post_request(uri, body.to_string())
+    }
+
+    fn add_hex_transaction(&self, hex: &str) -> Response<Body> {
+        let uri = format!("{}/transactions/raw", self.get_base_url());
+        let request = Request::post(uri)
+            .header("Content-Type", "text/plain")
+            .body(hex.to_string())
+            .unwrap();
+
+        isahc::send(request).unwrap()
+    }
+
+    fn validate_block(&self, block: &Block) -> serde_json::Value {
+        let uri = format!("{}/validate/block", self.get_base_url());
+        let body = serde_json::to_string(&block).unwrap();
+        let mut response = post_request(uri, body);
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn validate_transaction(&self, transaction: &Transaction) -> serde_json::Value {
+        let uri = format!("{}/validate/transaction", self.get_base_url());
+        let body = serde_json::to_string(&transaction).unwrap();
+        let mut response = post_request(uri, body);
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn simulate_transactions(&self, transactions: &[Transaction]) -> serde_json::Value {
+        let mut response = self.simulate_raw_transactions(transactions);
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn simulate_raw_transactions(&self, transactions: &[Transaction]) -> Response<Body> {
+        let uri = format!("{}/simulate", self.get_base_url());
+        let body = serde_json::to_string(&transactions).unwrap();
+        post_request(uri, body)
+    }
+
+    fn preflight_request(&self, path: &str, origin: &str, method: &str) -> Response<Body> {
+        let uri = format!("{}{}", self.get_base_url(), path);
+        let request = Request::options(uri)
+            .header("Origin", origin)
+            .header("Access-Control-Request-Method", method)
+            .body(())
+            .unwrap();
+
+        isahc::send(request).unwrap()
+    }
+
+    fn get_transactions(&self) -> Vec<Transaction> {
+        // list the mempool transactions by querying the REST API
+        let uri = format!("{}/transactions", self.get_base_url());
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        // parse the list of transactions from the response body
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn get_latest_block(&self) -> Option<Block> {
+        let uri = format!("{}/blocks/latest", self.get_base_url());
+        let mut response = isahc::get(uri).unwrap();
+
+        // an empty blockchain has no tip block
+        if response.status().as_u16() == 404 {
+            return None;
+        }
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        Some(serde_json::from_str(&raw_body).unwrap())
+    }
+
+    fn get_tip(&self) -> Option<serde_json::Value> {
+        let uri = format!("{}/tip", self.get_base_url());
+        let mut response = isahc::get(uri).unwrap();
+
+        // an empty blockchain has no tip
+        if response.status().as_u16() == 404 {
+            return None;
+        }
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        Some(serde_json::from_str(&raw_body).unwrap())
+    }
+
+    fn get_pending_transaction_count(&self) -> u64 {
+        let uri = format!("{}/transactions/pending/count", self.get_base_url());
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        let body: serde_json::Value = serde_json::from_str(&raw_body).unwrap();
+        body["count"].as_u64().unwrap()
+    }
+
+    fn get_peer_stats(&self) -> BTreeMap<String, PeerStats> {
+        let uri = format!("{}/peers/stats", self.get_base_url());
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn get_balance(&self, address: &Address) -> serde_json::Value {
+        let uri = format!("{}/accounts/{}/balance", self.get_base_url(), address);
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn get_history(&self, address: &Address, query: &str) -> serde_json::Value {
+        let uri = format!(
+            "{}/accounts/{}/history?{}",
+            self.get_base_url(),
+            address,
+            query
+        );
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn get_transaction_status(&self, id: &ConsensusHash) -> serde_json::Value {
+        let uri = format!("{}/transactions/{}/status", self.get_base_url(), id);
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn get_forks(&self) -> serde_json::Value {
+        let uri = format!("{}/forks", self.get_base_url());
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn get_orphans(&self) -> Vec<Block> {
+        let uri = format!("{}/orphans", self.get_base_url());
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn delete_transaction(&self, id: &ConsensusHash) -> Response<Body> {
+        // send the request to the REST API
+        let uri = format!("{}/transactions/{}", self.get_base_url(), id);
+        let request = Request::delete(uri).body(()).unwrap();
+
+        isahc::send(request).unwrap()
+    }
+
+    fn get_openapi(&self) -> serde_json::Value {
+        let uri = format!("{}/openapi.json", self.get_base_url());
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn get_genesis(&self) -> serde_json::Value {
+        let uri = format!("{}/genesis", self.get_base_url());
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn get_config(&self) -> serde_json::Value {
+        let uri = format!("{}/config", self.get_base_url());
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn get_raw_body(&self, path: &str) -> String {
+        let uri = format!("{}{}", self.get_base_url(), path);
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        response.text().unwrap()
+    }
+
+    fn export_chain(&self) -> Vec<u8> {
+        let uri = format!("{}/chain/export", self.get_base_url());
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let mut bytes = vec![];
+        response.copy_to(&mut bytes).unwrap();
+        bytes
+    }
+}
+
+// Server-sent events with the block templates of a node
+pub struct TemplateStream(BufReader<Body>);
+
+impl TemplateStream {
+    // Blocks until the next template is received
+    pub fn next_template(&mut self) -> Block {
+        loop {
+            let mut line = String::new();
+            self.0.read_line(&mut line).unwrap();
+            if let Some(data) = line.strip_prefix("data: ") {
+                return serde_json::from_str(data.trim_end()).unwrap();
+            }
+        }
+    }
 }
 
 fn post_request(uri: String, body: String) -> Response<Body> {
@@ -186,7 +702,6 @@ pub struct Miner {
     config: MinerArgs,
 }
 
-#[allow(dead_code)]
 impl Miner {
     pub fn new() -> Self {
         let config = Self::default_config();
@@ -205,18 +720,27 @@ impl Miner {
 
         let node_client = NetworkNodeClient::new(config.node_url.clone());
 
-        run_mining_loop(config, node_client);
+        run_mining_loop(config, &node_client, &CancellationToken::default()).unwrap();
 
         thread::sleep(Duration::from_millis(100));
     }
 
+    pub fn mine_one_block(&self) -> Block {
+        let node_client = NetworkNodeClient::new(self.config.node_url.clone());
+
+        let block =
+            mine_one_block(&self.config, &node_client, &CancellationToken::default()).unwrap();
+
+        thread::sleep(Duration::from_millis(100));
+        block
+    }
+
     fn default_config() -> MinerArgs {
         MinerArgs {
-            miner_address: miner_address(),
             node_url: Self::get_node_url(DEFAULT_PORT),
             difficulty: DEFAULT_DIFFICULTY,
-            max_blocks: 1_u64,
-            max_nonce: 1_000_000,
+            max_blocks: 1,
+            ..MinerArgs::with_defaults(&miner_address())
         }
     }
 
@@ -224,3 +748,9 @@ impl Miner {
         format!("http://localhost:{}", port)
     }
 }
+
+impl Default for Miner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
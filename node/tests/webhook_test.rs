@@ -0,0 +1,116 @@
+mod utils;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use rusty_fork::rusty_fork_test;
+use serial_test::serial;
+use spec::types::Block;
+
+use crate::utils::Miner;
+use crate::utils::RestApi;
+use crate::utils::TestServerBuilder;
+
+// We run each test in a separated process to force resource liberation (i.e. network ports)
+rusty_fork_test! {
+
+#[test]
+#[serial]
+fn test_should_notify_new_blocks_to_webhooks() {
+    // start a stub webhook endpoint that forwards every received body
+    let (sender, receiver) = mpsc::channel();
+    start_webhook_stub(9100, 0, sender);
+
+    // start the node with the webhook
+    let node = TestServerBuilder::new()
+        .webhook("http://localhost:9100/blocks")
+        .build();
+    node.start();
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+
+    // the webhook should eventually receive the new block
+    let (_, raw_body) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    let notified_block: Block = serde_json::from_str(&raw_body).unwrap();
+    assert_eq!(notified_block, node.get_last_block());
+}
+
+#[test]
+#[serial]
+fn test_should_retry_failed_notifications_after_a_backoff() {
+    // start a stub webhook endpoint that fails the first request
+    let (sender, receiver) = mpsc::channel();
+    start_webhook_stub(9100, 1, sender);
+
+    // start the node with the webhook, retrying 200 ms after a failure
+    let node = TestServerBuilder::new()
+        .webhook("http://localhost:9100/blocks")
+        .build();
+    node.start();
+
+    // mine the genesis block
+    let miner = Miner::new();
+    miner.mine_blocks(1);
+
+    // the genesis block is sent again, but only after the backoff
+    let receive_block = || {
+        let (received_at, raw_body) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        (received_at, serde_json::from_str::<Block>(&raw_body).unwrap())
+    };
+    let (failed_at, genesis) = receive_block();
+    let (retried_at, retried_block) = receive_block();
+    assert_eq!(retried_block, genesis);
+    assert!(retried_at - failed_at >= Duration::from_millis(200));
+
+    // once delivered, it is not sent again
+    assert!(receiver.recv_timeout(Duration::from_millis(500)).is_err());
+}
+}
+
+// Minimal HTTP server that accepts any request, answering with an empty response
+// that is an error for the first requests, and successful for the rest of them
+// Every body is forwarded along with the time it was received
+fn start_webhook_stub(port: u16, num_failures: usize, sender: mpsc::Sender<(Instant, String)>) {
+    let listener = TcpListener::bind(("localhost", port)).unwrap();
+
+    thread::spawn(move || {
+        for (num_request, stream) in listener.incoming().enumerate() {
+            let mut stream = stream.unwrap();
+            let received_at = Instant::now();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            // read the headers, looking for the length of the body
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let status = match num_request < num_failures {
+                true => "500 Internal Server Error",
+                false => "200 OK",
+            };
+            let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+            stream.write_all(response.as_bytes()).unwrap();
+
+            let body = String::from_utf8(body).unwrap();
+            sender.send((received_at, body)).unwrap();
+        }
+    });
+}
@@ -1,9 +1,64 @@
+use chrono::Utc;
+
 use crate::types::{Address, Block, Coin, Network, Transaction};
 
 pub trait Database {
     fn get_network(&self) -> Network;
     fn get_all_blocks(&self) -> Vec<Block>;
+    fn get_block_by_index(&self, index: u64) -> Option<Block>;
+    fn get_blocks_range(&self, from: u64, limit: u64) -> Vec<Block>;
     fn get_tip_block(&self) -> Option<Block>;
+    // Number of blocks in the chain, which is also the index of the next block
+    fn get_block_count(&self) -> u64;
     fn get_account_balance(&self, address: &Address) -> Option<Coin>;
     fn get_mempool_transactions(&self) -> Vec<Transaction>;
+
+    // Index of the tip block without cloning it (0 for an empty chain as well as for only the genesis block)
+    fn get_height(&self) -> u64 {
+        self.get_block_count().saturating_sub(1)
+    }
+
+    // Difficulty of a block of the chain, if the database keeps track of it,
+    // so it is not computed again by replaying every retarget window
+    fn get_block_difficulty(&self, _index: u64) -> Option<u32> {
+        None
+    }
+
+    // Timestamp of the next block template, which a node can keep until the tip changes
+    // so the same chain and mempool always produce the same template
+    fn get_template_timestamp(&self) -> i64 {
+        Utc::now().timestamp_millis()
+    }
+
+    // Balance of an account excluding the coinbase rewards that are not mature yet at some height,
+    // the coinbase of a block can only be spent after `coinbase_maturity` more blocks are added
+    // Premined funds are also excluded until their unlock height
+    fn get_spendable_balance(&self, address: &Address, current_height: u64) -> Option<Coin> {
+        let balance = self.get_account_balance(address)?;
+        let network = self.get_network();
+
+        let locked_funds: Coin = network
+            .premine
+            .iter()
+            .filter(|allocation| allocation.address == *address)
+            .filter(|allocation| current_height < allocation.unlock_height)
+            .map(|allocation| allocation.amount)
+            .sum();
+
+        let maturity = network.coinbase_maturity;
+        let first_immature_index = current_height.saturating_sub(maturity);
+        let immature_funds: Coin = self
+            .get_blocks_range(first_immature_index, current_height - first_immature_index)
+            .iter()
+            .filter_map(|block| block.transactions.first())
+            .filter(|coinbase| coinbase.recipient == *address)
+            .map(|coinbase| coinbase.amount)
+            .sum();
+
+        Some(
+            balance
+                .saturating_sub(immature_funds)
+                .saturating_sub(locked_funds),
+        )
+    }
 }
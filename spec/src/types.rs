@@ -3,10 +3,13 @@ mod block;
 mod coin;
 mod network;
 mod transaction;
+mod version;
 
 pub use address::Address;
 pub use block::Block;
-pub use coin::Coin;
-pub use network::Network;
+pub use coin::{deserialize_coin, Coin};
+pub use network::{GenesisAllocation, Network, PowAlgorithm};
 pub use transaction::Transaction;
+pub use version::FORMAT_VERSION;
+pub mod encoding;
 pub mod hash;
@@ -1,20 +1,33 @@
+use std::collections::HashMap;
+
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-use crate::Database;
+use crate::{validators::get_block_subsidy, Database};
 
 use super::{
+    encoding::CanonicalEncode,
     hash::{ConsensusHash, ConsensusHashable},
-    Transaction,
+    version::{default_format_version, deserialize_format_version, FORMAT_VERSION},
+    Address, Coin, Transaction,
 };
 
 // Represents a block in a blockchain
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
 pub struct Block {
+    // The format version is not part of the hash, as it only describes how the block is serialized
+    #[serde(
+        default = "default_format_version",
+        deserialize_with = "deserialize_format_version"
+    )]
+    pub version: u32,
     pub index: u64,
     pub timestamp: i64,
     pub nonce: u64,
+    #[schema(value_type = String)]
     pub previous_hash: ConsensusHash,
+    #[schema(value_type = String)]
     pub hash: ConsensusHash,
     pub transactions: Vec<Transaction>,
 }
@@ -28,6 +41,7 @@ impl Block {
         transactions: Vec<Transaction>,
     ) -> Block {
         let mut block = Block {
+            version: FORMAT_VERSION,
             index,
             timestamp: Utc::now().timestamp_millis(),
             nonce,
@@ -51,9 +65,36 @@ impl Block {
             }
         };
 
-        let transactions = database.get_mempool_transactions();
+        // blocks during the warmup only hold the coinbase, so the transactions keep waiting in the mempool
+        let transactions = if index < database.get_network().warmup_blocks {
+            vec![]
+        } else {
+            // transactions must follow the canonical order (sorted by id) to be valid
+            let payable_transactions = select_payable_transactions(database, index);
+            let capped_transactions =
+                select_transactions_per_sender(database, payable_transactions);
+            let mut transactions = select_transactions_within_weight(database, capped_transactions);
+            transactions.sort_by_key(|transaction| transaction.id());
+            transactions
+        };
+
+        let mut block = Block::new(index, 0, previous_hash, transactions);
+        block.timestamp = database.get_template_timestamp();
+        block.hash = block.calculate_hash();
+
+        block
+    }
+
+    // Same as a regular template, but it already includes the coinbase transaction
+    // so miners only need to find a valid nonce
+    pub fn new_template_with_coinbase<T: Database>(database: &T, miner_address: Address) -> Block {
+        let mut block = Block::new_template(database);
+        let mut coinbase = Transaction::new_coinbase(miner_address);
+        coinbase.amount = get_block_subsidy(database);
+        block.transactions.insert(0, coinbase);
+        block.hash = block.calculate_hash();
 
-        Block::new(index, 0, previous_hash, transactions)
+        block
     }
 
     // Calculate the hash value of the block
@@ -66,3 +107,133 @@ impl Block {
         hashable_data.consensus_hash()
     }
 }
+
+// The mempool may hold transactions of the same sender that together overspend its balance,
+// so only the first ones (in pool order) that the sender can pay for are included
+fn select_payable_transactions<T: Database>(database: &T, height: u64) -> Vec<Transaction> {
+    let mut spent_amounts: HashMap<Address, Coin> = HashMap::new();
+
+    database
+        .get_mempool_transactions()
+        .into_iter()
+        .filter(|transaction| {
+            let balance = database
+                .get_spendable_balance(&transaction.sender, height)
+                .unwrap_or_default();
+            let spent_amount = spent_amounts.entry(transaction.sender.clone()).or_default();
+
+            match spent_amount.checked_add(transaction.amount) {
+                Some(total_amount) if total_amount <= balance => {
+                    *spent_amount = total_amount;
+                    true
+                }
+                _ => false,
+            }
+        })
+        .collect()
+}
+
+// Only the first transactions (in pool order) of each sender are included, up to the network limit
+fn select_transactions_per_sender<T: Database>(
+    database: &T,
+    transactions: Vec<Transaction>,
+) -> Vec<Transaction> {
+    let max_txs_per_sender = database.get_network().max_txs_per_sender_per_block;
+    if max_txs_per_sender == 0 {
+        return transactions;
+    }
+
+    let mut sender_counts: HashMap<Address, u64> = HashMap::new();
+    transactions
+        .into_iter()
+        .filter(|transaction| {
+            let count = sender_counts.entry(transaction.sender.clone()).or_default();
+            *count += 1;
+            *count <= max_txs_per_sender
+        })
+        .collect()
+}
+
+// Transactions are taken in pool order until the next one does not fit in the block weight limit,
+// leaving room for a coinbase transaction carrying the largest data allowed (e.g. an extranonce)
+fn select_transactions_within_weight<T: Database>(
+    database: &T,
+    transactions: Vec<Transaction>,
+) -> Vec<Transaction> {
+    let network = database.get_network();
+    if network.max_block_weight == 0 {
+        return transactions;
+    }
+
+    let coinbase = Transaction::new_coinbase(Address::default()).with_data(vec![
+        0;
+        network.max_transaction_data_size
+            as usize
+    ]);
+    let mut block_weight = coinbase.weight();
+
+    transactions
+        .into_iter()
+        .take_while(|transaction| {
+            block_weight += transaction.weight();
+            block_weight <= network.max_block_weight
+        })
+        .collect()
+}
+
+impl CanonicalEncode for Block {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        self.index.encode(buffer);
+        self.timestamp.encode(buffer);
+        self.nonce.encode(buffer);
+        self.previous_hash.encode(buffer);
+        self.hash.encode(buffer);
+        self.transactions.encode(buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_the_first_format_version_with_defaults() {
+        // neither the block nor its transactions had a version, and transactions had no data
+        let json = serde_json::json!({
+            "index": 1,
+            "timestamp": 1_650_000_000_000_i64,
+            "nonce": 42,
+            "previous_hash": ConsensusHash::default().to_string(),
+            "hash": ConsensusHash::default().to_string(),
+            "transactions": [{
+                "sender": Address::default().to_string(),
+                "recipient": Address::default().to_string(),
+                "amount": 100,
+            }],
+        });
+
+        let block: Block = serde_json::from_value(json).unwrap();
+        assert_eq!(block.version, FORMAT_VERSION);
+        assert_eq!(
+            block.transactions,
+            vec![Transaction::new(
+                Address::default(),
+                Address::default(),
+                100
+            )]
+        );
+    }
+
+    #[test]
+    fn reject_newer_format_versions() {
+        let block = Block::new(1, 0, ConsensusHash::default(), vec![]);
+        let mut json = serde_json::to_value(&block).unwrap();
+        assert_eq!(json["version"], FORMAT_VERSION);
+
+        json["version"] = (FORMAT_VERSION + 1).into();
+        let error = serde_json::from_value::<Block>(json).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Unsupported format version 3, the latest supported is 2"));
+    }
+}
@@ -1 +1,23 @@
+use serde::{de, Deserialize, Deserializer};
+
 pub type Coin = u64;
+
+// Coin amounts are numbers, but nodes can be configured to write them in JSON as decimal strings,
+// so both are accepted from human-readable formats
+pub fn deserialize_coin<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Coin, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumberOrString {
+        Number(Coin),
+        String(String),
+    }
+
+    if !deserializer.is_human_readable() {
+        return Coin::deserialize(deserializer);
+    }
+
+    match NumberOrString::deserialize(deserializer)? {
+        NumberOrString::Number(amount) => Ok(amount),
+        NumberOrString::String(amount) => amount.parse().map_err(de::Error::custom),
+    }
+}
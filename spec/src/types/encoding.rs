@@ -0,0 +1,131 @@
+// Canonical binary encoding of the consensus types, used for hashing
+// It is explicitly defined for each type (fixed field order, fixed-width little-endian integers
+// and length-prefixed sequences), so it never changes with the struct layout or the serde representation
+pub trait CanonicalEncode {
+    fn encode(&self, buffer: &mut Vec<u8>);
+
+    fn canonical_encoding(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        self.encode(&mut buffer);
+        buffer
+    }
+}
+
+impl CanonicalEncode for u8 {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.push(*self);
+    }
+}
+
+impl CanonicalEncode for u32 {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl CanonicalEncode for u64 {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl CanonicalEncode for i64 {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl CanonicalEncode for str {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        (self.len() as u64).encode(buffer);
+        buffer.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl CanonicalEncode for String {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        self.as_str().encode(buffer);
+    }
+}
+
+impl<T: CanonicalEncode> CanonicalEncode for [T] {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        (self.len() as u64).encode(buffer);
+        for item in self {
+            item.encode(buffer);
+        }
+    }
+}
+
+impl<T: CanonicalEncode> CanonicalEncode for Vec<T> {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        self.as_slice().encode(buffer);
+    }
+}
+
+// A tag byte tells apart a missing value from any present one
+impl<T: CanonicalEncode> CanonicalEncode for Option<T> {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        match self {
+            Some(value) => {
+                1_u8.encode(buffer);
+                value.encode(buffer);
+            }
+            None => 0_u8.encode(buffer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{
+        hash::{ConsensusHash, ConsensusHashable},
+        Address, Block, Transaction, FORMAT_VERSION,
+    };
+
+    use super::*;
+
+    #[test]
+    fn encode_integers_as_little_endian() {
+        assert_eq!(1_u32.canonical_encoding(), vec![1, 0, 0, 0]);
+        assert_eq!(258_u64.canonical_encoding(), vec![2, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!((-1_i64).canonical_encoding(), vec![255; 8]);
+    }
+
+    #[test]
+    fn encode_sequences_with_length_prefix() {
+        assert_eq!(
+            "ab".canonical_encoding(),
+            vec![2, 0, 0, 0, 0, 0, 0, 0, b'a', b'b']
+        );
+        assert_eq!(
+            vec![1_u32].canonical_encoding(),
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn encode_options_with_tag() {
+        assert_eq!(None::<u32>.canonical_encoding(), vec![0]);
+        assert_eq!(Some(1_u32).canonical_encoding(), vec![1, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn pin_known_block_hash() {
+        // if this test fails, the consensus hashing has changed and all existing chains are invalid
+        let transaction = Transaction::new(Address::default(), Address::default(), 100);
+        let block = Block {
+            version: FORMAT_VERSION,
+            index: 1,
+            timestamp: 1_650_000_000_000,
+            nonce: 42,
+            previous_hash: ConsensusHash::default(),
+            hash: ConsensusHash::default(),
+            transactions: vec![transaction],
+        };
+
+        let expected_hash = "59dc08beec4c2d62e2d165bb83d6485ebcaf6841aa9b41b8bb84c8e369ffb5ef";
+        assert_eq!(block.calculate_hash().to_string(), expected_hash);
+        assert_eq!(block.consensus_hash().to_string(), expected_hash);
+    }
+}
@@ -9,6 +9,8 @@ use sha3::{
 };
 use thiserror::Error;
 
+use super::encoding::CanonicalEncode;
+
 #[derive(Error, PartialEq, Eq, Debug)]
 #[allow(clippy::enum_variant_names)]
 pub enum ConsensusHashError {
@@ -19,20 +21,20 @@ pub enum ConsensusHashError {
     InvalidLength,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[serde(try_from = "String", into = "String")]
 pub struct ConsensusHash([u8; 32]);
 
 impl ConsensusHash {
-    pub fn digest<T>(value: &T) -> Self
-    where
-        T: ?Sized + serde::Serialize,
-    {
-        let encoded: Vec<u8> = bincode::serialize(&value).unwrap();
-        let sha3_256_digest: GenericArray<u8, U32> = Sha3_256::digest(encoded);
+    pub fn digest(bytes: &[u8]) -> Self {
+        let sha3_256_digest: GenericArray<u8, U32> = Sha3_256::digest(bytes);
         ConsensusHash(sha3_256_digest.into())
     }
 
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
     pub fn leading_zeros(&self) -> u32 {
         let mut count = 0;
         for byte in self.0 {
@@ -90,13 +92,20 @@ impl fmt::Display for ConsensusHash {
     }
 }
 
+impl CanonicalEncode for ConsensusHash {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.0);
+    }
+}
+
 pub trait ConsensusHashable {
     fn consensus_hash(&self) -> ConsensusHash;
 }
 
-impl<T: ?Sized + Serialize> ConsensusHashable for T {
+// The consensus hash is always calculated over the canonical encoding
+impl<T: ?Sized + CanonicalEncode> ConsensusHashable for T {
     fn consensus_hash(&self) -> ConsensusHash {
-        ConsensusHash::digest(self)
+        ConsensusHash::digest(&self.canonical_encoding())
     }
 }
 
@@ -1,8 +1,177 @@
+use std::str::FromStr;
+
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{deserialize_coin, encoding::CanonicalEncode, Address, Coin};
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub struct Network {
     pub description: String,
     pub difficulty: u32,
     pub timestamp: i64,
+    pub max_transaction_data_size: u64,
+    pub target_block_time_ms: u64,
+    pub retarget_window: u64,
+    pub min_difficulty: u32,
+    pub max_difficulty: u32,
+    pub coinbase_maturity: u64,
+    #[serde(default)]
+    pub pow_algorithm: PowAlgorithm,
+    #[serde(default)]
+    pub premine: Vec<GenesisAllocation>,
+    // Maximum sum of the encoded sizes of the transactions of a block (0 for no limit)
+    #[serde(default)]
+    pub max_block_weight: u64,
+    // Difficulty of the genesis block alone, so the chain can start easier than the blocks after it
+    // (the regular difficulty if not set)
+    #[serde(default)]
+    pub genesis_difficulty: Option<u32>,
+    // Maximum number of transactions of the same sender in a block, not counting the coinbase (0 for no limit)
+    #[serde(default)]
+    pub max_txs_per_sender_per_block: u64,
+    // Number of blocks from the genesis that only hold the coinbase, to distribute the first coins
+    // before accepting transfers, which wait in the mempool until then
+    #[serde(default)]
+    pub warmup_blocks: u64,
+}
+
+// Funds given to an account (e.g. a treasury) when the genesis block is added
+// They can only be spent once the chain reaches the unlock height, like immature coinbase rewards
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct GenesisAllocation {
+    #[schema(value_type = String)]
+    pub address: Address,
+    #[serde(deserialize_with = "deserialize_coin")]
+    pub amount: Coin,
+    #[serde(default)]
+    pub unlock_height: u64,
+}
+
+// Parsed from "address:amount", or "address:amount:unlock_height" for vested allocations
+impl FromStr for GenesisAllocation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let (address, amount, unlock_height) = match parts[..] {
+            [address, amount] => (address, amount, "0"),
+            [address, amount, unlock_height] => (address, amount, unlock_height),
+            _ => return Err(format!("Invalid genesis allocation: {}", s)),
+        };
+
+        Ok(GenesisAllocation {
+            address: address.parse().map_err(|error| format!("{}", error))?,
+            amount: amount.parse().map_err(|error| format!("{}", error))?,
+            unlock_height: unlock_height
+                .parse()
+                .map_err(|error| format!("{}", error))?,
+        })
+    }
+}
+
+// Hash function used for the proof of work commitment of the blocks
+// The block hash (used to chain and identify blocks) is always Sha3-256
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum PowAlgorithm {
+    // The block hash itself
+    #[default]
+    Sha3,
+    // Sha3-256 over a buffer filled from the block hash, with data-dependent reads,
+    // so mining needs memory on top of raw hashing power
+    MemoryHard,
+}
+
+impl FromStr for PowAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "sha3" => Ok(PowAlgorithm::Sha3),
+            "memory-hard" => Ok(PowAlgorithm::MemoryHard),
+            _ => Err(format!("Unknown proof of work algorithm: {}", s)),
+        }
+    }
+}
+
+impl Network {
+    // Network used by the tests: blocks are mined right away, with no limits nor retargets
+    pub fn test_default() -> Self {
+        Network {
+            description: "Test network".to_string(),
+            difficulty: 0,
+            timestamp: 0,
+            max_transaction_data_size: 256,
+            target_block_time_ms: 10000,
+            retarget_window: 0,
+            min_difficulty: 0,
+            max_difficulty: 256,
+            coinbase_maturity: 0,
+            pow_algorithm: PowAlgorithm::Sha3,
+            premine: vec![],
+            max_block_weight: 0,
+            genesis_difficulty: None,
+            max_txs_per_sender_per_block: 0,
+            warmup_blocks: 0,
+        }
+    }
+}
+
+impl CanonicalEncode for Network {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        self.description.encode(buffer);
+        self.difficulty.encode(buffer);
+        self.timestamp.encode(buffer);
+        self.max_transaction_data_size.encode(buffer);
+        self.target_block_time_ms.encode(buffer);
+        self.retarget_window.encode(buffer);
+        self.min_difficulty.encode(buffer);
+        self.max_difficulty.encode(buffer);
+        self.coinbase_maturity.encode(buffer);
+        self.pow_algorithm.encode(buffer);
+        self.premine.encode(buffer);
+        self.max_block_weight.encode(buffer);
+        self.genesis_difficulty.encode(buffer);
+        self.max_txs_per_sender_per_block.encode(buffer);
+        self.warmup_blocks.encode(buffer);
+    }
+}
+
+impl CanonicalEncode for PowAlgorithm {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        let id: u8 = match self {
+            PowAlgorithm::Sha3 => 0,
+            PowAlgorithm::MemoryHard => 1,
+        };
+        id.encode(buffer);
+    }
+}
+
+impl CanonicalEncode for GenesisAllocation {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        self.address.encode(buffer);
+        self.amount.encode(buffer);
+        self.unlock_height.encode(buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_genesis_allocations() {
+        let address = "f780b958227ff0bf5795ede8f9f7eaac67e7e06666b043a400026cbd421ce28e";
+
+        let allocation: GenesisAllocation = format!("{}:1000", address).parse().unwrap();
+        assert_eq!(allocation.amount, 1000);
+        assert_eq!(allocation.unlock_height, 0);
+
+        let allocation: GenesisAllocation = format!("{}:1000:50", address).parse().unwrap();
+        assert_eq!(allocation.address.to_string(), address);
+        assert_eq!(allocation.unlock_height, 50);
+
+        assert!(address.parse::<GenesisAllocation>().is_err());
+    }
 }
@@ -1,20 +1,109 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-use super::{Address, Coin};
+use crate::validators::BLOCK_SUBSIDY;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+use super::{
+    deserialize_coin,
+    encoding::CanonicalEncode,
+    hash::{ConsensusHash, ConsensusHashable},
+    version::{default_format_version, deserialize_format_version, FORMAT_VERSION},
+    Address, Coin,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
 pub struct Transaction {
+    // The format version is not part of the id, as it only describes how the transaction is serialized
+    #[serde(
+        default = "default_format_version",
+        deserialize_with = "deserialize_format_version"
+    )]
+    pub version: u32,
+    #[schema(value_type = String)]
     pub sender: Address,
+    #[schema(value_type = String)]
     pub recipient: Address,
+    #[serde(deserialize_with = "deserialize_coin")]
     pub amount: Coin,
+    // Optional payload attached by the sender (e.g. an invoice id)
+    #[serde(default, with = "hex")]
+    #[schema(value_type = String)]
+    pub data: Vec<u8>,
 }
 
 impl Transaction {
     pub fn new(sender: Address, recipient: Address, amount: Coin) -> Self {
         Self {
+            version: FORMAT_VERSION,
             sender,
             recipient,
             amount,
+            data: vec![],
         }
     }
+
+    // The coinbase transaction rewards the miner of a block with new coins
+    pub fn new_coinbase(recipient: Address) -> Self {
+        Self::new(Address::default(), recipient, BLOCK_SUBSIDY)
+    }
+
+    pub fn with_data(mut self, data: Vec<u8>) -> Self {
+        self.data = data;
+        self
+    }
+
+    // Unique identifier of the transaction, derived from its contents
+    pub fn id(&self) -> ConsensusHash {
+        self.consensus_hash()
+    }
+
+    // Space taken by the transaction in a block, which is the size of its canonical encoding
+    pub fn weight(&self) -> u64 {
+        self.canonical_encoding().len() as u64
+    }
+}
+
+impl CanonicalEncode for Transaction {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        self.sender.encode(buffer);
+        self.recipient.encode(buffer);
+        self.amount.encode(buffer);
+        self.data.encode(buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_data_as_json() {
+        let transaction =
+            Transaction::new(Address::default(), Address::default(), 10).with_data(vec![1, 2, 255]);
+
+        let json = serde_json::to_value(&transaction).unwrap();
+        assert_eq!(json["data"], "0102ff");
+
+        let parsed_transaction: Transaction = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed_transaction, transaction);
+    }
+
+    #[test]
+    fn parse_amount_written_as_string() {
+        let transaction = Transaction::new(Address::default(), Address::default(), 1 << 60);
+
+        let mut json = serde_json::to_value(&transaction).unwrap();
+        json["amount"] = serde_json::Value::String((1u64 << 60).to_string());
+
+        let parsed_transaction: Transaction = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed_transaction, transaction);
+    }
+
+    #[test]
+    fn include_data_in_the_hash() {
+        let transaction = Transaction::new(Address::default(), Address::default(), 10);
+        let transaction_with_data = transaction.clone().with_data(vec![1]);
+
+        assert_ne!(transaction.id(), transaction_with_data.id());
+    }
 }
@@ -0,0 +1,25 @@
+use serde::{de, Deserialize, Deserializer};
+
+// Version of the serialized format of blocks and transactions, increased when they gain new fields
+// Version 1 had neither the "version" field nor the transaction "data", which are read as their defaults
+pub const FORMAT_VERSION: u32 = 2;
+
+pub fn default_format_version() -> u32 {
+    FORMAT_VERSION
+}
+
+// Older formats are read into the current one, while newer formats may have fields with meaning
+// that would be silently dropped, so they are rejected with a clear reason instead
+pub fn deserialize_format_version<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u32, D::Error> {
+    let version = u32::deserialize(deserializer)?;
+    if version > FORMAT_VERSION {
+        return Err(de::Error::custom(format!(
+            "Unsupported format version {}, the latest supported is {} (the node may need an upgrade)",
+            version, FORMAT_VERSION
+        )));
+    }
+
+    Ok(FORMAT_VERSION)
+}
@@ -1,6 +1,7 @@
 pub mod block;
 pub mod chain;
 pub mod coinbase;
+pub mod difficulty;
 pub mod genesis;
 pub mod proof_of_work;
 pub mod transaction;
@@ -8,6 +9,7 @@ pub mod transaction;
 pub use block::*;
 pub use chain::*;
 pub use coinbase::*;
+pub use difficulty::*;
 pub use genesis::*;
 pub use proof_of_work::*;
 pub use transaction::*;
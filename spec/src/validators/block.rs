@@ -1,16 +1,37 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
 use thiserror::Error;
 
 use super::{
-    chain::validate_chain, coinbase::validate_coinbase, genesis::validate_genesis,
-    proof_of_work::validate_pow, transaction::validate_transaction,
+    chain::validate_chain,
+    coinbase::{get_block_subsidy, validate_coinbase, validate_max_supply},
+    difficulty::get_next_difficulty,
+    genesis::validate_genesis,
+    proof_of_work::validate_pow,
+    transaction::validate_transaction,
+};
+use crate::{
+    types::{Address, Block, Transaction},
+    Database,
 };
-use crate::{types::Block, Database};
 
 #[derive(Error, PartialEq, Eq, Debug)]
 pub enum BlockError {
     #[error("Invalid hash")]
     InvalidHash,
+
+    #[error("Transactions are not in canonical order")]
+    InvalidTransactionOrder,
+
+    #[error("Block weight exceeds the limit")]
+    WeightLimitExceeded,
+
+    #[error("Too many transactions from the same sender")]
+    SenderLimitExceeded,
+
+    #[error("Only the coinbase is allowed during the warmup blocks")]
+    TransactionsDuringWarmup,
 }
 
 pub fn validate_block<T: Database>(database: &T, block: &Block) -> Result<()> {
@@ -29,9 +50,9 @@ pub fn validate_block_metadata<T: Database>(database: &T, block: &Block) -> Resu
 
     validate_block_hash(block)?;
 
-    // proof of work validation
-    let difficulty = database.get_network().difficulty;
-    validate_pow(difficulty, block)?;
+    // proof of work validation, the difficulty may have been retargeted since the genesis
+    let difficulty = get_next_difficulty(database);
+    validate_pow(database.get_network().pow_algorithm, difficulty, block)?;
 
     Ok(())
 }
@@ -45,12 +66,47 @@ pub fn validate_block_hash(block: &Block) -> Result<()> {
 }
 
 pub fn validate_block_transactions<T: Database>(database: &T, block: &Block) -> Result<()> {
+    // the weight of the coinbase also counts, as it takes space in the block like any other transaction
+    let max_block_weight = database.get_network().max_block_weight;
+    if max_block_weight > 0 && calculate_block_weight(&block.transactions) > max_block_weight {
+        return Err(BlockError::WeightLimitExceeded.into());
+    }
+
     let mut transactions = block.transactions.iter();
 
     // the first transaction is always the coinbase transaction
     // in which the miner receives the mining rewards
     let coinbase = transactions.next();
-    validate_coinbase(coinbase)?;
+    validate_max_supply(database, coinbase)?;
+    validate_coinbase(coinbase, get_block_subsidy(database))?;
+
+    // blocks during the warmup only distribute coins to the miners
+    if block.index < database.get_network().warmup_blocks && !transactions.as_slice().is_empty() {
+        return Err(BlockError::TransactionsDuringWarmup.into());
+    }
+
+    // the rest of the transactions are sorted by id, so the same set of transactions
+    // can only be included in a single way
+    let is_sorted = transactions
+        .as_slice()
+        .windows(2)
+        .all(|pair| pair[0].id() <= pair[1].id());
+    if !is_sorted {
+        return Err(BlockError::InvalidTransactionOrder.into());
+    }
+
+    // a single sender cannot crowd out the transactions of everyone else
+    let max_txs_per_sender = database.get_network().max_txs_per_sender_per_block;
+    if max_txs_per_sender > 0 {
+        let mut sender_counts: HashMap<&Address, u64> = HashMap::new();
+        for transaction in transactions.clone() {
+            let count = sender_counts.entry(&transaction.sender).or_default();
+            *count += 1;
+            if *count > max_txs_per_sender {
+                return Err(BlockError::SenderLimitExceeded.into());
+            }
+        }
+    }
 
     // all the rest of the transactions are regular ones,
     // where funds get transfered from one account to another
@@ -60,3 +116,8 @@ pub fn validate_block_transactions<T: Database>(database: &T, block: &Block) ->
 
     Ok(())
 }
+
+// Total size of the transactions of a block, bounded by the network weight limit
+pub fn calculate_block_weight(transactions: &[Transaction]) -> u64 {
+    transactions.iter().map(Transaction::weight).sum()
+}
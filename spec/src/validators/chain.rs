@@ -13,6 +13,9 @@ pub enum ChainError {
 
     #[error("Invalid previous_hash")]
     InvalidPreviousHash,
+
+    #[error("Timestamp is earlier than the previous block")]
+    TimestampBeforePreviousBlock,
 }
 
 pub fn validate_chain<T: Database>(database: &T, block: &Block) -> Result<()> {
@@ -33,5 +36,10 @@ pub fn validate_chain<T: Database>(database: &T, block: &Block) -> Result<()> {
         return Err(ChainError::InvalidPreviousHash.into());
     }
 
+    // the time of the chain never goes backwards, starting from the timestamp of the genesis block
+    if block.timestamp < tip_block.timestamp {
+        return Err(ChainError::TimestampBeforePreviousBlock.into());
+    }
+
     Ok(())
 }
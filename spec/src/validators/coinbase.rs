@@ -1,35 +1,109 @@
 use anyhow::Result;
 use thiserror::Error;
 
-use crate::types::Transaction;
+use crate::{
+    types::{Address, Coin, Transaction},
+    Database,
+};
 
 // The reward for successfully mining a block
-// For now, this amount is constant
+// It is constant until the maximum supply is reached, when it drops to what is left (and then to zero)
 pub const BLOCK_SUBSIDY: u64 = 100;
 
+// Hard cap on the coins that can ever exist, including the premine
+pub const MAX_SUPPLY: Coin = 21_000_000 * BLOCK_SUBSIDY;
+
 #[derive(Error, PartialEq, Eq, Debug)]
 pub enum CoinbaseError {
     #[error("Coinbase transaction not found")]
     CoinbaseTransactionNotFound,
 
+    #[error("Invalid coinbase sender")]
+    InvalidCoinbaseSender,
+
     #[error("Invalid coinbase amount")]
     InvalidCoinbaseAmount,
+
+    #[error("Maximum supply exceeded")]
+    MaxSupplyExceeded,
 }
 
-pub fn validate_coinbase(coinbase: Option<&Transaction>) -> Result<()> {
+// `subsidy` is the amount that the coinbase must claim, usually the one from `get_block_subsidy`
+pub fn validate_coinbase(coinbase: Option<&Transaction>, subsidy: Coin) -> Result<()> {
     // The coinbase transaction is required in a valid block
     let coinbase = match coinbase {
         Some(transaction) => transaction,
         None => return Err(CoinbaseError::CoinbaseTransactionNotFound.into()),
     };
 
-    // In coinbase transactions, we only need to check that the amount is valid,
-    // because whoever provides a valid proof-of-work block can receive the new coins
-    // i.e. the sender is totally ignored and its balance never decreased
-    let is_valid_amount = coinbase.amount == BLOCK_SUBSIDY;
+    // The coinbase sender is always the zero address, as the new coins are not coming from any account
+    // i.e. its balance is never decreased. Any other sender means that this is a regular transaction
+    if coinbase.sender != Address::default() {
+        return Err(CoinbaseError::InvalidCoinbaseSender.into());
+    }
+
+    // Whoever provides a valid proof-of-work block can receive the new coins,
+    // so we only need to check that the amount is valid
+    let is_valid_amount = coinbase.amount == subsidy;
     if !is_valid_amount {
         return Err(CoinbaseError::InvalidCoinbaseAmount.into());
     }
 
     Ok(())
 }
+
+// Besides the premine of the genesis block, the coinbase transactions are the only source of new coins,
+// so the total supply is the premine plus the sum of the subsidies of all the blocks in the chain
+// Subsidies stop at the maximum supply, which the premine can not exceed, so the supply is capped too
+pub fn calculate_total_supply<T: Database>(database: &T) -> Coin {
+    let num_blocks = database.get_block_count();
+    let premine: Coin = match num_blocks {
+        0 => 0,
+        _ => calculate_premine(database),
+    };
+
+    num_blocks
+        .saturating_mul(BLOCK_SUBSIDY)
+        .saturating_add(premine)
+        .min(MAX_SUPPLY)
+}
+
+// Amount that the coinbase of the next block must claim
+pub fn get_block_subsidy<T: Database>(database: &T) -> Coin {
+    let remaining_supply = MAX_SUPPLY.saturating_sub(calculate_supply_before_coinbase(database));
+
+    BLOCK_SUBSIDY.min(remaining_supply)
+}
+
+// Check that the coins issued by the block do not take the total supply over the maximum
+// The final subsidies already fit below the cap, so this only catches miscalculated coinbases and premines
+pub fn validate_max_supply<T: Database>(
+    database: &T,
+    coinbase: Option<&Transaction>,
+) -> Result<()> {
+    let claimed_amount = coinbase.map_or(0, |coinbase| coinbase.amount);
+    let total_supply = calculate_supply_before_coinbase(database).checked_add(claimed_amount);
+
+    match total_supply {
+        Some(total_supply) if total_supply <= MAX_SUPPLY => Ok(()),
+        _ => Err(CoinbaseError::MaxSupplyExceeded.into()),
+    }
+}
+
+// The premine is issued by the genesis block, before its coinbase
+fn calculate_supply_before_coinbase<T: Database>(database: &T) -> Coin {
+    match database.get_block_count() {
+        0 => calculate_premine(database),
+        _ => calculate_total_supply(database),
+    }
+}
+
+fn calculate_premine<T: Database>(database: &T) -> Coin {
+    database
+        .get_network()
+        .premine
+        .iter()
+        .fold(0, |total, allocation| {
+            total.saturating_add(allocation.amount)
+        })
+}
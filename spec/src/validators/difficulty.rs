@@ -0,0 +1,104 @@
+use crate::{types::Network, Database};
+
+// The genesis block has its own difficulty in the network, or the regular one if not set.
+// The next blocks start at the regular difficulty of the network. Then, every `retarget_window` blocks,
+// the difficulty is adjusted so the time between blocks gets closer to the network `target_block_time_ms`. A window smaller than 2 disables retargeting.
+// Retargeted difficulties are always kept between `min_difficulty` and `max_difficulty`.
+pub fn get_next_difficulty<T: Database>(database: &T) -> u32 {
+    get_difficulty_at(database, database.get_block_count())
+}
+
+// Difficulty required for the block with the indicated index
+pub fn get_difficulty_at<T: Database>(database: &T, index: u64) -> u32 {
+    let network = database.get_network();
+    if index == 0 {
+        return network.genesis_difficulty.unwrap_or(network.difficulty);
+    }
+
+    let window = network.retarget_window;
+
+    if window < 2 {
+        return network.difficulty;
+    }
+
+    if let Some(difficulty) = database.get_block_difficulty(index) {
+        return difficulty;
+    }
+
+    // the difficulty only changes at window boundaries, so it follows from the one of the parent
+    // (except for the genesis block, which may have its own difficulty)
+    let parent_difficulty = match index {
+        1 => None,
+        _ => database.get_block_difficulty(index - 1),
+    };
+    if let Some(parent_difficulty) = parent_difficulty {
+        return match index % window {
+            0 => adjust_at_boundary(database, &network, index, parent_difficulty),
+            _ => parent_difficulty,
+        };
+    }
+
+    // replay all the adjustments made at each window boundary so far
+    let mut difficulty = network.difficulty;
+    let mut boundary = window;
+    while boundary <= index {
+        difficulty = adjust_at_boundary(database, &network, boundary, difficulty);
+        boundary += window;
+    }
+
+    difficulty
+}
+
+// Adjust the difficulty of the window that ends right before the boundary, if its blocks are known
+fn adjust_at_boundary<T: Database>(
+    database: &T,
+    network: &Network,
+    boundary: u64,
+    difficulty: u32,
+) -> u32 {
+    let window = network.retarget_window;
+    let first_block = database.get_block_by_index(boundary - window);
+    let last_block = database.get_block_by_index(boundary - 1);
+
+    let (Some(first_block), Some(last_block)) = (first_block, last_block) else {
+        return difficulty;
+    };
+
+    // the first and last blocks of the window are separated by (window - 1) intervals
+    let actual_ms = last_block.timestamp.saturating_sub(first_block.timestamp);
+    let expected_ms = network
+        .target_block_time_ms
+        .saturating_mul(window - 1)
+        .try_into()
+        .unwrap_or(i64::MAX);
+    retarget(difficulty, actual_ms, expected_ms)
+        .max(network.min_difficulty)
+        .min(network.max_difficulty)
+}
+
+// The difficulty is the number of leading zero bits of the hash,
+// so each step doubles or halves the expected amount of work per block
+pub fn retarget(difficulty: u32, actual_ms: i64, expected_ms: i64) -> u32 {
+    if actual_ms.saturating_mul(2) < expected_ms {
+        // blocks are being mined too fast
+        difficulty.saturating_add(1)
+    } else if actual_ms > expected_ms.saturating_mul(2) {
+        // blocks are being mined too slow
+        difficulty.saturating_sub(1)
+    } else {
+        difficulty
+    }
+}
+
+// Expected number of hashes needed to mine a block at some difficulty
+pub fn calculate_work(difficulty: u32) -> u128 {
+    1_u128.checked_shl(difficulty).unwrap_or(u128::MAX)
+}
+
+// Expected time to find a block at some difficulty, given a hashrate in hashes per second.
+// Each hash has a 1 in 2^difficulty chance to have enough leading zero bits.
+pub fn estimate_mining_time_secs(difficulty: u32, hashrate: f64) -> f64 {
+    let expected_hashes = 2_f64.powi(difficulty as i32);
+
+    expected_hashes / hashrate
+}
@@ -1,7 +1,10 @@
 use anyhow::Result;
 use thiserror::Error;
 
-use crate::types::Block;
+use crate::types::{hash::ConsensusHash, Block, PowAlgorithm};
+
+// Number of hashes kept in memory by the memory-hard algorithm (32 KiB)
+const MEMORY_HARD_SIZE: usize = 1024;
 
 #[derive(Error, PartialEq, Eq, Debug)]
 pub enum ProofOfWorkError {
@@ -9,10 +12,69 @@ pub enum ProofOfWorkError {
     InvalidDifficulty,
 }
 
-pub fn validate_pow(difficulty: u32, block: &Block) -> Result<()> {
-    if block.hash.leading_zeros() < difficulty {
+pub fn validate_pow(algorithm: PowAlgorithm, difficulty: u32, block: &Block) -> Result<()> {
+    if calculate_pow_hash(algorithm, block).leading_zeros() < difficulty {
         return Err(ProofOfWorkError::InvalidDifficulty.into());
     }
 
     Ok(())
 }
+
+// Hash that must have enough starting zeroes for the block to be valid
+pub fn calculate_pow_hash(algorithm: PowAlgorithm, block: &Block) -> ConsensusHash {
+    match algorithm {
+        PowAlgorithm::Sha3 => block.hash.clone(),
+        PowAlgorithm::MemoryHard => calculate_memory_hard_hash(&block.hash),
+    }
+}
+
+// Fill a buffer with a chain of hashes and then read it back in an order that depends on the
+// values read so far, so the whole buffer must be kept in memory for each nonce
+fn calculate_memory_hard_hash(seed: &ConsensusHash) -> ConsensusHash {
+    let mut memory = Vec::with_capacity(MEMORY_HARD_SIZE);
+    let mut current = ConsensusHash::digest(seed.as_bytes());
+    for _ in 0..MEMORY_HARD_SIZE {
+        let next = ConsensusHash::digest(current.as_bytes());
+        memory.push(current);
+        current = next;
+    }
+
+    for _ in 0..MEMORY_HARD_SIZE {
+        let bytes = current.as_bytes();
+        let position =
+            u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize % MEMORY_HARD_SIZE;
+
+        let mut mixed = *bytes;
+        for (byte, other) in mixed.iter_mut().zip(memory[position].as_bytes()) {
+            *byte ^= other;
+        }
+        current = ConsensusHash::digest(&mixed);
+    }
+
+    current
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_use_the_block_hash_with_sha3() {
+        let block = Block::new(0, 0, ConsensusHash::default(), vec![]);
+
+        assert_eq!(calculate_pow_hash(PowAlgorithm::Sha3, &block), block.hash);
+    }
+
+    #[test]
+    fn should_be_deterministic_with_memory_hard() {
+        let block = Block::new(0, 0, ConsensusHash::default(), vec![]);
+
+        let pow_hash = calculate_pow_hash(PowAlgorithm::MemoryHard, &block);
+
+        assert_ne!(pow_hash, block.hash);
+        assert_eq!(
+            calculate_pow_hash(PowAlgorithm::MemoryHard, &block),
+            pow_hash
+        );
+    }
+}
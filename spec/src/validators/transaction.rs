@@ -1,5 +1,10 @@
-use crate::{types::Transaction, Database};
+use crate::{
+    types::{Address, Transaction},
+    Database,
+};
 use anyhow::Result;
+
+use super::coinbase::calculate_total_supply;
 use thiserror::Error;
 
 #[derive(Error, PartialEq, Eq, Debug)]
@@ -9,10 +14,38 @@ pub enum TransactionError {
 
     #[error("Insufficient funds")]
     InsufficientFunds,
+
+    #[error("Sender is the zero address")]
+    ZeroAddressSender,
+
+    #[error("Amount exceeds total supply")]
+    AmountExceedsTotalSupply,
+
+    #[error("Data too large")]
+    DataTooLarge,
 }
 
 pub fn validate_transaction<T: Database>(database: &T, transaction: &Transaction) -> Result<()> {
-    let sender_balance = database.get_account_balance(&transaction.sender);
+    // Only the coinbase transaction can be sent from the zero address
+    if transaction.sender == Address::default() {
+        return Err(TransactionError::ZeroAddressSender.into());
+    }
+
+    // Limit the attached data to avoid bloating the blockchain
+    let max_data_size = database.get_network().max_transaction_data_size;
+    if transaction.data.len() as u64 > max_data_size {
+        return Err(TransactionError::DataTooLarge.into());
+    }
+
+    // No account can ever hold more coins than the ones in circulation,
+    // this also ensures that adding the amount to any balance never overflows
+    if transaction.amount > calculate_total_supply(database) {
+        return Err(TransactionError::AmountExceedsTotalSupply.into());
+    }
+
+    // Coinbase rewards can not be spent until they are mature
+    let sender_balance =
+        database.get_spendable_balance(&transaction.sender, database.get_block_count());
 
     match sender_balance {
         Some(balance) => {
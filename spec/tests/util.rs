@@ -1,6 +1,6 @@
 use anyhow::Result;
 use spec::{
-    types::{hash::ConsensusHashable, Address, Block, Coin, Network, Transaction},
+    types::{hash::ConsensusHashable, Address, Block, Coin, Network, Transaction, FORMAT_VERSION},
     validators::{validate_block_transactions, BLOCK_SUBSIDY},
     Database,
 };
@@ -36,6 +36,10 @@ impl MockDatabase {
         Ok(())
     }
 
+    pub fn add_mempool_transaction(&mut self, transaction: Transaction) {
+        self.transactions.push(transaction);
+    }
+
     pub fn process_transactions(&mut self, block: &Block) -> Result<()> {
         // make sure that the transactions in the block are valid
         validate_block_transactions(self, block)?;
@@ -74,13 +78,7 @@ impl MockDatabase {
 
 impl Default for MockDatabase {
     fn default() -> Self {
-        let network = Network {
-            description: "Test network".to_string(),
-            difficulty: 0,
-            timestamp: 0,
-        };
-
-        MockDatabase::new(network.clone())
+        MockDatabase::new(Network::test_default())
     }
 }
 
@@ -93,18 +91,29 @@ impl Database for MockDatabase {
         self.blocks.clone()
     }
 
+    fn get_block_by_index(&self, index: u64) -> Option<Block> {
+        self.blocks.get(index as usize).cloned()
+    }
+
+    fn get_blocks_range(&self, from: u64, limit: u64) -> Vec<Block> {
+        self.blocks
+            .iter()
+            .skip(from as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect()
+    }
+
     fn get_tip_block(&self) -> Option<Block> {
-        match self.blocks.last() {
-            Some(block) => Some(block.clone()),
-            None => None,
-        }
+        self.blocks.last().cloned()
+    }
+
+    fn get_block_count(&self) -> u64 {
+        self.blocks.len() as u64
     }
 
     fn get_account_balance(&self, address: &Address) -> Option<Coin> {
-        match self.balances.get(address) {
-            Some(balance) => Some(*balance),
-            None => None,
-        }
+        self.balances.get(address).cloned()
     }
 
     fn get_mempool_transactions(&self) -> Vec<Transaction> {
@@ -114,9 +123,11 @@ impl Database for MockDatabase {
 
 pub fn build_coinbase_transaction() -> Transaction {
     Transaction {
+        version: FORMAT_VERSION,
         sender: Address::default(),
         recipient: miner_address(),
         amount: BLOCK_SUBSIDY,
+        data: vec![],
     }
 }
 
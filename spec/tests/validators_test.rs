@@ -1,10 +1,11 @@
 mod util;
 
 use spec::types::hash::{ConsensusHash, ConsensusHashable};
-use spec::types::{Block, Network, Transaction};
+use spec::types::{Block, GenesisAllocation, Network, PowAlgorithm, Transaction};
 use spec::validators::{
-    validate_block, BlockError, ChainError, CoinbaseError, ProofOfWorkError, TransactionError,
-    BLOCK_SUBSIDY,
+    calculate_total_supply, estimate_mining_time_secs, get_next_difficulty, validate_block,
+    validate_pow, validate_transaction, BlockError, ChainError, CoinbaseError, ProofOfWorkError,
+    TransactionError, BLOCK_SUBSIDY, MAX_SUPPLY,
 };
 use spec::Database;
 use util::MockDatabase;
@@ -95,9 +96,8 @@ fn should_reject_blocks_with_invalid_hash() {
 fn should_reject_blocks_with_invalid_difficulty() {
     // set up a blockchain with an insane difficulty
     let network = Network {
-        description: "Test network".to_string(),
         difficulty: 30,
-        timestamp: 0,
+        ..Network::test_default()
     };
     let db = MockDatabase::new(network.clone());
 
@@ -114,6 +114,57 @@ fn should_reject_blocks_with_invalid_difficulty() {
     assert!(matches!(inner_err, ProofOfWorkError::InvalidDifficulty));
 }
 
+#[test]
+fn should_only_use_the_genesis_difficulty_for_the_genesis_block() {
+    // the chain starts easy, and gets harder right after the genesis block
+    let mut network = create_retarget_network(10000, 10);
+    network.genesis_difficulty = Some(0);
+    network.difficulty = 30;
+    let mut db = MockDatabase::new(network.clone());
+
+    // the genesis block is valid without meeting the regular difficulty
+    let genesis = Block::new(
+        0,
+        0,
+        network.consensus_hash(),
+        vec![build_coinbase_transaction()],
+    );
+    assert!(genesis.hash.leading_zeros() < network.difficulty);
+    validate_block(&db, &genesis).unwrap();
+    db.append_block(&genesis).unwrap();
+
+    // while the next block needs the regular difficulty
+    assert_eq!(get_next_difficulty(&db), network.difficulty);
+    let block = Block::new(1, 0, genesis.hash, vec![build_coinbase_transaction()]);
+    let err = validate_block(&db, &block).unwrap_err();
+    let inner_err = err.downcast::<ProofOfWorkError>().unwrap();
+    assert!(matches!(inner_err, ProofOfWorkError::InvalidDifficulty));
+}
+
+#[test]
+fn should_reject_blocks_mined_with_another_pow_algorithm() {
+    // set up a blockchain that expects memory-hard proofs of work
+    let mut network = create_retarget_network(10000, 0);
+    network.difficulty = 4;
+    network.pow_algorithm = PowAlgorithm::MemoryHard;
+    let db = MockDatabase::new(network.clone());
+
+    // mine the genesis block with sha3, finding a nonce that is not valid with the memory-hard algorithm
+    let coinbase = build_coinbase_transaction();
+    let mut block = Block::new(0, 0, network.consensus_hash(), vec![coinbase]);
+    while validate_pow(PowAlgorithm::Sha3, network.difficulty, &block).is_err()
+        || validate_pow(PowAlgorithm::MemoryHard, network.difficulty, &block).is_ok()
+    {
+        block.nonce += 1;
+        block.hash = block.calculate_hash();
+    }
+
+    // it should reject the block
+    let err = validate_block(&db, &block).unwrap_err();
+    let inner_err = err.downcast::<ProofOfWorkError>().unwrap();
+    assert!(matches!(inner_err, ProofOfWorkError::InvalidDifficulty));
+}
+
 #[test]
 fn should_reject_blocks_with_no_coinbase() {
     let mut db = MockDatabase::default();
@@ -149,11 +200,96 @@ fn should_reject_blocks_with_invalid_coinbase() {
     assert!(matches!(inner_err, CoinbaseError::InvalidCoinbaseAmount));
 }
 
+#[test]
+fn should_reject_coinbases_over_the_max_supply() {
+    // the premine leaves room for the genesis subsidy and half of the next one
+    let mut network = create_retarget_network(10000, 0);
+    network.premine = vec![GenesisAllocation {
+        address: alice(),
+        amount: MAX_SUPPLY - BLOCK_SUBSIDY - BLOCK_SUBSIDY / 2,
+        unlock_height: 0,
+    }];
+    let mut db = MockDatabase::new(network);
+    db.append_genesis_block().unwrap();
+
+    // the next coinbase can only claim what is left before the cap
+    let mut coinbase = build_coinbase_transaction();
+    let tip_block = db.get_tip_block().unwrap();
+    let block = Block::new(1, 0, tip_block.hash.clone(), vec![coinbase.clone()]);
+    let err = validate_block(&db, &block).unwrap_err();
+    let inner_err = err.downcast::<CoinbaseError>().unwrap();
+    assert!(matches!(inner_err, CoinbaseError::MaxSupplyExceeded));
+
+    let template = Block::new_template_with_coinbase(&db, miner_address());
+    assert_eq!(template.transactions[0].amount, BLOCK_SUBSIDY / 2);
+    db.append_block(&template).unwrap();
+    assert_eq!(calculate_total_supply(&db), MAX_SUPPLY);
+
+    // once the cap is reached, a coinbase claiming any subsidy is rejected
+    coinbase.amount = 1;
+    let tip_block = db.get_tip_block().unwrap();
+    let block = Block::new(2, 0, tip_block.hash, vec![coinbase]);
+    let err = validate_block(&db, &block).unwrap_err();
+    let inner_err = err.downcast::<CoinbaseError>().unwrap();
+    assert!(matches!(inner_err, CoinbaseError::MaxSupplyExceeded));
+
+    // but blocks can still be mined without it
+    let template = Block::new_template_with_coinbase(&db, miner_address());
+    assert_eq!(template.transactions[0].amount, 0);
+    validate_block(&db, &template).unwrap();
+}
+
+#[test]
+fn should_reject_blocks_with_fake_coinbase() {
+    let mut db = MockDatabase::default();
+    db.append_genesis_block().unwrap();
+
+    // create a block where the first transaction spends from a real account
+    // note that the miner address have funds from the genesis block's coinbase
+    let fake_coinbase = Transaction::new(miner_address(), alice(), BLOCK_SUBSIDY);
+    let tip_block = db.get_tip_block().unwrap();
+    let block = Block::new(tip_block.index + 1, 0, tip_block.hash, vec![fake_coinbase]);
+
+    // it should reject the block
+    let err = validate_block(&db, &block).unwrap_err();
+    let inner_err = err.downcast::<CoinbaseError>().unwrap();
+    assert!(matches!(inner_err, CoinbaseError::InvalidCoinbaseSender));
+}
+
+#[test]
+fn should_reject_blocks_with_multiple_zero_address_senders() {
+    let mut db = MockDatabase::default();
+    db.append_genesis_block().unwrap();
+
+    // create a block with a second transaction that also spends from the zero address
+    let coinbase = build_coinbase_transaction();
+    let second_coinbase = build_coinbase_transaction();
+    let tip_block = db.get_tip_block().unwrap();
+    let block = Block::new(
+        tip_block.index + 1,
+        0,
+        tip_block.hash,
+        vec![coinbase, second_coinbase],
+    );
+
+    // it should reject the block
+    let err = validate_block(&db, &block).unwrap_err();
+    let inner_err = err.downcast::<TransactionError>().unwrap();
+    assert!(matches!(inner_err, TransactionError::ZeroAddressSender));
+}
+
 #[test]
 fn should_reject_transactions_with_insufficient_funds() {
     let mut db = MockDatabase::default();
     db.append_genesis_block().unwrap();
 
+    // mine another block so the total supply is bigger than the miner's balance
+    let tip_block = db.get_tip_block().unwrap();
+    let mut coinbase = build_coinbase_transaction();
+    coinbase.recipient = alice();
+    let block = Block::new(tip_block.index + 1, 0, tip_block.hash, vec![coinbase]);
+    db.append_block(&block).unwrap();
+
     // let's create a transaction with an invalid amount
     // at this point the miner address has funds from the genesis coinbase
     // but we are going to try sending a bigger amount
@@ -175,6 +311,202 @@ fn should_reject_transactions_with_insufficient_funds() {
     assert!(matches!(inner_err, TransactionError::InsufficientFunds));
 }
 
+#[test]
+fn should_reject_transactions_exceeding_total_supply() {
+    let mut db = MockDatabase::default();
+    db.append_genesis_block().unwrap();
+
+    // no account can hold this amount, as only the genesis coinbase has been mined
+    let invalid_transaction = Transaction::new(miner_address(), alice(), u64::MAX);
+
+    // create a block with the invalid transaction
+    let coinbase = build_coinbase_transaction();
+    let tip_block = db.get_tip_block().unwrap();
+    let block = Block::new(
+        tip_block.index + 1,
+        0,
+        tip_block.hash,
+        vec![coinbase, invalid_transaction],
+    );
+
+    // it should reject the block
+    let err = validate_block(&db, &block).unwrap_err();
+    let inner_err = err.downcast::<TransactionError>().unwrap();
+    assert!(matches!(
+        inner_err,
+        TransactionError::AmountExceedsTotalSupply
+    ));
+}
+
+#[test]
+fn should_reject_transactions_with_too_large_data() {
+    let mut db = MockDatabase::default();
+    db.append_genesis_block().unwrap();
+
+    // data is accepted up to the network limit...
+    let max_data_size = db.get_network().max_transaction_data_size as usize;
+    let valid_transaction =
+        Transaction::new(miner_address(), alice(), 1).with_data(vec![0; max_data_size]);
+    validate_transaction(&db, &valid_transaction).unwrap();
+
+    // ...but not beyond it
+    let invalid_transaction =
+        Transaction::new(miner_address(), alice(), 1).with_data(vec![0; max_data_size + 1]);
+    let err = validate_transaction(&db, &invalid_transaction).unwrap_err();
+    let inner_err = err.downcast::<TransactionError>().unwrap();
+    assert!(matches!(inner_err, TransactionError::DataTooLarge));
+}
+
+#[test]
+fn should_reject_blocks_exceeding_the_weight_limit() {
+    let coinbase = build_coinbase_transaction();
+    let transfers = create_sorted_transfers(2);
+
+    // the limit only leaves room for the coinbase and a single transfer
+    let mut network = create_retarget_network(10000, 0);
+    network.max_block_weight = coinbase.weight() + transfers[0].weight();
+    let mut db = MockDatabase::new(network);
+    db.append_genesis_block().unwrap();
+
+    let tip_block = db.get_tip_block().unwrap();
+    let block = Block::new(
+        1,
+        0,
+        tip_block.hash.clone(),
+        vec![coinbase.clone(), transfers[0].clone()],
+    );
+    validate_block(&db, &block).unwrap();
+
+    let mut transactions = vec![coinbase];
+    transactions.extend(transfers);
+    let block = Block::new(1, 0, tip_block.hash, transactions);
+    let err = validate_block(&db, &block).unwrap_err();
+    let inner_err = err.downcast::<BlockError>().unwrap();
+    assert!(matches!(inner_err, BlockError::WeightLimitExceeded));
+}
+
+#[test]
+fn should_stop_the_template_at_the_weight_limit() {
+    let transfers = create_sorted_transfers(3);
+
+    // the limit leaves room for a coinbase with the largest data allowed and two transfers
+    let mut network = create_retarget_network(10000, 0);
+    let max_coinbase =
+        build_coinbase_transaction().with_data(vec![0; network.max_transaction_data_size as usize]);
+    network.max_block_weight = max_coinbase.weight() + 2 * transfers[0].weight();
+    let mut db = MockDatabase::new(network);
+    db.append_genesis_block().unwrap();
+    for transfer in transfers.iter() {
+        db.add_mempool_transaction(transfer.clone());
+    }
+
+    // the pending transactions are included in order until the next one does not fit
+    let template = Block::new_template_with_coinbase(&db, miner_address());
+    assert_eq!(template.transactions[1..], transfers[..2]);
+    validate_block(&db, &template).unwrap();
+}
+
+#[test]
+fn should_reject_blocks_older_than_their_previous_block() {
+    let mut db = MockDatabase::default();
+    db.append_genesis_block().unwrap();
+    let genesis = db.get_tip_block().unwrap();
+
+    // a block can have the same timestamp as the previous one...
+    let mut block = Block::new(1, 0, genesis.hash, vec![build_coinbase_transaction()]);
+    block.timestamp = genesis.timestamp;
+    block.hash = block.calculate_hash();
+    validate_block(&db, &block).unwrap();
+
+    // ...but not an earlier one
+    block.timestamp = genesis.timestamp - 1;
+    block.hash = block.calculate_hash();
+    let err = validate_block(&db, &block).unwrap_err();
+    let inner_err = err.downcast::<ChainError>().unwrap();
+    assert!(matches!(
+        inner_err,
+        ChainError::TimestampBeforePreviousBlock
+    ));
+}
+
+#[test]
+fn should_only_include_transactions_after_the_warmup() {
+    let transfer = Transaction::new(miner_address(), alice(), 1);
+
+    // the genesis and the next block are coinbase only
+    let mut network = create_retarget_network(10000, 0);
+    network.warmup_blocks = 2;
+    let mut db = MockDatabase::new(network);
+    db.append_genesis_block().unwrap();
+    db.add_mempool_transaction(transfer.clone());
+
+    // the pending transaction is left out of the template during the warmup
+    let template = Block::new_template_with_coinbase(&db, miner_address());
+    assert_eq!(template.transactions.len(), 1);
+    validate_block(&db, &template).unwrap();
+
+    // and blocks including transactions are rejected
+    let tip_block = db.get_tip_block().unwrap();
+    let block = Block::new(
+        1,
+        0,
+        tip_block.hash,
+        vec![build_coinbase_transaction(), transfer.clone()],
+    );
+    let err = validate_block(&db, &block).unwrap_err();
+    let inner_err = err.downcast::<BlockError>().unwrap();
+    assert!(matches!(inner_err, BlockError::TransactionsDuringWarmup));
+
+    // once the warmup is over, the pending transaction is included
+    db.append_block(&template).unwrap();
+    let template = Block::new_template_with_coinbase(&db, miner_address());
+    assert_eq!(template.index, 2);
+    assert_eq!(template.transactions[1..], [transfer]);
+    validate_block(&db, &template).unwrap();
+}
+
+#[test]
+fn should_reject_blocks_with_too_many_transactions_from_a_sender() {
+    let coinbase = build_coinbase_transaction();
+    let transfers = create_sorted_transfers(3);
+
+    // the coinbase does not count towards the limit of the miner
+    let mut network = create_retarget_network(10000, 0);
+    network.max_txs_per_sender_per_block = 2;
+    let mut db = MockDatabase::new(network);
+    db.append_genesis_block().unwrap();
+
+    let tip_block = db.get_tip_block().unwrap();
+    let mut transactions = vec![coinbase];
+    transactions.extend_from_slice(&transfers[..2]);
+    let block = Block::new(1, 0, tip_block.hash.clone(), transactions.clone());
+    validate_block(&db, &block).unwrap();
+
+    transactions.push(transfers[2].clone());
+    let block = Block::new(1, 0, tip_block.hash, transactions);
+    let err = validate_block(&db, &block).unwrap_err();
+    let inner_err = err.downcast::<BlockError>().unwrap();
+    assert!(matches!(inner_err, BlockError::SenderLimitExceeded));
+}
+
+#[test]
+fn should_cap_the_template_transactions_per_sender() {
+    let transfers = create_sorted_transfers(3);
+
+    let mut network = create_retarget_network(10000, 0);
+    network.max_txs_per_sender_per_block = 2;
+    let mut db = MockDatabase::new(network);
+    db.append_genesis_block().unwrap();
+    for transfer in transfers.iter() {
+        db.add_mempool_transaction(transfer.clone());
+    }
+
+    // only the first pending transactions of the sender are included
+    let template = Block::new_template_with_coinbase(&db, miner_address());
+    assert_eq!(template.transactions[1..], transfers[..2]);
+    validate_block(&db, &template).unwrap();
+}
+
 #[test]
 fn should_reject_transactions_with_non_existent_sender() {
     let mut db = MockDatabase::default();
@@ -201,3 +533,142 @@ fn should_reject_transactions_with_non_existent_sender() {
         TransactionError::SenderAccountDoesNotExist
     ));
 }
+
+#[test]
+fn should_only_spend_mature_coinbase_funds() {
+    // coinbase rewards can only be spent after two more blocks
+    let mut network = MockDatabase::default().get_network();
+    network.coinbase_maturity = 2;
+    let mut db = MockDatabase::new(network);
+    db.append_genesis_block().unwrap();
+
+    // immediately after mining, the total balance exceeds the spendable one
+    let height = db.get_block_count();
+    assert_eq!(
+        db.get_account_balance(&miner_address()),
+        Some(BLOCK_SUBSIDY)
+    );
+    assert_eq!(db.get_spendable_balance(&miner_address(), height), Some(0));
+
+    // so the miner can not spend the coinbase reward yet
+    let transaction = Transaction::new(miner_address(), alice(), 10);
+    let err = validate_transaction(&db, &transaction).unwrap_err();
+    let inner_err = err.downcast::<TransactionError>().unwrap();
+    assert!(matches!(inner_err, TransactionError::InsufficientFunds));
+
+    // after two more blocks (rewarding another account), both balances converge
+    for _ in 0..2 {
+        let tip_block = db.get_tip_block().unwrap();
+        let coinbase = Transaction::new_coinbase(alice());
+        let block = Block::new(tip_block.index + 1, 0, tip_block.hash, vec![coinbase]);
+        db.append_block(&block).unwrap();
+    }
+    let height = db.get_block_count();
+    assert_eq!(
+        db.get_spendable_balance(&miner_address(), height),
+        db.get_account_balance(&miner_address())
+    );
+    validate_transaction(&db, &transaction).unwrap();
+}
+
+#[test]
+fn should_keep_the_network_difficulty_without_retarget_window() {
+    // blocks are mined way faster than the target, but retargeting is disabled
+    let difficulty = simulate_mining(create_retarget_network(1000, 0), 100);
+
+    assert_eq!(difficulty, 0);
+}
+
+#[test]
+fn should_retarget_to_a_higher_difficulty_with_a_longer_target_block_time() {
+    // with the same hashrate, a longer target block time requires more work per block
+    let short_target_difficulty = simulate_mining(create_retarget_network(100, 10), 200);
+    let long_target_difficulty = simulate_mining(create_retarget_network(1000, 10), 200);
+
+    // each step doubles the work, so blocks are mined between half and double of the target time
+    assert!((6..=8).contains(&short_target_difficulty));
+    assert!((9..=11).contains(&long_target_difficulty));
+    assert!(short_target_difficulty < long_target_difficulty);
+}
+
+#[test]
+fn should_retarget_faster_with_a_shorter_retarget_window() {
+    // with the same hashrate and number of blocks, a shorter window retargets more often
+    let short_window_difficulty = simulate_mining(create_retarget_network(1000, 5), 30);
+    let long_window_difficulty = simulate_mining(create_retarget_network(1000, 10), 30);
+
+    assert_eq!(short_window_difficulty, 6);
+    assert_eq!(long_window_difficulty, 3);
+}
+
+#[test]
+fn should_halve_the_mining_time_estimate_when_doubling_the_hashrate() {
+    let difficulty = 20;
+
+    let estimate = estimate_mining_time_secs(difficulty, 1000.0);
+    let doubled_hashrate_estimate = estimate_mining_time_secs(difficulty, 2000.0);
+
+    assert_eq!(estimate, 1048.576);
+    assert_eq!(doubled_hashrate_estimate, estimate / 2.0);
+}
+
+#[test]
+fn should_not_retarget_above_the_max_difficulty() {
+    // blocks would need a difficulty of around 10 to be mined every second
+    let mut network = create_retarget_network(1000, 10);
+    network.max_difficulty = 5;
+
+    let difficulty = simulate_mining(network, 200);
+
+    assert_eq!(difficulty, 5);
+}
+
+#[test]
+fn should_not_retarget_below_the_min_difficulty() {
+    // blocks would need a difficulty of around 3 to be mined every 10 milliseconds
+    let mut network = create_retarget_network(10, 10);
+    network.difficulty = 12;
+    network.min_difficulty = 8;
+
+    let difficulty = simulate_mining(network, 200);
+
+    assert_eq!(difficulty, 8);
+}
+
+// Transfers of the same size from the miner, in canonical order
+fn create_sorted_transfers(num_transfers: u8) -> Vec<Transaction> {
+    let mut transfers: Vec<Transaction> = (0..num_transfers)
+        .map(|i| Transaction::new(miner_address(), alice(), 1).with_data(vec![i]))
+        .collect();
+    transfers.sort_by_key(|transfer| transfer.id());
+
+    transfers
+}
+
+fn create_retarget_network(target_block_time_ms: u64, retarget_window: u64) -> Network {
+    Network {
+        target_block_time_ms,
+        retarget_window,
+        ..Network::test_default()
+    }
+}
+
+// Mine a chain with a constant hashrate of one hash per millisecond,
+// returning the difficulty required for the next block
+fn simulate_mining(network: Network, num_blocks: u64) -> u32 {
+    let mut db = MockDatabase::new(network);
+
+    let mut timestamp = 0;
+    for index in 0..num_blocks {
+        // on average, a block needs 2^difficulty hashes to be mined
+        let difficulty = get_next_difficulty(&db);
+        timestamp += 2_i64.pow(difficulty);
+
+        let coinbase = build_coinbase_transaction();
+        let mut block = Block::new(index, 0, ConsensusHash::default(), vec![coinbase]);
+        block.timestamp = timestamp;
+        db.append_block(&block).unwrap();
+    }
+
+    get_next_difficulty(&db)
+}